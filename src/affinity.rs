@@ -0,0 +1,72 @@
+//! Optional CPU pinning of the capture, publish and RTSP main-loop threads
+//! (`--pin-threads`), to keep them from contending on small hosts.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use core_affinity::CoreId;
+use once_cell::sync::OnceCell;
+
+/// Cores handed out round-robin, in the order threads start.
+struct Pinning {
+    cores: Vec<CoreId>,
+    next: AtomicUsize,
+}
+
+static PINNING: OnceCell<Pinning> = OnceCell::new();
+
+/// Enables pinning over the `cores` given by index, or over every available core
+/// when the list is empty.
+pub fn enable(cores: &[usize]) -> anyhow::Result<()> {
+    let available = core_affinity::get_core_ids()
+        .filter(|ids| !ids.is_empty())
+        .ok_or_else(|| anyhow::anyhow!("--pin-threads: the available CPU cores can't be listed"))?;
+    let cores = if cores.is_empty() {
+        available
+    } else {
+        cores
+            .iter()
+            .map(|&id| {
+                available
+                    .iter()
+                    .copied()
+                    .find(|core| core.id == id)
+                    .ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "--pin-threads: core {id} doesn't exist (available: 0-{})",
+                            available.len() - 1
+                        )
+                    })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?
+    };
+    log::info!(
+        "Pinning threads round-robin over cores {:?}",
+        cores.iter().map(|c| c.id).collect::<Vec<_>>()
+    );
+    if PINNING
+        .set(Pinning {
+            cores,
+            next: AtomicUsize::new(0),
+        })
+        .is_err()
+    {
+        log::warn!("Thread pinning already enabled; ignoring new core list");
+    }
+    Ok(())
+}
+
+/// Pins the calling thread to the next core. No-op unless [`enable`] was called.
+pub fn pin_current_thread() {
+    let Some(pinning) = PINNING.get() else {
+        return;
+    };
+    let index = pinning.next.fetch_add(1, Ordering::Relaxed) % pinning.cores.len();
+    let core = pinning.cores[index];
+    let thread = std::thread::current();
+    let name = thread.name().unwrap_or("unnamed");
+    if core_affinity::set_for_current(core) {
+        log::info!("Thread '{name}' pinned to core {}", core.id);
+    } else {
+        log::warn!("Failed to pin thread '{name}' to core {}", core.id);
+    }
+}
@@ -1,31 +1,127 @@
-use std::{sync::Arc, time::Duration};
+use std::{sync::Arc, thread::JoinHandle, time::Duration};
 
 use anyhow::Context;
 use bytemuck::try_cast_slice;
+use gstreamer::prelude::*;
+use gstreamer::{self as gst};
+use gstreamer_app as gst_app;
 // no async ring buffers needed for RTSP publishing path
 use kinect_v2::audio_capture::{AudioFrameCapture, AudioFrameCaptureIter, AudioFrameData};
-use ringbuf::{
-    HeapRb, SharedRb,
-    storage::Heap,
-    traits::{Consumer, Producer, Split},
-    wrap::caching::Caching,
-};
 
+use crate::affinity::pin_current_thread;
 use crate::audio_frame_buffer::AudioFrameBuffer;
+use crate::frame_queue::{FrameHandoff, FrameSender, frame_queue, inline_sender};
+use crate::priority::lower_current_thread;
 use crate::rtsp_publisher::RtspPublisher;
+use crate::throttle::{PROGRESS_LOG_INTERVAL, STALL_WARN_INTERVAL, Throttle};
+
+/// Where the audio of the streams comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AudioSource {
+    /// Kinect microphone array
+    #[default]
+    Kinect,
+    /// Default capture device of the OS, through WASAPI
+    System,
+}
+
+/// OS capture pipeline, normalized to what the publisher expects: mono f32 at 16 kHz.
+const SYSTEM_AUDIO_PIPELINE: &str = "wasapisrc \
+    ! audioconvert ! audioresample \
+    ! audio/x-raw,format=F32LE,layout=interleaved,rate=16000,channels=1 \
+    ! appsink name=sink sync=false max-buffers=32 drop=true";
+
+/// Checks that the OS audio capture element is available.
+pub fn check_system_audio() -> anyhow::Result<()> {
+    if gst::ElementFactory::find("wasapisrc").is_none() {
+        return Err(anyhow::anyhow!(
+            "--audio-source system needs the 'wasapisrc' GStreamer element (gst-plugins-bad)"
+        ));
+    }
+    Ok(())
+}
+
+/// Captures from the OS audio device while clients are connected and feeds the
+/// same 20 ms chunks to the publisher as the Kinect path.
+fn system_audio_capture(rtsp: Arc<RtspPublisher>) -> anyhow::Result<()> {
+    let pipeline = gst::parse::launch(SYSTEM_AUDIO_PIPELINE)
+        .context("Failed to build system audio pipeline")?
+        .downcast::<gst::Pipeline>()
+        .map_err(|_| anyhow::anyhow!("System audio pipeline is not a gst::Pipeline"))?;
+    let sink = pipeline
+        .by_name("sink")
+        .and_then(|e| e.downcast::<gst_app::AppSink>().ok())
+        .context("System audio pipeline has no appsink")?;
+
+    let mut audio_frame_buffer = AudioFrameBuffer::<f32>::new();
+    let mut playing = false;
+    let always_on = rtsp.is_audio_always_on();
+
+    while !rtsp.is_stopping() {
+        if !always_on && !rtsp.is_audio_capture_active() {
+            if playing {
+                pipeline.set_state(gst::State::Null)?;
+                playing = false;
+                log::info!("System audio capture paused (no active subscribers)");
+            }
+            std::thread::sleep(Duration::from_millis(30));
+            continue;
+        }
+
+        if !playing {
+            log::info!("System audio capture starting...");
+            pipeline
+                .set_state(gst::State::Playing)
+                .context("Failed to start system audio capture")?;
+            playing = true;
+        }
+
+        let Some(sample) = sink.try_pull_sample(gst::ClockTime::from_mseconds(100)) else {
+            if sink.is_eos() {
+                return Err(anyhow::anyhow!(
+                    "System audio device stopped delivering samples"
+                ));
+            }
+            continue;
+        };
+        let Some(map) = sample.buffer().and_then(|b| b.map_readable().ok()) else {
+            continue;
+        };
+        match try_cast_slice::<u8, f32>(&map) {
+            Ok(samples) => audio_frame_buffer.append_samples(samples.iter().copied()),
+            Err(err) => {
+                log::warn!(
+                    "Unexpected system audio layout ({} bytes): {err}",
+                    map.len()
+                );
+                continue;
+            }
+        }
+
+        while let Some(input_chunk) = audio_frame_buffer.pop_frame(AUDIO_FRAME_SIZE) {
+            rtsp.send_audio_f32(&input_chunk);
+        }
+    }
+
+    pipeline.set_state(gst::State::Null)?;
+    Ok(())
+}
 
 fn audio_frame_capture(
     rtsp: Arc<RtspPublisher>,
-    raw_tx: &mut Caching<Arc<SharedRb<Heap<AudioFrameData>>>, true, false>,
+    frames: &FrameSender<AudioFrameData>,
 ) -> anyhow::Result<()> {
     let mut audio_capture: Option<AudioFrameCapture> = None;
     let mut iter: Option<AudioFrameCaptureIter> = None;
 
     let mut frame_count = 0;
-    let mut last_log_time = std::time::Instant::now();
+    let mut progress_log = Throttle::new(PROGRESS_LOG_INTERVAL);
+    let mut stall_warning = Throttle::new(STALL_WARN_INTERVAL);
+    let always_on = rtsp.is_audio_always_on();
 
-    loop {
-        if !rtsp.is_capture_active() {
+    while !rtsp.is_stopping() {
+        if !always_on && !rtsp.is_audio_capture_active() {
             // RTSP capture not active, release Kinect resources.
             if iter.is_some() {
                 iter = None;
@@ -51,6 +147,7 @@ fn audio_frame_capture(
                         .iter()
                         .context("Failed to create audio capture iterator")?,
                 );
+                stall_warning.reset();
             } else {
                 std::thread::sleep(Duration::from_millis(30));
                 continue;
@@ -61,14 +158,12 @@ fn audio_frame_capture(
             match iter.next() {
                 Some(Ok(data)) => {
                     frame_count += 1;
-
-                    // Log audio capture every 100 frames (less frequent than video)
-                    if frame_count % 100 == 0 || last_log_time.elapsed() > Duration::from_secs(10) {
+                    stall_warning.reset();
+                    if progress_log.should_log() {
                         log::debug!("🎵 Captured audio frame #{frame_count}");
-                        last_log_time = std::time::Instant::now();
                     }
 
-                    if raw_tx.try_push(data).is_err() {
+                    if !frames.push(data) {
                         log::debug!("❌ Audio frame ring buffer full, dropping frame");
                     }
                 }
@@ -77,75 +172,108 @@ fn audio_frame_capture(
                 }
                 None => {
                     // No new frame available yet - log periodically to show we're still trying
-                    if last_log_time.elapsed() > Duration::from_secs(15) {
+                    if stall_warning.should_log() {
                         log::warn!(
                             "🔍 No audio frames available from Kinect - is the device connected?"
                         );
-                        last_log_time = std::time::Instant::now();
                     }
                     std::thread::sleep(Duration::from_millis(5));
                 }
             }
         }
     }
+    Ok(())
 }
 
-fn audio_frame_publish(
-    rtsp: Arc<RtspPublisher>,
-    raw_rx: &mut Caching<Arc<SharedRb<Heap<AudioFrameData>>>, false, true>,
-) -> anyhow::Result<()> {
+/// RTSP branch expects S16LE 16kHz mono; we buffer in 20ms chunks (320 samples).
+pub const AUDIO_FRAME_SIZE: usize = 320;
+
+/// One chunk of a 1 kHz test tone at -12 dBFS. A chunk holds exactly 20 periods at
+/// 16 kHz, so repeating it keeps the phase continuous.
+pub fn test_tone() -> [f32; AUDIO_FRAME_SIZE] {
+    std::array::from_fn(|i| 0.25 * (2.0 * std::f32::consts::PI * 1000.0 * i as f32 / 16000.0).sin())
+}
+
+/// The publish step of one captured frame: regroups its samples into
+/// [`AUDIO_FRAME_SIZE`] chunks and sends every full one.
+fn audio_frame_publisher(rtsp: Arc<RtspPublisher>) -> impl FnMut(AudioFrameData) + Send + 'static {
     let mut audio_frame_buffer = AudioFrameBuffer::<f32>::new();
-    // RTSP branch expects S16LE 16kHz mono; we’ll buffer in 20ms chunks (320 samples)
-    const FRAME_SIZE: usize = 320;
 
-    loop {
-        if let Some(audio_frame) = raw_rx.try_pop() {
-            if audio_frame.data.is_empty() {
-                log::trace!("Skipping empty audio frame");
-                continue;
-            }
+    move |audio_frame| {
+        if audio_frame.data.is_empty() {
+            log::trace!("Skipping empty audio frame");
+            return;
+        }
 
-            // Decode raw bytes into f32 samples without per-frame allocation
-            match try_cast_slice::<u8, f32>(&audio_frame.data) {
-                Ok(samples) => {
-                    audio_frame_buffer.append_samples(samples.iter().copied());
-                }
-                Err(err) => {
-                    log::warn!(
-                        "Unexpected audio frame layout ({} bytes): {err}",
-                        audio_frame.data.len()
-                    );
-                    continue;
-                }
-            }
+        // Decode raw bytes into f32 samples without per-frame allocation; a sample
+        // split across frames is reassembled instead of dropping the whole frame
+        audio_frame_buffer.append_f32_le_bytes(&audio_frame.data);
 
-            // Process each full 320‐sample chunk by sending it to RTSP (it will be converted to S16 in publisher)
-            while let Some(input_chunk) = audio_frame_buffer.pop_frame(FRAME_SIZE) {
-                rtsp.send_audio_f32(&input_chunk);
-            }
-        } else {
-            // No new frame yet, sleep briefly to avoid busy waiting
-            std::thread::sleep(Duration::from_millis(30));
+        // Process each full 320‐sample chunk by sending it to RTSP (it will be converted to S16 in publisher)
+        while let Some(input_chunk) = audio_frame_buffer.pop_frame(AUDIO_FRAME_SIZE) {
+            rtsp.send_audio_f32(&input_chunk);
         }
     }
 }
 
-pub fn spawn_audio_pipeline(rtsp: Arc<RtspPublisher>) {
-    let raw_ring_buffer = HeapRb::<AudioFrameData>::new(32);
-    let (mut raw_tx, mut raw_rx) = raw_ring_buffer.split();
+pub fn spawn_audio_pipeline(
+    rtsp: Arc<RtspPublisher>,
+    source: AudioSource,
+    handoff: FrameHandoff,
+) -> Vec<JoinHandle<()>> {
+    if source == AudioSource::System {
+        let capture = std::thread::Builder::new()
+            .name("audio-system".into())
+            .spawn(move || {
+                pin_current_thread();
+                lower_current_thread();
+                if let Err(e) = system_audio_capture(rtsp) {
+                    log::error!("Error capturing system audio: {e}");
+                }
+            })
+            .expect("Failed to spawn system audio thread");
+        return vec![capture];
+    }
+
+    let publish = audio_frame_publisher(rtsp.clone());
+    let mut threads = Vec::new();
+    let frames_tx = match handoff {
+        FrameHandoff::Inline => inline_sender(publish),
+        FrameHandoff::Queue(queue) => {
+            let (frames_tx, frames_rx) = frame_queue(32, queue.full);
+            let frames_tx = frames_tx.monitor(
+                "audio",
+                rtsp.metrics().audio_queue.clone(),
+                queue.high_water_percent,
+            );
+            let rtsp = rtsp.clone();
+            // Audio publish thread
+            let thread = std::thread::Builder::new()
+                .name("audio-publish".into())
+                .spawn(move || {
+                    pin_current_thread();
+                    lower_current_thread();
+                    let wakeups = &rtsp.metrics().audio_publish_wakeups;
+                    frames_rx.for_each(|| rtsp.is_stopping(), wakeups, publish);
+                })
+                .expect("Failed to spawn audio publish thread");
+            threads.push(thread);
+            frames_tx
+        }
+    };
 
-    let rtsp_clone = rtsp.clone();
     // Audio capture thread
-    std::thread::spawn(move || {
-        if let Err(e) = audio_frame_capture(rtsp_clone, &mut raw_tx) {
-            log::error!("Error capturing audio frames: {e}");
-        }
-    });
+    let capture = std::thread::Builder::new()
+        .name("audio-capture".into())
+        .spawn(move || {
+            pin_current_thread();
+            lower_current_thread();
+            if let Err(e) = audio_frame_capture(rtsp, &frames_tx) {
+                log::error!("Error capturing audio frames: {e}");
+            }
+        })
+        .expect("Failed to spawn audio capture thread");
+    threads.push(capture);
 
-    // Audio publish thread
-    std::thread::spawn(move || {
-        if let Err(e) = audio_frame_publish(rtsp, &mut raw_rx) {
-            log::error!("Error publishing audio frames: {e}");
-        }
-    });
+    threads
 }
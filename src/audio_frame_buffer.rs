@@ -9,6 +9,8 @@ impl AudioSample for f32 {}
 #[derive(Debug)]
 pub struct AudioFrameBuffer<T: AudioSample> {
     samples: VecDeque<T>,
+    /// Bytes of a sample split across two [`Self::append_f32_le_bytes`] calls.
+    partial_sample: Vec<u8>,
 }
 
 impl<T: AudioSample> AudioFrameBuffer<T> {
@@ -16,6 +18,7 @@ impl<T: AudioSample> AudioFrameBuffer<T> {
     pub fn new() -> Self {
         Self {
             samples: VecDeque::new(),
+            partial_sample: Vec::with_capacity(4),
         }
     }
 
@@ -56,6 +59,29 @@ impl<T: AudioSample> AudioFrameBuffer<T> {
     }
 }
 
+impl AudioFrameBuffer<f32> {
+    /// Append f32 little-endian samples from raw bytes. A trailing partial sample is
+    /// kept and completed by the first bytes of the next call instead of being lost,
+    /// so the byte length of each call doesn't have to be a multiple of 4.
+    pub fn append_f32_le_bytes(&mut self, mut bytes: &[u8]) {
+        if !self.partial_sample.is_empty() {
+            let missing = (4 - self.partial_sample.len()).min(bytes.len());
+            self.partial_sample.extend_from_slice(&bytes[..missing]);
+            bytes = &bytes[missing..];
+            let Ok(sample) = <[u8; 4]>::try_from(self.partial_sample.as_slice()) else {
+                return;
+            };
+            self.samples.push_back(f32::from_le_bytes(sample));
+            self.partial_sample.clear();
+        }
+        let chunks = bytes.chunks_exact(4);
+        self.partial_sample.extend_from_slice(chunks.remainder());
+        self.samples.extend(
+            chunks.map(|chunk| f32::from_le_bytes(chunk.try_into().expect("4-byte chunk"))),
+        );
+    }
+}
+
 impl<T: AudioSample> Default for AudioFrameBuffer<T> {
     fn default() -> Self {
         Self::new()
@@ -252,4 +278,20 @@ mod tests {
         assert_eq!(frame_i16.unwrap(), vec![1i16, 2]);
         assert_eq!(frame_f32.unwrap(), vec![1.0f32, 2.0]);
     }
+
+    #[test]
+    fn test_f32_bytes_split_across_calls() {
+        let bytes: Vec<u8> = [0.5f32, -1.0, 0.25]
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect();
+        let mut buffer = AudioFrameBuffer::<f32>::new();
+        buffer.append_f32_le_bytes(&bytes[..5]);
+        assert_eq!(buffer._len(), 1);
+        buffer.append_f32_le_bytes(&bytes[5..6]);
+        assert_eq!(buffer._len(), 1);
+        buffer.append_f32_le_bytes(&bytes[6..]);
+        assert_eq!(buffer.pop_frame(3), Some(vec![0.5, -1.0, 0.25]));
+        assert!(buffer.partial_sample.is_empty());
+    }
 }
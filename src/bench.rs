@@ -0,0 +1,201 @@
+//! `bench` subcommand: measures how fast each H.264 encoder handles synthetic
+//! 1080p color frames on this host, without a Kinect or an RTSP server.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use gstreamer_app as gst_app;
+use parking_lot::Mutex;
+
+use crate::encoder::{BFrames, EncoderPreset, VideoEncoder};
+
+const WIDTH: usize = 1920;
+const HEIGHT: usize = 1080;
+const FPS: u64 = 30;
+/// Same bitrate and GOP as the color stream.
+const BITRATE: u32 = 6_000_000;
+const GOP_SIZE: u32 = 30;
+/// Distinct synthetic frames cycled through so the encoder can't skip static content.
+const PATTERN_FRAMES: usize = 8;
+
+/// Throughput of one encoder/preset combination.
+struct BenchResult {
+    frames: u64,
+    elapsed: Duration,
+    total_latency: Duration,
+}
+
+impl BenchResult {
+    fn fps(&self) -> f64 {
+        self.frames as f64 / self.elapsed.as_secs_f64()
+    }
+
+    fn avg_latency_ms(&self) -> f64 {
+        if self.frames == 0 {
+            return 0.0;
+        }
+        self.total_latency.as_secs_f64() * 1000.0 / self.frames as f64
+    }
+}
+
+/// Runs the benchmark for every encoder (and every preset unless `preset` is set)
+/// and prints a comparison table.
+pub fn run(duration: Duration, preset: Option<EncoderPreset>) -> Result<()> {
+    gst::init()?;
+
+    let presets: Vec<EncoderPreset> = match preset {
+        Some(p) => vec![p],
+        None => EncoderPreset::value_variants().to_vec(),
+    };
+    let frames = synthetic_frames();
+
+    println!(
+        "Encoding {WIDTH}x{HEIGHT} YUY2 at {} kbit/s, {}s per run",
+        BITRATE / 1000,
+        duration.as_secs()
+    );
+    println!(
+        "{:<10} {:<8} {:>8} {:>12} {:>8}",
+        "encoder", "preset", "fps", "latency ms", "1080p30"
+    );
+    for encoder in VideoEncoder::value_variants() {
+        let name = encoder.element_name();
+        if gst::ElementFactory::find(name).is_none() {
+            println!("{:<10} {:<8} {:>8}", format!("{encoder:?}"), "-", "missing");
+            continue;
+        }
+        for preset in &presets {
+            let result = bench_encoder(*encoder, *preset, &frames, duration)
+                .with_context(|| format!("Benchmark of {name} failed"))?;
+            println!(
+                "{:<10} {:<8} {:>8.1} {:>12.1} {:>8}",
+                format!("{encoder:?}"),
+                format!("{preset:?}"),
+                result.fps(),
+                result.avg_latency_ms(),
+                if result.fps() >= FPS as f64 {
+                    "yes"
+                } else {
+                    "no"
+                }
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Pushes frames as fast as the encoder accepts them for `duration`, then drains it.
+fn bench_encoder(
+    encoder: VideoEncoder,
+    preset: EncoderPreset,
+    frames: &[Vec<u8>],
+    duration: Duration,
+) -> Result<BenchResult> {
+    let caps = format!("video/x-raw,format=YUY2,width={WIDTH},height={HEIGHT},framerate={FPS}/1");
+    // No leaky queues here: every frame has to be encoded to measure throughput.
+    let launch = format!(
+        "appsrc name=src format=time block=true max-bytes={} caps={caps} \
+        ! videoconvert ! video/x-raw,format=I420 \
+        ! {} ! h264parse ! appsink name=sink sync=false",
+        frames[0].len() * 2,
+        encoder.encoder_fragment(BITRATE, GOP_SIZE, preset, BFrames::default())
+    );
+    let pipeline = gst::parse::launch(&launch)
+        .with_context(|| format!("Failed to build bench pipeline: {launch}"))?
+        .downcast::<gst::Pipeline>()
+        .map_err(|_| anyhow::anyhow!("Bench pipeline is not a gst::Pipeline"))?;
+    let appsrc = pipeline
+        .by_name("src")
+        .and_then(|e| e.downcast::<gst_app::AppSrc>().ok())
+        .context("Bench pipeline has no appsrc")?;
+    let appsink = pipeline
+        .by_name("sink")
+        .and_then(|e| e.downcast::<gst_app::AppSink>().ok())
+        .context("Bench pipeline has no appsink")?;
+
+    // Push time of every frame keyed by PTS; the sink measures the delta on arrival.
+    let pushed: Arc<Mutex<HashMap<u64, Instant>>> = Arc::default();
+    let received: Arc<Mutex<(u64, Duration)>> = Arc::default();
+    {
+        let pushed = pushed.clone();
+        let received = received.clone();
+        appsink.set_callbacks(
+            gst_app::AppSinkCallbacks::builder()
+                .new_sample(move |sink| {
+                    let sample = sink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
+                    let pts = sample.buffer().and_then(|b| b.pts());
+                    let mut received = received.lock();
+                    received.0 += 1;
+                    if let Some(start) = pts.and_then(|pts| pushed.lock().remove(&pts.nseconds())) {
+                        received.1 += start.elapsed();
+                    }
+                    Ok(gst::FlowSuccess::Ok)
+                })
+                .build(),
+        );
+    }
+
+    pipeline.set_state(gst::State::Playing)?;
+    let frame_duration = gst::ClockTime::from_nseconds(1_000_000_000 / FPS);
+    let start = Instant::now();
+    let mut index = 0u64;
+    while start.elapsed() < duration {
+        let data = &frames[index as usize % frames.len()];
+        let mut buffer = gst::Buffer::from_slice(data.clone());
+        let pts = frame_duration * index;
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(pts);
+            buffer.set_duration(frame_duration);
+        }
+        pushed.lock().insert(pts.nseconds(), Instant::now());
+        if appsrc.push_buffer(buffer).is_err() {
+            break;
+        }
+        index += 1;
+    }
+    appsrc.end_of_stream()?;
+
+    let bus = pipeline.bus().context("Bench pipeline has no bus")?;
+    let msg = bus.timed_pop_filtered(
+        gst::ClockTime::from_seconds(10),
+        &[gst::MessageType::Eos, gst::MessageType::Error],
+    );
+    let elapsed = start.elapsed();
+    pipeline.set_state(gst::State::Null)?;
+    if let Some(msg) = msg
+        && let gst::MessageView::Error(err) = msg.view()
+    {
+        return Err(anyhow::anyhow!("{}", err.error()));
+    }
+
+    let (frames, total_latency) = *received.lock();
+    Ok(BenchResult {
+        frames,
+        elapsed,
+        total_latency,
+    })
+}
+
+/// Moving diagonal gradients in YUY2, different enough between frames to
+/// make the encoder do real motion estimation.
+fn synthetic_frames() -> Vec<Vec<u8>> {
+    (0..PATTERN_FRAMES)
+        .map(|n| {
+            let shift = n * 16;
+            let mut frame = vec![0u8; WIDTH * HEIGHT * 2];
+            for (y, row) in frame.chunks_exact_mut(WIDTH * 2).enumerate() {
+                for (x, px) in row.chunks_exact_mut(4).enumerate() {
+                    let luma = ((x * 2 + y + shift) % 220 + 16) as u8;
+                    px.copy_from_slice(&[luma, (x + shift) as u8, luma, (y + shift) as u8]);
+                }
+            }
+            frame
+        })
+        .collect()
+}
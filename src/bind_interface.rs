@@ -0,0 +1,112 @@
+//! `--bind-interface`: the RTSP server listens on the current address of a network
+//! interface given by name, which stays stable when DHCP changes the address.
+
+use std::net::IpAddr;
+
+use crate::error::KinectRtspError;
+
+/// Address family preferred when the interface has addresses of both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AddressFamily {
+    #[default]
+    Ipv4,
+    Ipv6,
+}
+
+impl AddressFamily {
+    fn matches(self, ip: &IpAddr) -> bool {
+        match self {
+            Self::Ipv4 => ip.is_ipv4(),
+            Self::Ipv6 => ip.is_ipv6(),
+        }
+    }
+}
+
+/// Resolves the current address of the interface `name` (case-insensitive).
+pub fn resolve(name: &str, family: AddressFamily) -> Result<IpAddr, KinectRtspError> {
+    let interfaces = if_addrs::get_if_addrs().map_err(|e| {
+        KinectRtspError::InvalidConfig(format!("Failed to list the network interfaces: {e}"))
+    })?;
+    let addresses: Vec<(String, IpAddr)> = interfaces
+        .into_iter()
+        .map(|interface| {
+            let ip = interface.ip();
+            (interface.name, ip)
+        })
+        .collect();
+    let ip = pick_address(&addresses, name, family).map_err(KinectRtspError::InvalidConfig)?;
+    log::info!("Interface '{name}' resolved to {ip}");
+    Ok(ip)
+}
+
+/// The address of interface `name` in `family`, else its first address in the other
+/// family. Link-local IPv6 addresses need a scope id to bind, so they are skipped.
+fn pick_address(
+    addresses: &[(String, IpAddr)],
+    name: &str,
+    family: AddressFamily,
+) -> Result<IpAddr, String> {
+    let mut candidates: Vec<IpAddr> = addresses
+        .iter()
+        .filter(|(interface, _)| interface.eq_ignore_ascii_case(name))
+        .map(|&(_, ip)| ip)
+        .collect();
+    if candidates.is_empty() {
+        let mut names: Vec<&str> = addresses.iter().map(|(name, _)| name.as_str()).collect();
+        names.sort_unstable();
+        names.dedup();
+        return Err(format!(
+            "Network interface '{name}' not found or has no address; interfaces with an address: {}",
+            names.join(", ")
+        ));
+    }
+    let link_local = |ip: &IpAddr| matches!(ip, IpAddr::V6(v6) if v6.is_unicast_link_local());
+    candidates.retain(|ip| !link_local(ip));
+    candidates.sort_by_key(|ip| !family.matches(ip));
+    let Some(&ip) = candidates.first() else {
+        return Err(format!(
+            "Network interface '{name}' only has link-local IPv6 addresses, which can't be \
+            bound without a scope id; pick an interface with a routable address"
+        ));
+    };
+    if !family.matches(&ip) {
+        log::warn!("Interface '{name}' has no {family:?} address, using {ip}");
+    }
+    Ok(ip)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pick_address() {
+        let addresses = vec![
+            ("Ethernet".to_string(), "fe80::1".parse().unwrap()),
+            ("Ethernet".to_string(), "2001:db8::5".parse().unwrap()),
+            ("Ethernet".to_string(), "192.168.1.20".parse().unwrap()),
+            ("Wi-Fi".to_string(), "fe80::2".parse().unwrap()),
+            ("VPN".to_string(), "10.8.0.2".parse().unwrap()),
+        ];
+        let pick = |name, family| pick_address(&addresses, name, family);
+        assert_eq!(
+            pick("ethernet", AddressFamily::Ipv4),
+            Ok("192.168.1.20".parse().unwrap())
+        );
+        assert_eq!(
+            pick("Ethernet", AddressFamily::Ipv6),
+            Ok("2001:db8::5".parse().unwrap())
+        );
+        let err = pick("Wi-Fi", AddressFamily::Ipv4).unwrap_err();
+        assert!(err.contains("only has link-local"));
+        // Falls back to the other family
+        assert_eq!(
+            pick("VPN", AddressFamily::Ipv6),
+            Ok("10.8.0.2".parse().unwrap())
+        );
+        let err = pick("eth0", AddressFamily::Ipv4).unwrap_err();
+        assert!(err.contains("'eth0' not found"));
+        assert!(err.contains("Ethernet, VPN, Wi-Fi"));
+    }
+}
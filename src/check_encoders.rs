@@ -0,0 +1,143 @@
+//! `check-encoders` subcommand: prints which of the encoders, payloaders, muxers and
+//! sinks the streams can use this GStreamer installation has, with the options that
+//! need each, so a missing element shows up before a stream fails on it.
+
+use anyhow::Result;
+use clap::ValueEnum;
+use gstreamer as gst;
+use gstreamer::prelude::*;
+
+use crate::encoder::VideoEncoder;
+#[cfg(feature = "recording")]
+use crate::motion_recording::RecordFormat;
+
+/// Elements the default streams can't start without.
+const REQUIRED: &[&str] = &["appsrc", "videoconvert", "h264parse", "rtph264pay", "queue"];
+
+/// Each element with what needs it, in table order.
+#[derive(Default)]
+struct Matrix {
+    rows: Vec<(&'static str, Vec<String>)>,
+}
+
+impl Matrix {
+    /// Adds `used_for` to the uses of `element`, adding its row if it's new.
+    fn add(&mut self, element: &'static str, used_for: impl Into<String>) {
+        let used_for = used_for.into();
+        match self.rows.iter_mut().find(|(name, _)| *name == element) {
+            Some((_, uses)) if !uses.contains(&used_for) => uses.push(used_for),
+            Some(_) => {}
+            None => self.rows.push((element, vec![used_for])),
+        }
+    }
+}
+
+/// The name of a value as given on the command line.
+fn option_value(value: &impl ValueEnum) -> String {
+    value
+        .to_possible_value()
+        .map_or_else(String::new, |v| v.get_name().to_string())
+}
+
+fn matrix() -> Matrix {
+    let mut matrix = Matrix::default();
+    for &element in REQUIRED {
+        matrix.add(element, "every stream");
+    }
+    for encoder in VideoEncoder::value_variants() {
+        matrix.add(
+            encoder.element_name(),
+            format!("--software-encoder {}", option_value(encoder)),
+        );
+    }
+    #[cfg(feature = "audio")]
+    for (n, (encoder, payloaders)) in crate::rtsp_publisher::audio_encoder_elements()
+        .into_iter()
+        .enumerate()
+    {
+        let used_for = if n == 0 {
+            "audio"
+        } else {
+            "audio with --audio-fallback"
+        };
+        matrix.add(encoder, used_for);
+        for payloader in payloaders {
+            matrix.add(payloader, format!("audio from {encoder}"));
+        }
+    }
+    #[cfg(feature = "recording")]
+    for format in RecordFormat::value_variants() {
+        matrix.add(
+            format.muxer(),
+            format!("--record-format {}", option_value(format)),
+        );
+    }
+    matrix.add("mpegtsmux", "--srt-url");
+    matrix.add("srtsink", "--srt-url");
+    matrix.add("shmsink", "--shm-color, --shm-depth");
+    for element in crate::color_pipe::ELEMENTS {
+        matrix.add(element, "--color-pipe");
+    }
+    #[cfg(feature = "recording")]
+    {
+        matrix.add("tee", "--tee-record");
+        matrix.add("filesink", "--tee-record");
+        matrix.add("appsink", "--record-on-motion");
+    }
+    matrix.add("videoscale", "--color-low, --infra-scale");
+    matrix.add("videocrop", "--color-crop");
+    matrix.add("videorate", "--color-keyframe-interval-secs");
+    matrix.add("videobalance", "--color-grayscale");
+    matrix.add("videomedian", "--denoise");
+    matrix.add("gaussianblur", "--color-sharpen");
+    matrix.add("textoverlay", "--overlay-ir-config");
+    matrix.add("ndisink", "--ndi");
+    matrix.add("autovideosink", "--preview");
+    matrix
+}
+
+/// Prints the table. Missing elements aren't an error: the table is informational.
+pub fn run() -> Result<()> {
+    gst::init()?;
+    println!("GStreamer {}", gst::version_string());
+    println!("{:<14} {:<32} needed for", "element", "available");
+    for (element, uses) in matrix().rows {
+        let available = match gst::ElementFactory::find(element) {
+            Some(factory) => match factory.plugin() {
+                Some(plugin) => format!("yes ({} {})", plugin.plugin_name(), plugin.version()),
+                None => "yes".into(),
+            },
+            None => "MISSING".into(),
+        };
+        println!("{element:<14} {available:<32} {}", uses.join("; "));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_element_has_one_row() {
+        let matrix = matrix();
+        let mut names: Vec<_> = matrix.rows.iter().map(|(name, _)| *name).collect();
+        names.sort_unstable();
+        names.dedup();
+        assert_eq!(names.len(), matrix.rows.len());
+
+        let uses = |element: &str| {
+            matrix
+                .rows
+                .iter()
+                .find(|(name, _)| *name == element)
+                .map(|(_, uses)| uses.join("; "))
+        };
+        assert_eq!(uses("x264enc").as_deref(), Some("--software-encoder x264"));
+        assert_eq!(
+            uses("mpegtsmux").as_deref(),
+            Some("--record-format ts; --srt-url")
+        );
+        assert!(uses("appsink").is_some_and(|uses| uses.contains("--color-pipe")));
+    }
+}
@@ -1,31 +1,640 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    sync::Arc,
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
 
 use anyhow::Context;
 use kinect_v2::{
     ColorImageFormat,
     color_capture::{ColorFrameCapture, ColorFrameCaptureIter, ColorFrameData},
 };
-use ringbuf::{
-    HeapRb, SharedRb,
-    storage::Heap,
-    traits::{Consumer, Producer, Split},
-    wrap::caching::Caching,
+
+use crate::affinity::pin_current_thread;
+use crate::frame_queue::{
+    FrameHandoff, FrameSender, KINECT_FPS, PublishRateCap, frame_queue, inline_sender,
 };
+use crate::priority::lower_current_thread;
+use crate::rtsp_publisher::{CaptureSource, CaptureStop, RtspPublisher};
+use crate::throttle::{PROGRESS_LOG_INTERVAL, STALL_WARN_INTERVAL, Throttle};
+use crate::warmup::Warmup;
+
+/// Color frame format requested from the Kinect SDK. YUY2 is the sensor's native
+/// format; the others are converted by the SDK on the CPU.
+///
+/// The SDK's `Yuv` and `Bayer` formats aren't offered: their byte layout isn't
+/// documented, so there are no raw caps to describe them to GStreamer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColorCaptureFormat {
+    /// Packed 4:2:2, native, 2 bytes per pixel
+    #[default]
+    Yuy2,
+    /// 4 bytes per pixel, B G R A
+    Bgra,
+    /// 4 bytes per pixel, R G B A
+    Rgba,
+}
+
+impl ColorCaptureFormat {
+    pub fn image_format(self) -> ColorImageFormat {
+        match self {
+            ColorCaptureFormat::Yuy2 => ColorImageFormat::Yuy2,
+            ColorCaptureFormat::Bgra => ColorImageFormat::Bgra,
+            ColorCaptureFormat::Rgba => ColorImageFormat::Rgba,
+        }
+    }
+
+    /// GStreamer `video/x-raw` format name of the frames.
+    pub fn caps_format(self) -> &'static str {
+        match self {
+            ColorCaptureFormat::Yuy2 => "YUY2",
+            ColorCaptureFormat::Bgra => "BGRA",
+            ColorCaptureFormat::Rgba => "RGBA",
+        }
+    }
+
+    /// A black frame in this format.
+    pub fn blank_frame(self, width: usize, height: usize) -> Vec<u8> {
+        let pixels = width * height;
+        match self {
+            // Two pixels per 4-byte macropixel: Y U Y V
+            ColorCaptureFormat::Yuy2 => [16u8, 128, 16, 128].repeat(pixels / 2),
+            ColorCaptureFormat::Bgra | ColorCaptureFormat::Rgba => [0u8, 0, 0, 255].repeat(pixels),
+        }
+    }
+
+    /// 75% color bars (white, yellow, cyan, green, magenta, red, blue) in this format.
+    pub fn color_bars(self, width: usize, height: usize) -> Vec<u8> {
+        const BARS: [[u8; 3]; 7] = [
+            [191, 191, 191],
+            [191, 191, 0],
+            [0, 191, 191],
+            [0, 191, 0],
+            [191, 0, 191],
+            [191, 0, 0],
+            [0, 0, 191],
+        ];
+        let bar = |x: usize| BARS[x * BARS.len() / width];
+        let row: Vec<u8> = match self {
+            ColorCaptureFormat::Yuy2 => (0..width)
+                .step_by(2)
+                .flat_map(|x| {
+                    // Chroma of the macropixel is taken from its left pixel
+                    let (y0, u, v) = bt709_limited(bar(x));
+                    let (y1, _, _) = bt709_limited(bar(x + 1));
+                    [y0, u, y1, v]
+                })
+                .collect(),
+            ColorCaptureFormat::Bgra => (0..width)
+                .flat_map(|x| {
+                    let [r, g, b] = bar(x);
+                    [b, g, r, 255]
+                })
+                .collect(),
+            ColorCaptureFormat::Rgba => (0..width)
+                .flat_map(|x| {
+                    let [r, g, b] = bar(x);
+                    [r, g, b, 255]
+                })
+                .collect(),
+        };
+        row.repeat(height)
+    }
+
+    /// Converts a frame of this format to packed 8-bit RGB.
+    pub fn to_rgb8(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            // Both pixels of a macropixel share its chroma
+            ColorCaptureFormat::Yuy2 => data
+                .chunks_exact(4)
+                .flat_map(|m| {
+                    let [r0, g0, b0] = bt709_limited_to_rgb(m[0], m[1], m[3]);
+                    let [r1, g1, b1] = bt709_limited_to_rgb(m[2], m[1], m[3]);
+                    [r0, g0, b0, r1, g1, b1]
+                })
+                .collect(),
+            ColorCaptureFormat::Bgra => data
+                .chunks_exact(4)
+                .flat_map(|p| [p[2], p[1], p[0]])
+                .collect(),
+            ColorCaptureFormat::Rgba => data
+                .chunks_exact(4)
+                .flat_map(|p| [p[0], p[1], p[2]])
+                .collect(),
+        }
+    }
+
+    /// Checks that the SDK can deliver this format on the connected device.
+    pub fn check_supported(self) -> anyhow::Result<()> {
+        let error = || format!("Color capture format {self:?} is not supported by this Kinect/SDK");
+        let capture =
+            ColorFrameCapture::new_with_format(self.image_format()).with_context(error)?;
+        capture.iter().with_context(error)?;
+        Ok(())
+    }
+
+    /// The format the captured bytes are described to GStreamer as: `order`
+    /// overrides the byte order of the 4-byte formats, YUY2 has no channels to swap.
+    pub fn with_channel_order(self, order: Option<ColorChannelOrder>) -> Self {
+        match (self, order) {
+            (ColorCaptureFormat::Yuy2, _) | (_, None) => self,
+            (_, Some(ColorChannelOrder::Bgra)) => ColorCaptureFormat::Bgra,
+            (_, Some(ColorChannelOrder::Rgba)) => ColorCaptureFormat::Rgba,
+        }
+    }
+}
+
+/// Byte order of the 4-byte color frames (`--color-channel-order`), for SDK builds
+/// that deliver red and blue swapped relative to the format they were asked for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColorChannelOrder {
+    /// B G R A
+    Bgra,
+    /// R G B A
+    Rgba,
+}
+
+/// Why `order` is likely a mistake for frames captured in `format`, if it is.
+pub fn channel_order_warning(
+    format: ColorCaptureFormat,
+    order: Option<ColorChannelOrder>,
+) -> Option<String> {
+    let order = order?;
+    let described = format.with_channel_order(Some(order));
+    if format == ColorCaptureFormat::Yuy2 {
+        Some(format!(
+            "--color-channel-order {} has no effect with --color-capture-format yuy2",
+            order_name(order)
+        ))
+    } else if described != format {
+        Some(format!(
+            "--color-channel-order {} describes the {} frames requested from the SDK as {}; \
+             red and blue are swapped unless this SDK build delivers them swapped",
+            order_name(order),
+            format.caps_format(),
+            described.caps_format()
+        ))
+    } else {
+        None
+    }
+}
+
+/// The name of `order` as given on the command line.
+fn order_name(order: ColorChannelOrder) -> &'static str {
+    match order {
+        ColorChannelOrder::Bgra => "bgra",
+        ColorChannelOrder::Rgba => "rgba",
+    }
+}
+
+/// Width of the color frames. The Kinect V2 color camera has this one capture
+/// mode; the SDK reports no other resolution for any format.
+pub const COLOR_WIDTH: u32 = 1920;
+pub const COLOR_HEIGHT: u32 = 1080;
+
+/// Resolution the color frames are captured in (`--color-resolution`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct ColorResolution {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for ColorResolution {
+    fn default() -> Self {
+        Self::NATIVE
+    }
+}
+
+impl ColorResolution {
+    pub const NATIVE: Self = Self {
+        width: COLOR_WIDTH,
+        height: COLOR_HEIGHT,
+    };
+    /// The resolutions the sensor can capture in.
+    const SUPPORTED: [Self; 1] = [Self::NATIVE];
+
+    /// Parses `WxH`, e.g. `1920x1080`. Only sizes the sensor captures natively are
+    /// accepted; frames aren't scaled, see `--color-low` for a smaller stream.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let resolution = Self::parse_size(s)?;
+        if !Self::SUPPORTED.contains(&resolution) {
+            let supported: Vec<String> = Self::SUPPORTED.iter().map(|r| r.to_string()).collect();
+            return Err(format!(
+                "unsupported color resolution {resolution}: the Kinect V2 color camera only captures {}",
+                supported.join(", ")
+            ));
+        }
+        Ok(resolution)
+    }
+
+    /// Parses the `WxH` size of a downscaled color stream (`--color-low-resolution`):
+    /// even, so it stays whole in YUY2 and I420, and no larger than the captured frame.
+    pub fn parse_scaled(s: &str) -> Result<Self, String> {
+        let resolution = Self::parse_size(s)?;
+        let Self { width, height } = resolution;
+        if width == 0 || height == 0 || !width.is_multiple_of(2) || !height.is_multiple_of(2) {
+            return Err(format!(
+                "invalid color resolution {resolution}: width and height must be even and above 0"
+            ));
+        }
+        if width > COLOR_WIDTH || height > COLOR_HEIGHT {
+            return Err(format!(
+                "invalid color resolution {resolution}: larger than the captured {}",
+                Self::NATIVE
+            ));
+        }
+        Ok(resolution)
+    }
+
+    fn parse_size(s: &str) -> Result<Self, String> {
+        let (width, height) = s
+            .split_once(['x', 'X'])
+            .ok_or_else(|| format!("invalid color resolution '{s}': expected WxH"))?;
+        let parse = |v: &str| {
+            v.trim()
+                .parse::<u32>()
+                .map_err(|e| format!("invalid color resolution '{s}': {e}"))
+        };
+        Ok(Self {
+            width: parse(width)?,
+            height: parse(height)?,
+        })
+    }
+}
+
+impl std::fmt::Display for ColorResolution {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+/// Rectangle of the 1920x1080 color frame blacked out in the raw frame before it's
+/// passed on, so the area is never encoded, written to shm or previewed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct PrivacyMask {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl PrivacyMask {
+    /// Parses `x,y,w,h` in source pixels; the region must lie within the 1920x1080 frame.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let values = s
+            .split(',')
+            .map(|v| v.trim().parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("invalid privacy mask '{s}': {e}"))?;
+        let [x, y, width, height] = values[..] else {
+            return Err(format!("invalid privacy mask '{s}': expected x,y,w,h"));
+        };
+        if width == 0 || height == 0 {
+            return Err(format!("invalid privacy mask '{s}': empty region"));
+        }
+        let exceeds =
+            |start: u32, len: u32, max: u32| start.checked_add(len).is_none_or(|end| end > max);
+        if exceeds(x, width, 1920) || exceeds(y, height, 1080) {
+            return Err(format!(
+                "invalid privacy mask '{s}': region exceeds 1920x1080"
+            ));
+        }
+        Ok(Self {
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+
+    /// Blacks out the mask in `frame`, `width` pixels wide, in `format`. In YUY2 the
+    /// region is widened to whole macropixels so no chroma of it survives.
+    pub fn apply(&self, format: ColorCaptureFormat, frame: &mut [u8], width: usize) {
+        let (black, pixels_per_unit): (&[u8], usize) = match format {
+            ColorCaptureFormat::Yuy2 => (&[16, 128, 16, 128], 2),
+            ColorCaptureFormat::Bgra | ColorCaptureFormat::Rgba => (&[0, 0, 0, 255], 1),
+        };
+        let stride = width * black.len() / pixels_per_unit;
+        let left = (self.x as usize / pixels_per_unit).min(width / pixels_per_unit);
+        let right = (self.x + self.width) as usize;
+        let right = right.div_ceil(pixels_per_unit).min(width / pixels_per_unit);
+        for row in frame
+            .chunks_exact_mut(stride)
+            .skip(self.y as usize)
+            .take(self.height as usize)
+        {
+            for unit in row[left * black.len()..right * black.len()].chunks_exact_mut(black.len()) {
+                unit.copy_from_slice(black);
+            }
+        }
+    }
+}
+
+/// Automatic white balance of the raw color frames (`--color-awb`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AutoWhiteBalance {
+    #[default]
+    Off,
+    /// Assume the scene averages to gray and correct the channels toward it
+    GrayWorld,
+}
+
+/// Frames between two gray-world estimates; the correction is applied to every frame.
+const AWB_INTERVAL: u64 = 10;
+/// Only every Nth row and every Nth 4-byte unit of a row are sampled.
+const AWB_GRID: usize = 8;
+/// Weight of a new estimate in the smoothed means, so the correction doesn't flicker.
+const AWB_SMOOTHING: f32 = 0.25;
+/// Largest RGB gain or smallest inverse; keeps a one-colored scene from being wrecked.
+const AWB_MAX_GAIN: f32 = 2.0;
+/// Largest YUY2 chroma shift.
+const AWB_MAX_CHROMA_SHIFT: f32 = 32.0;
+
+/// Gray-world white balance on the raw frames. Every [`AWB_INTERVAL`] frames the
+/// per-channel means are sampled on a coarse grid, then every frame is corrected
+/// through one lookup table per byte of the 4-byte unit (Y U Y V, B G R A or R G B A):
+/// RGB channels are scaled toward their common mean, YUY2 chroma is shifted so its
+/// mean is neutral.
+pub struct GrayWorld {
+    format: ColorCaptureFormat,
+    frame_count: u64,
+    means: Option<[f32; 4]>,
+    luts: Box<[[u8; 256]; 4]>,
+}
+
+impl GrayWorld {
+    pub fn new(format: ColorCaptureFormat) -> Self {
+        let identity = std::array::from_fn(|v| v as u8);
+        Self {
+            format,
+            frame_count: 0,
+            means: None,
+            luts: Box::new([identity; 4]),
+        }
+    }
+
+    /// Corrects `frame`, `width` pixels wide, re-estimating the cast when due.
+    pub fn apply(&mut self, frame: &mut [u8], width: usize) {
+        if self.frame_count.is_multiple_of(AWB_INTERVAL) {
+            self.estimate(frame, width);
+        }
+        self.frame_count += 1;
+        for unit in frame.chunks_exact_mut(4) {
+            for (byte, lut) in unit.iter_mut().zip(self.luts.iter()) {
+                *byte = lut[*byte as usize];
+            }
+        }
+    }
+
+    fn estimate(&mut self, frame: &[u8], width: usize) {
+        let stride = match self.format {
+            ColorCaptureFormat::Yuy2 => width * 2,
+            ColorCaptureFormat::Bgra | ColorCaptureFormat::Rgba => width * 4,
+        };
+        if stride == 0 {
+            return;
+        }
+        let mut sums = [0u64; 4];
+        let mut count = 0u64;
+        for row in frame.chunks_exact(stride).step_by(AWB_GRID) {
+            for unit in row.chunks_exact(4).step_by(AWB_GRID) {
+                for (sum, &byte) in sums.iter_mut().zip(unit) {
+                    *sum += u64::from(byte);
+                }
+                count += 1;
+            }
+        }
+        if count == 0 {
+            return;
+        }
+        let sample = sums.map(|sum| sum as f32 / count as f32);
+        let means = match self.means {
+            Some(means) => {
+                std::array::from_fn(|i| means[i] + (sample[i] - means[i]) * AWB_SMOOTHING)
+            }
+            None => sample,
+        };
+        self.means = Some(means);
+        *self.luts = gray_world_luts(self.format, means);
+    }
+}
+
+/// Lookup tables per byte of the 4-byte unit that neutralize the `means` cast.
+fn gray_world_luts(format: ColorCaptureFormat, means: [f32; 4]) -> [[u8; 256]; 4] {
+    let identity: [u8; 256] = std::array::from_fn(|v| v as u8);
+    let shifted = |mean: f32| -> [u8; 256] {
+        let shift = (128.0 - mean).clamp(-AWB_MAX_CHROMA_SHIFT, AWB_MAX_CHROMA_SHIFT);
+        std::array::from_fn(|v| (v as f32 + shift).round().clamp(0.0, 255.0) as u8)
+    };
+    let scaled = |mean: f32, gray: f32| -> [u8; 256] {
+        let gain = if mean > 0.0 {
+            (gray / mean).clamp(1.0 / AWB_MAX_GAIN, AWB_MAX_GAIN)
+        } else {
+            1.0
+        };
+        std::array::from_fn(|v| (v as f32 * gain).round().min(255.0) as u8)
+    };
+    match format {
+        ColorCaptureFormat::Yuy2 => [identity, shifted(means[1]), identity, shifted(means[3])],
+        ColorCaptureFormat::Bgra | ColorCaptureFormat::Rgba => {
+            let gray = (means[0] + means[1] + means[2]) / 3.0;
+            [
+                scaled(means[0], gray),
+                scaled(means[1], gray),
+                scaled(means[2], gray),
+                identity,
+            ]
+        }
+    }
+}
+
+/// Default of `--color-auto-brightness-rate`.
+pub const DEFAULT_AUTO_BRIGHTNESS_RATE: f32 = 0.1;
+/// Frames between two brightness measurements; the gain is applied to every frame.
+const BRIGHTNESS_INTERVAL: u64 = 3;
+/// Largest brightness gain or smallest inverse; beyond it the noise or the clipping
+/// is worse than a frame that is off target.
+const BRIGHTNESS_MAX_GAIN: f32 = 4.0;
+
+/// Software auto-brightness of the raw color frames (`--color-auto-brightness`).
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+pub struct AutoBrightness {
+    /// Mean luma to hold, 0-255 full range.
+    pub target: u8,
+    /// Fraction of the remaining correction applied per measurement, in (0, 1].
+    pub rate: f32,
+}
+
+/// Parses `--color-auto-brightness-rate`, a fraction in (0, 1].
+pub fn parse_auto_brightness_rate(s: &str) -> Result<f32, String> {
+    let rate: f32 = s
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid rate '{s}': expected a number like 0.1"))?;
+    if !(rate > 0.0 && rate <= 1.0) {
+        return Err(format!(
+            "invalid rate {rate}: must be above 0 and at most 1"
+        ));
+    }
+    Ok(rate)
+}
+
+/// Holds the mean luma of the raw frames at [`AutoBrightness::target`] with a gain
+/// on the luma (YUY2) or on the RGB channels. Every [`BRIGHTNESS_INTERVAL`] frames
+/// the luma is sampled on the [`AWB_GRID`] grid and the gain moves `rate` of the
+/// way toward the one that would hit the target, so it follows a change in light
+/// within a second or two without pumping.
+pub struct BrightnessAssist {
+    format: ColorCaptureFormat,
+    settings: AutoBrightness,
+    frame_count: u64,
+    gain: f32,
+    luts: Box<[[u8; 256]; 4]>,
+}
+
+impl BrightnessAssist {
+    pub fn new(format: ColorCaptureFormat, settings: AutoBrightness) -> Self {
+        let identity = std::array::from_fn(|v| v as u8);
+        Self {
+            format,
+            settings,
+            frame_count: 0,
+            gain: 1.0,
+            luts: Box::new([identity; 4]),
+        }
+    }
+
+    /// Brightens or darkens `frame`, `width` pixels wide, re-measuring when due.
+    pub fn apply(&mut self, frame: &mut [u8], width: usize) {
+        if self.frame_count.is_multiple_of(BRIGHTNESS_INTERVAL) {
+            self.adjust(frame, width);
+        }
+        self.frame_count += 1;
+        if self.gain == 1.0 {
+            return;
+        }
+        for unit in frame.chunks_exact_mut(4) {
+            for (byte, lut) in unit.iter_mut().zip(self.luts.iter()) {
+                *byte = lut[*byte as usize];
+            }
+        }
+    }
+
+    fn adjust(&mut self, frame: &[u8], width: usize) {
+        let Some(luma) = mean_luma(self.format, frame, width) else {
+            return;
+        };
+        // A black frame (lens covered, lights off) has no brightness to scale
+        let wanted = if luma >= 1.0 {
+            (f32::from(self.settings.target) / luma)
+                .clamp(1.0 / BRIGHTNESS_MAX_GAIN, BRIGHTNESS_MAX_GAIN)
+        } else {
+            self.gain
+        };
+        self.gain += (wanted - self.gain) * self.settings.rate;
+        *self.luts = brightness_luts(self.format, self.gain);
+    }
+}
+
+/// Mean full-range luma of `frame`, sampled on the [`AWB_GRID`] grid.
+fn mean_luma(format: ColorCaptureFormat, frame: &[u8], width: usize) -> Option<f32> {
+    let stride = match format {
+        ColorCaptureFormat::Yuy2 => width * 2,
+        ColorCaptureFormat::Bgra | ColorCaptureFormat::Rgba => width * 4,
+    };
+    if stride == 0 {
+        return None;
+    }
+    let luma = |unit: &[u8]| -> f32 {
+        match format {
+            ColorCaptureFormat::Yuy2 => {
+                let y = (f32::from(unit[0]) + f32::from(unit[2])) / 2.0;
+                ((y - 16.0) * 255.0 / 219.0).clamp(0.0, 255.0)
+            }
+            ColorCaptureFormat::Bgra => {
+                0.2126 * f32::from(unit[2])
+                    + 0.7152 * f32::from(unit[1])
+                    + 0.0722 * f32::from(unit[0])
+            }
+            ColorCaptureFormat::Rgba => {
+                0.2126 * f32::from(unit[0])
+                    + 0.7152 * f32::from(unit[1])
+                    + 0.0722 * f32::from(unit[2])
+            }
+        }
+    };
+    let (mut sum, mut count) = (0.0f64, 0u64);
+    for row in frame.chunks_exact(stride).step_by(AWB_GRID) {
+        for unit in row.chunks_exact(4).step_by(AWB_GRID) {
+            sum += f64::from(luma(unit));
+            count += 1;
+        }
+    }
+    (count > 0).then(|| (sum / count as f64) as f32)
+}
 
-use crate::rtsp_publisher::RtspPublisher;
+/// Lookup tables per byte of the 4-byte unit that apply `gain` to the luma: to the
+/// limited-range Y of YUY2 (chroma untouched), or to each RGB channel.
+fn brightness_luts(format: ColorCaptureFormat, gain: f32) -> [[u8; 256]; 4] {
+    let identity: [u8; 256] = std::array::from_fn(|v| v as u8);
+    match format {
+        ColorCaptureFormat::Yuy2 => {
+            let y: [u8; 256] = std::array::from_fn(|v| {
+                (16.0 + (v as f32 - 16.0) * gain).round().clamp(16.0, 235.0) as u8
+            });
+            [y, identity, y, identity]
+        }
+        ColorCaptureFormat::Bgra | ColorCaptureFormat::Rgba => {
+            let scaled: [u8; 256] =
+                std::array::from_fn(|v| (v as f32 * gain).round().min(255.0) as u8);
+            [scaled, scaled, scaled, identity]
+        }
+    }
+}
+
+/// Converts RGB to limited-range BT.709 Y'CbCr.
+fn bt709_limited([r, g, b]: [u8; 3]) -> (u8, u8, u8) {
+    let (r, g, b) = (r as f32, g as f32, b as f32);
+    let luma = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let y = 16.0 + luma * 219.0 / 255.0;
+    let cb = 128.0 + (b - luma) / 1.8556 * 224.0 / 255.0;
+    let cr = 128.0 + (r - luma) / 1.5748 * 224.0 / 255.0;
+    (y.round() as u8, cb.round() as u8, cr.round() as u8)
+}
+
+/// Converts limited-range BT.709 Y'CbCr back to RGB, the inverse of [`bt709_limited`].
+fn bt709_limited_to_rgb(y: u8, cb: u8, cr: u8) -> [u8; 3] {
+    let luma = (y as f32 - 16.0) * 255.0 / 219.0;
+    let cb = (cb as f32 - 128.0) * 255.0 / 224.0;
+    let cr = (cr as f32 - 128.0) * 255.0 / 224.0;
+    let r = luma + 1.5748 * cr;
+    let b = luma + 1.8556 * cb;
+    let g = (luma - 0.2126 * r - 0.0722 * b) / 0.7152;
+    [r, g, b].map(|c| c.round().clamp(0.0, 255.0) as u8)
+}
 
 fn color_frame_capture(
     rtsp: Arc<RtspPublisher>,
-    raw_tx: &mut Caching<Arc<SharedRb<Heap<ColorFrameData>>>, true, false>,
+    format: ColorCaptureFormat,
+    frame_skip: u32,
+    warmup_frames: u32,
+    stop: &CaptureStop,
+    frames: &FrameSender<(Instant, ColorFrameData)>,
 ) -> anyhow::Result<()> {
     let mut color_capture: Option<ColorFrameCapture> = None;
     let mut iter: Option<ColorFrameCaptureIter> = None;
+    let mut warmup = Warmup::new(warmup_frames);
 
-    let mut frame_count = 0;
-    let mut last_log_time = std::time::Instant::now();
+    let mut frame_count: u64 = 0;
+    let mut progress_log = Throttle::new(PROGRESS_LOG_INTERVAL);
+    let mut stall_warning = Throttle::new(STALL_WARN_INTERVAL);
+    let always_on = rtsp.is_always_on(CaptureSource::Color);
 
-    loop {
-        if !rtsp.is_color_active() {
+    while !rtsp.should_stop(stop) {
+        if !always_on && !rtsp.is_color_active() {
             // RTSP color capture not active, release Kinect resources.
             if iter.is_some() {
                 iter = None;
@@ -42,8 +651,9 @@ fn color_frame_capture(
             if color_capture.is_none() {
                 log::info!("Kinect color capture starting...");
                 color_capture = Some(
-                    ColorFrameCapture::new_with_format(ColorImageFormat::Yuy2)
-                        .context("Failed to create color capture with YUY2 format")?,
+                    ColorFrameCapture::new_with_format(format.image_format()).with_context(
+                        || format!("Failed to create color capture with {format:?} format"),
+                    )?,
                 );
             }
 
@@ -53,6 +663,8 @@ fn color_frame_capture(
                         .iter()
                         .context("Failed to create color capture iterator")?,
                 );
+                warmup.restart();
+                stall_warning.reset();
             } else {
                 std::thread::sleep(Duration::from_millis(30));
                 continue;
@@ -62,17 +674,25 @@ fn color_frame_capture(
         if let Some(iter) = &mut iter {
             match iter.next() {
                 Some(Ok(data)) => {
+                    if warmup.discard() {
+                        log::trace!("Discarding color warmup frame");
+                        continue;
+                    }
                     frame_count += 1;
-                    if frame_count % 30 == 0 || last_log_time.elapsed() > Duration::from_secs(5) {
+                    stall_warning.reset();
+                    if progress_log.should_log() {
                         log::debug!(
                             "✅ Captured color frame #{}: {}x{}",
                             frame_count,
                             data.width,
                             data.height
                         );
-                        last_log_time = std::time::Instant::now();
                     }
-                    if raw_tx.try_push(data).is_err() {
+                    // Time-lapse: only every Nth frame goes on, starting with the first
+                    if frame_skip > 1 && !(frame_count - 1).is_multiple_of(u64::from(frame_skip)) {
+                        continue;
+                    }
+                    if !frames.push((Instant::now(), data)) {
                         log::debug!("❌ Color frame buffer full, dropping frame");
                     }
                 }
@@ -80,59 +700,315 @@ fn color_frame_capture(
                     log::warn!("⚠️ Error capturing color frame: {e}");
                 }
                 None => {
-                    if last_log_time.elapsed() > Duration::from_secs(10) {
+                    if stall_warning.should_log() {
                         log::warn!(
                             "🔍 No color frames available from Kinect - is the device connected?"
                         );
-                        last_log_time = std::time::Instant::now();
                     }
                     std::thread::sleep(Duration::from_millis(5));
                 }
             }
         }
     }
+    Ok(())
 }
 
-fn color_frame_publish(
+/// The publish step of one captured frame: white-balances it, blacks out the privacy
+/// masks and sends it.
+fn color_frame_publisher(
     rtsp: Arc<RtspPublisher>,
-    raw_rx: &mut Caching<Arc<SharedRb<Heap<ColorFrameData>>>, false, true>,
-) -> anyhow::Result<()> {
-    loop {
-        if let Some(color_frame) = raw_rx.try_pop() {
-            if color_frame.data.is_empty() {
-                continue;
-            }
-            assert_eq!(
-                color_frame.image_format,
-                ColorImageFormat::Yuy2,
-                "Color frame format mismatch"
-            );
-
-            rtsp.send_color_yuy2(color_frame.width, color_frame.height, &color_frame.data);
-        } else {
-            // No new frame yet, sleep briefly to avoid busy waiting
-            std::thread::sleep(Duration::from_millis(30));
+    format: ColorCaptureFormat,
+    brightness: Option<AutoBrightness>,
+    awb: AutoWhiteBalance,
+    privacy_masks: Vec<PrivacyMask>,
+    publish_fps: u32,
+) -> impl FnMut((Instant, ColorFrameData)) + Send + 'static {
+    let mut rate_cap = PublishRateCap::new(publish_fps);
+    let mut brightness = brightness.map(|settings| BrightnessAssist::new(format, settings));
+    let mut gray_world = (awb == AutoWhiteBalance::GrayWorld).then(|| GrayWorld::new(format));
+    move |(captured, mut color_frame)| {
+        // With --capture-mode always-on frames keep coming without consumers
+        if color_frame.data.is_empty() || !rtsp.is_color_active() {
+            return;
         }
+        // Before any processing, so a dropped frame costs nothing
+        rate_cap.set_fps(rtsp.governed_fps(publish_fps));
+        if !rate_cap.admit() {
+            return;
+        }
+        assert_eq!(
+            color_frame.image_format,
+            format.image_format(),
+            "Color frame format mismatch"
+        );
+        // Before the white balance, which a uniform gain doesn't change
+        if let Some(brightness) = brightness.as_mut() {
+            brightness.apply(&mut color_frame.data, color_frame.width as usize);
+        }
+        if let Some(gray_world) = gray_world.as_mut() {
+            gray_world.apply(&mut color_frame.data, color_frame.width as usize);
+        }
+        // After the white balance, so masked regions stay black
+        for mask in &privacy_masks {
+            mask.apply(format, &mut color_frame.data, color_frame.width as usize);
+        }
+
+        rtsp.send_color(
+            captured,
+            color_frame.width,
+            color_frame.height,
+            &color_frame.data,
+        );
     }
 }
 
-pub fn spawn_color_pipeline(rtsp: Arc<RtspPublisher>) {
-    // Limit buffering to reduce peak memory: 16 x 1920x1080 YUY2 ~ 64MB
-    let raw_ring_buffer = HeapRb::<ColorFrameData>::new(16);
-    let (mut raw_tx, mut raw_rx) = raw_ring_buffer.split();
+/// What the color pipeline captures and how it processes the frames before
+/// [`RtspPublisher::send_color`].
+pub struct ColorPipelineSettings {
+    pub format: ColorCaptureFormat,
+    /// Only every Nth captured frame is published.
+    pub frame_skip: u32,
+    /// Frames discarded after the capture starts or resumes.
+    pub warmup_frames: u32,
+    pub brightness: Option<AutoBrightness>,
+    pub awb: AutoWhiteBalance,
+    /// Regions blacked out in the raw frames.
+    pub privacy_masks: Vec<PrivacyMask>,
+    /// Most frames published per second.
+    pub publish_fps: u32,
+}
+
+pub fn spawn_color_pipeline(
+    rtsp: Arc<RtspPublisher>,
+    settings: ColorPipelineSettings,
+    handoff: FrameHandoff,
+) -> Vec<JoinHandle<()>> {
+    let ColorPipelineSettings {
+        format,
+        frame_skip,
+        warmup_frames,
+        brightness,
+        awb,
+        privacy_masks,
+        publish_fps,
+    } = settings;
+    if let Some(AutoBrightness { target, rate }) = brightness {
+        log::info!("Color auto brightness: target luma {target}, rate {rate}");
+    }
+    if awb != AutoWhiteBalance::Off {
+        log::info!("Color auto white balance: {awb:?}");
+    }
+    if !privacy_masks.is_empty() {
+        log::info!(
+            "Blacking out {} privacy mask(s) in the raw color frames",
+            privacy_masks.len()
+        );
+    }
+    if publish_fps < KINECT_FPS {
+        log::info!("Color publish rate capped at {publish_fps} fps");
+    }
+    let publish = color_frame_publisher(
+        rtsp.clone(),
+        format,
+        brightness,
+        awb,
+        privacy_masks,
+        publish_fps,
+    );
+    let stop = rtsp.capture_stop(CaptureSource::Color);
+    let mut threads = Vec::new();
+    let frames_tx = match handoff {
+        FrameHandoff::Inline => inline_sender(publish),
+        FrameHandoff::Queue(queue) => {
+            // Limit buffering to reduce peak memory: 16 frames, ~64MB at 1920x1080 YUY2
+            // (twice that for BGRA/RGBA). Frames are queued as captured; one whose data
+            // doesn't match its dimensions is dropped at the push, see `frame_byte_len`
+            let (frames_tx, frames_rx) = frame_queue(16, queue.full);
+            let frames_tx = frames_tx.monitor(
+                "color",
+                rtsp.metrics().color_queue.clone(),
+                queue.high_water_percent,
+            );
+            let rtsp = rtsp.clone();
+            let stop = stop.clone();
+            // Publish thread
+            let thread = std::thread::Builder::new()
+                .name("color-publish".into())
+                .spawn(move || {
+                    pin_current_thread();
+                    lower_current_thread();
+                    let wakeups = &rtsp.metrics().color_publish_wakeups;
+                    frames_rx.for_each(|| rtsp.should_stop(&stop), wakeups, publish);
+                })
+                .expect("Failed to spawn color publish thread");
+            threads.push(thread);
+            frames_tx
+        }
+    };
 
-    let rtsp_clone = rtsp.clone();
     // Color capture thread
-    std::thread::spawn(move || {
-        if let Err(e) = color_frame_capture(rtsp_clone, &mut raw_tx) {
-            log::error!("Error capturing color frames: {e}");
+    let capture = std::thread::Builder::new()
+        .name("color-capture".into())
+        .spawn(move || {
+            pin_current_thread();
+            lower_current_thread();
+            if let Err(e) = color_frame_capture(
+                rtsp.clone(),
+                format,
+                frame_skip,
+                warmup_frames,
+                &stop,
+                &frames_tx,
+            ) {
+                log::error!("Error capturing color frames: {e}");
+                rtsp.set_capture_failed(CaptureSource::Color);
+            }
+        })
+        .expect("Failed to spawn color capture thread");
+    threads.push(capture);
+
+    threads
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_channel_order_sets_the_caps_format() {
+        use ColorCaptureFormat::*;
+        // Not specified keeps the capture format's own order
+        assert_eq!(Bgra.with_channel_order(None).caps_format(), "BGRA");
+        assert_eq!(Rgba.with_channel_order(None).caps_format(), "RGBA");
+        let rgba = Some(ColorChannelOrder::Rgba);
+        let bgra = Some(ColorChannelOrder::Bgra);
+        assert_eq!(Bgra.with_channel_order(rgba).caps_format(), "RGBA");
+        assert_eq!(Rgba.with_channel_order(bgra).caps_format(), "BGRA");
+        assert_eq!(Yuy2.with_channel_order(rgba).caps_format(), "YUY2");
+
+        assert_eq!(channel_order_warning(Bgra, None), None);
+        assert_eq!(channel_order_warning(Bgra, bgra), None);
+        let warning = channel_order_warning(Bgra, rgba).unwrap();
+        assert!(
+            warning.contains("BGRA frames requested from the SDK as RGBA"),
+            "{warning}"
+        );
+        let warning = channel_order_warning(Yuy2, rgba).unwrap();
+        assert!(warning.contains("no effect"), "{warning}");
+    }
+
+    #[test]
+    fn test_color_resolution_accepts_only_the_native_mode() {
+        assert_eq!(
+            ColorResolution::parse("1920x1080"),
+            Ok(ColorResolution::NATIVE)
+        );
+        assert_eq!(
+            ColorResolution::parse(" 1920 X 1080 "),
+            Ok(ColorResolution::NATIVE)
+        );
+        let err = ColorResolution::parse("1280x720").unwrap_err();
+        assert!(err.contains("only captures 1920x1080"), "{err}");
+        assert!(ColorResolution::parse("1920").is_err());
+        assert!(ColorResolution::parse("1920x-1").is_err());
+    }
+
+    #[test]
+    fn test_scaled_color_resolution_is_even_and_fits() {
+        assert_eq!(
+            ColorResolution::parse_scaled("640x360"),
+            Ok(ColorResolution {
+                width: 640,
+                height: 360
+            })
+        );
+        assert!(ColorResolution::parse_scaled("1920x1080").is_ok());
+        let err = ColorResolution::parse_scaled("641x360").unwrap_err();
+        assert!(err.contains("must be even"), "{err}");
+        let err = ColorResolution::parse_scaled("2560x1440").unwrap_err();
+        assert!(err.contains("larger than the captured 1920x1080"), "{err}");
+        assert!(ColorResolution::parse_scaled("0x360").is_err());
+    }
+
+    #[test]
+    fn test_privacy_mask_blacks_out_whole_pixels() {
+        let mask = PrivacyMask::parse("1, 1, 2,1").unwrap();
+        let mut bgra = vec![255u8; 4 * 4 * 2];
+        mask.apply(ColorCaptureFormat::Bgra, &mut bgra, 4);
+        assert_eq!(bgra[..16], [255; 16]);
+        assert_eq!(bgra[16..20], [255; 4]);
+        assert_eq!(bgra[20..28], [0, 0, 0, 255, 0, 0, 0, 255]);
+        assert_eq!(bgra[28..], [255; 4]);
+
+        // x=1..3 touches both macropixels of the 4-pixel YUY2 row
+        let mut yuy2 = vec![255u8; 2 * 4 * 2];
+        mask.apply(ColorCaptureFormat::Yuy2, &mut yuy2, 4);
+        assert_eq!(yuy2[..8], [255; 8]);
+        assert_eq!(yuy2[8..], [16, 128, 16, 128, 16, 128, 16, 128]);
+    }
+
+    #[test]
+    fn test_gray_world_removes_a_cast() {
+        // 16x16 BGRA frame with a blue cast
+        let mut bgra = [160u8, 100, 80, 255].repeat(16 * 16);
+        GrayWorld::new(ColorCaptureFormat::Bgra).apply(&mut bgra, 16);
+        let [b, g, r, a] = bgra[..4] else {
+            unreachable!()
+        };
+        assert!(b.abs_diff(g) <= 1 && g.abs_diff(r) <= 1, "{b} {g} {r}");
+        assert_eq!(a, 255);
+
+        // YUY2 with chroma off neutral: the shift is capped at AWB_MAX_CHROMA_SHIFT
+        let mut yuy2 = [90u8, 140, 110, 60].repeat(8 * 16);
+        GrayWorld::new(ColorCaptureFormat::Yuy2).apply(&mut yuy2, 16);
+        assert_eq!(yuy2[..4], [90, 128, 110, 92]);
+    }
+
+    #[test]
+    fn test_brightness_assist_converges_on_target() {
+        let settings = AutoBrightness {
+            target: 120,
+            rate: 0.5,
+        };
+        let mut assist = BrightnessAssist::new(ColorCaptureFormat::Bgra, settings);
+        let dark = [40u8, 40, 40, 255].repeat(16 * 16);
+        let mut bgra = dark.clone();
+        for _ in 0..60 {
+            bgra.copy_from_slice(&dark);
+            assist.apply(&mut bgra, 16);
         }
-    });
+        assert!(bgra[0].abs_diff(120) <= 2, "{:?}", &bgra[..4]);
+        assert_eq!(bgra[3], 255);
 
-    // Publish thread
-    std::thread::spawn(move || {
-        if let Err(e) = color_frame_publish(rtsp, &mut raw_rx) {
-            log::error!("Error publishing color frames: {e}");
+        // The first frame only moves half way, and YUY2 chroma is left alone
+        let mut assist = BrightnessAssist::new(ColorCaptureFormat::Yuy2, settings);
+        let mut yuy2 = [60u8, 100, 60, 150].repeat(8 * 16);
+        assist.apply(&mut yuy2, 16);
+        assert!(yuy2[0] > 60 && yuy2[0] < 120, "{:?}", &yuy2[..4]);
+        assert_eq!((yuy2[1], yuy2[3]), (100, 150));
+    }
+
+    #[test]
+    fn test_yuy2_to_rgb_round_trips_color_bars() {
+        let yuy2 = ColorCaptureFormat::Yuy2.color_bars(14, 1);
+        let rgb = ColorCaptureFormat::Yuy2.to_rgb8(&yuy2);
+        let expected: Vec<u8> = ColorCaptureFormat::Bgra
+            .color_bars(14, 1)
+            .chunks_exact(4)
+            .flat_map(|p| [p[2], p[1], p[0]])
+            .collect();
+        assert_eq!(rgb.len(), expected.len());
+        for (got, want) in rgb.iter().zip(&expected) {
+            assert!(got.abs_diff(*want) <= 1, "{rgb:?} vs {expected:?}");
         }
-    });
+    }
+
+    #[test]
+    fn test_privacy_mask_must_fit_the_frame() {
+        assert!(PrivacyMask::parse("0,0,1920,1080").is_ok());
+        assert!(PrivacyMask::parse("1900,0,21,10").is_err());
+        assert!(PrivacyMask::parse("4294967295,0,1,10").is_err());
+        assert!(PrivacyMask::parse("0,0,0,10").is_err());
+        assert!(PrivacyMask::parse("0,0,10").is_err());
+    }
 }
@@ -0,0 +1,95 @@
+//! `--color-pipe <name>`: writes the encoded `/color` stream to a Windows named pipe
+//! as Annex-B H.264, for same-machine consumers that don't want an RTSP round trip.
+//!
+//! The pipe is opened once, at startup, by a [`LocalSink`] that only writes what it
+//! gets. Every `/color` media tees the output of its h264parse into an appsink that
+//! feeds that sink, so the stream is encoded once for RTSP and the pipe.
+
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use gstreamer_app as gst_app;
+use gstreamer_rtsp_server as rtsp;
+use gstreamer_rtsp_server::prelude::*;
+
+use crate::encoder::tee_after_parse;
+use crate::local_sink::LocalSink;
+
+/// Elements the pipe output needs in the media and in its own pipeline.
+pub const ELEMENTS: [&str; 4] = ["tee", "queue", "appsink", "filesink"];
+
+/// Format written to the pipe: Annex-B, one access unit per write.
+const PIPE_CAPS: &str = "video/x-h264,stream-format=byte-stream,alignment=au";
+
+/// Windows named pipe path for `--color-pipe <name>`, e.g. `\\.\pipe\kinect` for
+/// `kinect`. A full `\\.\pipe\...` path is kept as is.
+pub fn path(name: &str) -> String {
+    const PREFIX: &str = r"\\.\pipe\";
+    if name.starts_with(PREFIX) {
+        name.to_string()
+    } else {
+        format!("{PREFIX}{name}")
+    }
+}
+
+/// Opens the pipe `name` and tees the encoded stream of every media of `factory`
+/// into it.
+pub fn start(factory: &rtsp::RTSPMediaFactory, name: &str) -> Result<()> {
+    let path = path(name);
+    let sink = LocalSink::start(
+        "Color pipe",
+        PIPE_CAPS,
+        &format!(
+            "filesink location=\"{}\" sync=false",
+            path.replace('\\', "\\\\")
+        ),
+    )
+    .with_context(|| {
+        format!("Failed to open {path}, the reading process must create the pipe first")
+    })?;
+    let sink = Arc::new(sink);
+    factory.connect_media_configure(move |_, media| {
+        if let Err(e) = add_branch(media, sink.clone()) {
+            log::warn!("Color pipe not fed by this media: {e:#}");
+        }
+    });
+    log::info!("Encoded color stream written to named pipe {path} (Annex-B H.264)");
+    Ok(())
+}
+
+/// Tees the media's h264parse into an appsink that pushes to `sink`.
+fn add_branch(media: &rtsp::RTSPMedia, sink: Arc<LocalSink>) -> Result<()> {
+    let queue = gst::ElementFactory::make("queue").build()?;
+    let appsink = gst::ElementFactory::make("appsink")
+        .property("caps", PIPE_CAPS.parse::<gst::Caps>()?)
+        .property("sync", false)
+        .property("async", false)
+        .build()?
+        .downcast::<gst_app::AppSink>()
+        .map_err(|_| anyhow::anyhow!("appsink isn't an AppSink"))?;
+    appsink.set_callbacks(
+        gst_app::AppSinkCallbacks::builder()
+            .new_sample(move |appsink| {
+                let sample = appsink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
+                if let Some(buffer) = sample.buffer_owned() {
+                    sink.push_buffer(buffer);
+                }
+                Ok(gst::FlowSuccess::Ok)
+            })
+            .build(),
+    );
+    tee_after_parse(media, &[&queue, appsink.upcast_ref()])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_color_pipe_name_gets_the_pipe_prefix() {
+        assert_eq!(path("kinect"), r"\\.\pipe\kinect");
+        assert_eq!(path(r"\\.\pipe\kinect"), r"\\.\pipe\kinect");
+    }
+}
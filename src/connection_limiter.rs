@@ -0,0 +1,130 @@
+//! Per-IP limit on new RTSP connections (`--max-connects-per-sec`), so a client or
+//! scanner reconnecting in a tight loop can't keep medias preparing and the Kinect
+//! capture switching on and off.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Hosts tracked before entries that are neither counting nor blocked are dropped.
+const PRUNE_THRESHOLD: usize = 1024;
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// What to do with a new connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    Allowed,
+    /// The host is still serving its block.
+    Blocked,
+    /// This connection crossed the limit; the host is blocked from now on.
+    NewlyBlocked,
+}
+
+struct Host {
+    window_start: Instant,
+    connects: u32,
+    blocked_until: Option<Instant>,
+}
+
+pub struct ConnectionLimiter {
+    /// Connections allowed per host and second; 0 disables the limit.
+    max_per_sec: u32,
+    /// How long a host that exceeded the limit is refused.
+    block: Duration,
+    hosts: HashMap<String, Host>,
+}
+
+impl ConnectionLimiter {
+    pub fn new(max_per_sec: u32, block: Duration) -> Self {
+        Self {
+            max_per_sec,
+            block,
+            hosts: HashMap::new(),
+        }
+    }
+
+    /// Counts a connection from `ip` at `now` and tells whether to serve it.
+    pub fn admit(&mut self, ip: &str, now: Instant) -> Admission {
+        if self.max_per_sec == 0 {
+            return Admission::Allowed;
+        }
+        if self.hosts.len() >= PRUNE_THRESHOLD {
+            self.hosts.retain(|_, host| {
+                host.blocked_until.is_some_and(|until| now < until)
+                    || now.duration_since(host.window_start) < WINDOW
+            });
+        }
+        let host = self.hosts.entry(ip.to_string()).or_insert(Host {
+            window_start: now,
+            connects: 0,
+            blocked_until: None,
+        });
+        match host.blocked_until {
+            Some(until) if now < until => return Admission::Blocked,
+            Some(_) => host.blocked_until = None,
+            None => {}
+        }
+        if now.duration_since(host.window_start) >= WINDOW {
+            host.window_start = now;
+            host.connects = 0;
+        }
+        host.connects += 1;
+        if host.connects > self.max_per_sec {
+            host.blocked_until = Some(now + self.block);
+            host.connects = 0;
+            return Admission::NewlyBlocked;
+        }
+        Admission::Allowed
+    }
+
+    /// Whether `ip` is serving a block at `now`. Unlike [`Self::admit`] it counts
+    /// nothing, so it can be asked on every request of an open connection.
+    pub fn is_blocked(&self, ip: &str, now: Instant) -> bool {
+        self.hosts
+            .get(ip)
+            .and_then(|host| host.blocked_until)
+            .is_some_and(|until| now < until)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flooding_host_is_blocked_for_the_block_time() {
+        let mut limiter = ConnectionLimiter::new(3, Duration::from_secs(30));
+        let t0 = Instant::now();
+        for i in 0..3 {
+            let now = t0 + Duration::from_millis(i * 100);
+            assert_eq!(limiter.admit("10.0.0.9", now), Admission::Allowed);
+        }
+        let fourth = t0 + Duration::from_millis(300);
+        assert_eq!(limiter.admit("10.0.0.9", fourth), Admission::NewlyBlocked);
+        assert!(limiter.is_blocked("10.0.0.9", fourth));
+        // Other hosts are unaffected
+        assert_eq!(limiter.admit("10.0.0.2", fourth), Admission::Allowed);
+        assert!(!limiter.is_blocked("10.0.0.2", fourth));
+        assert_eq!(
+            limiter.admit("10.0.0.9", fourth + Duration::from_secs(29)),
+            Admission::Blocked
+        );
+        assert_eq!(
+            limiter.admit("10.0.0.9", fourth + Duration::from_secs(30)),
+            Admission::Allowed
+        );
+    }
+
+    #[test]
+    fn test_steady_reconnects_stay_allowed() {
+        let mut limiter = ConnectionLimiter::new(2, Duration::from_secs(30));
+        let t0 = Instant::now();
+        for i in 0..20 {
+            let now = t0 + Duration::from_millis(i * 600);
+            assert_eq!(limiter.admit("10.0.0.9", now), Admission::Allowed);
+        }
+        assert_eq!(
+            ConnectionLimiter::new(0, Duration::ZERO).admit("10.0.0.9", t0),
+            Admission::Allowed
+        );
+    }
+}
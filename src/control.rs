@@ -0,0 +1,223 @@
+//! Local HTTP control API for toggling streams at runtime.
+//!
+//! - `GET /health` reports whether the Kinect is available, the uptime, the total
+//!   QoS events and the sensor orientation, which is always `null`.
+//! - `GET /streams` lists every stream with its mount path, state, session count,
+//!   frame count and the User-Agents of its clients.
+//! - `GET /clients` lists the connected RTSP clients with address, User-Agent, path and
+//!   RTP transport.
+//! - `GET /logs` returns the last log lines as plain text.
+//! - `GET /color/camera-settings` reports the color camera's exposure, frame interval,
+//!   gain and gamma, all `null` since the capture doesn't receive them, and that they
+//!   can't be changed.
+//! - `GET /depth/distance?x=<x>&y=<y>` returns the depth in millimeters around a pixel
+//!   of the latest depth frame, the frame center by default, or 503 if there's none.
+//! - `POST /stream/<name>/enable` and `POST /stream/<name>/disable` toggle a stream.
+//! - `POST /stream/<name>/restart` restarts the Kinect capture feeding a stream and
+//!   recreates the media of every stream it feeds; the other streams keep playing. It
+//!   answers once the capture runs again, or 500 if its threads didn't stop.
+//! - `PUT /auth/credentials` with `{"current_username": ..., "current_password": ...,
+//!   "username": ..., "password": ...}` replaces the RTSP Basic Auth credentials if
+//!   the current ones match; sessions already playing aren't affected.
+//! - `GET /location` returns the current location fix, `null` if there's none, and
+//!   `POST /location` with `{"lat": ..., "lon": ..., "alt": ...}` reports one
+//!   (`alt` optional) when `--embed-location` is on.
+//! - `POST /config/reload` applies the `--encoder-config` file now and returns the
+//!   applied settings, or 400 with the reason the file was rejected.
+
+use std::sync::Arc;
+
+use crate::depth::{DEPTH_HEIGHT, DEPTH_WIDTH, DISTANCE_WINDOW, distance_at};
+use crate::encoder_config::EncoderConfigFile;
+use crate::http::{self, Response};
+use crate::location::{self, Location};
+use crate::log_tail;
+use crate::rtsp_publisher::{CaptureRestartFailed, RtspPublisher};
+
+/// Port the `status` subcommand queries when `--control-port` isn't given.
+pub const DEFAULT_PORT: u16 = 8080;
+
+/// Serves the control API on `127.0.0.1:port` until the process exits. It isn't
+/// authenticated, so it's only reachable from the local host.
+pub async fn serve(
+    port: u16,
+    rtsp: Arc<RtspPublisher>,
+    encoder_config: Option<Arc<EncoderConfigFile>>,
+) -> anyhow::Result<()> {
+    http::serve(
+        "Control API",
+        "127.0.0.1",
+        port,
+        move |method, path, body| handle(&rtsp, encoder_config.as_deref(), method, path, body),
+    )
+    .await
+}
+
+fn handle(
+    rtsp: &RtspPublisher,
+    encoder_config: Option<&EncoderConfigFile>,
+    method: &str,
+    path: &str,
+    body: &str,
+) -> Response {
+    let (path, query) = path.split_once('?').unwrap_or((path, ""));
+    match (method, path) {
+        ("GET", "/health") => {
+            return Response::json(&serde_json::json!({
+                "device_available": rtsp.is_device_available(),
+                "uptime_secs": rtsp.uptime().as_secs(),
+                "qos_events": rtsp.qos_events().iter().map(|(_, n)| n).sum::<u64>(),
+                // The Kinect V2 has no tilt motor and the floor clip plane comes with
+                // body frames, which aren't read; report them unavailable, not guessed
+                "floor_clip_plane": null,
+                "tilt_degrees": null,
+            }));
+        }
+        ("GET", "/streams") => return Response::json(&rtsp.stream_statuses()),
+        ("GET", "/clients") => return Response::json(&rtsp.clients()),
+        ("GET", "/depth/distance") => return depth_distance(rtsp, query),
+        ("GET", "/color/camera-settings") => {
+            return Response::json(&serde_json::json!({
+                // The SDK reports these per color frame, but the kinect_v2 color
+                // capture doesn't pass them on; report them unavailable, not guessed
+                "exposure_time_us": null,
+                "frame_interval_us": null,
+                "gain": null,
+                "gamma": null,
+                // The SDK only reads them, the camera adjusts them automatically
+                "writable": false,
+            }));
+        }
+        ("GET", "/logs") => {
+            let mut body = log_tail::lines().join("\n");
+            body.push('\n');
+            return Response::ok("text/plain", body);
+        }
+        ("PUT", "/auth/credentials") => return set_auth_credentials(rtsp, body),
+        ("GET", "/location") => return Response::json(&location::current()),
+        ("POST", "/location") => return set_location(body),
+        ("POST", "/config/reload") => {
+            let Some(file) = encoder_config else {
+                return Response::error("404 Not Found", "No --encoder-config file to reload");
+            };
+            return match file.reload() {
+                Ok(config) => Response::json(&config),
+                Err(e) => Response::error("400 Bad Request", &format!("{e:#}")),
+            };
+        }
+        _ => {}
+    }
+
+    let Some((name, action)) = path
+        .strip_prefix("/stream/")
+        .and_then(|rest| rest.split_once('/'))
+    else {
+        return Response::not_found();
+    };
+    let result = match (method, action) {
+        ("POST", "enable") => rtsp.set_stream_enabled(name, true),
+        ("POST", "disable") => rtsp.set_stream_enabled(name, false),
+        ("POST", "restart") => rtsp.restart_stream(name),
+        _ => return Response::not_found(),
+    };
+    match result {
+        Ok(()) => Response::json(&rtsp.stream_statuses()),
+        Err(e) if e.is::<CaptureRestartFailed>() => {
+            Response::error("500 Internal Server Error", &e.to_string())
+        }
+        Err(e) => Response::error("404 Not Found", &e.to_string()),
+    }
+}
+
+/// Body of `PUT /auth/credentials`: the current credentials, since the control API
+/// itself isn't authenticated, and the new ones.
+#[derive(serde::Deserialize)]
+struct Credentials {
+    current_username: String,
+    current_password: String,
+    username: String,
+    password: String,
+}
+
+fn set_auth_credentials(rtsp: &RtspPublisher, body: &str) -> Response {
+    if !rtsp.is_auth_enabled() {
+        return Response::error(
+            "409 Conflict",
+            "RTSP auth is disabled; start with --username and --password",
+        );
+    }
+    let credentials: Credentials = match serde_json::from_str(body) {
+        Ok(credentials) => credentials,
+        Err(e) => return Response::error("400 Bad Request", &format!("Invalid credentials: {e}")),
+    };
+    if !rtsp.auth_credentials_match(&credentials.current_username, &credentials.current_password) {
+        return Response::error("403 Forbidden", "The current credentials don't match");
+    }
+    match rtsp.set_auth_credentials(&credentials.username, &credentials.password) {
+        Ok(()) => Response::ok("text/plain", "Credentials updated\n".to_string()),
+        Err(e) => Response::error("400 Bad Request", &format!("Invalid credentials: {e}")),
+    }
+}
+
+fn set_location(body: &str) -> Response {
+    if !location::is_enabled() {
+        return Response::error(
+            "409 Conflict",
+            "Location embedding is disabled; start with --embed-location",
+        );
+    }
+    let fix: Location = match serde_json::from_str(body) {
+        Ok(fix) => fix,
+        Err(e) => return Response::error("400 Bad Request", &format!("Invalid location: {e}")),
+    };
+    if let Err(e) = fix.validate() {
+        return Response::error("400 Bad Request", &format!("Invalid location: {e}"));
+    }
+    location::set(fix, "the control API");
+    Response::json(&fix)
+}
+
+/// Value of `name` in a `key=value&...` query string.
+fn query_param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find_map(|(key, value)| (key == name).then_some(value))
+}
+
+/// `GET /depth/distance`, averaged over the measured pixels of a
+/// `DISTANCE_WINDOW` square; `distance_mm` is `null` if none of them was measured.
+fn depth_distance(rtsp: &RtspPublisher, query: &str) -> Response {
+    let coordinate = |name, default: usize| match query_param(query, name) {
+        None => Ok(default),
+        Some(value) => value
+            .parse::<usize>()
+            .map_err(|_| format!("{name} must be a pixel index, got {value:?}")),
+    };
+    let (x, y) = match (
+        coordinate("x", DEPTH_WIDTH / 2),
+        coordinate("y", DEPTH_HEIGHT / 2),
+    ) {
+        (Ok(x), Ok(y)) => (x, y),
+        (Err(e), _) | (_, Err(e)) => return Response::error("400 Bad Request", &e),
+    };
+
+    let response = rtsp.with_latest_depth(|captured, frame| {
+        let (width, height) = (frame.width as usize, frame.height as usize);
+        if x >= width || y >= height {
+            return Response::error(
+                "400 Bad Request",
+                &format!("({x}, {y}) is outside the {width}x{height} depth frame"),
+            );
+        }
+        Response::json(&serde_json::json!({
+            "x": x,
+            "y": y,
+            "distance_mm": distance_at(&frame.data, width, x, y),
+            "window": DISTANCE_WINDOW,
+            "age_ms": captured.elapsed().as_millis() as u64,
+        }))
+    });
+    response
+        .unwrap_or_else(|| Response::error("503 Service Unavailable", "No depth frame available"))
+}
@@ -0,0 +1,199 @@
+//! `--cpu-governor <percent>`: sheds load when the host can't keep up, e.g. a mini PC
+//! that throttles once it runs hot.
+//!
+//! Every [`CHECK_INTERVAL`] the CPU usage of this process is read, as a share of all
+//! cores. Once it stays above the threshold for `--cpu-governor-secs`, the publish fps
+//! and the video bitrates step down one [`LEVELS`] entry; once it stays
+//! [`RECOVER_MARGIN`] points below the threshold as long, they step back up.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+
+use sysinfo::{ProcessRefreshKind, ProcessesToUpdate, System};
+
+/// How often the CPU usage is read.
+const CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Share of the configured publish fps and bitrates, in percent, of each level.
+const LEVELS: [u32; 4] = [100, 75, 50, 25];
+
+/// Points below the threshold the usage must stay at before a level is restored,
+/// so the governor doesn't flip between two levels.
+const RECOVER_MARGIN: f32 = 15.0;
+
+/// Default of `--cpu-governor-secs`.
+pub const DEFAULT_SUSTAIN_SECS: u64 = 10;
+
+/// Share of the configured publish fps and bitrates currently in effect, shared by
+/// the governor, the publish loops and the bitrate limiter.
+#[derive(Debug)]
+pub struct LoadFactor(AtomicU32);
+
+impl Default for LoadFactor {
+    fn default() -> Self {
+        Self(AtomicU32::new(100))
+    }
+}
+
+impl LoadFactor {
+    /// The share in percent.
+    pub fn percent(&self) -> u32 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// The share as a factor, 1.0 without load shedding.
+    pub fn factor(&self) -> f64 {
+        f64::from(self.percent()) / 100.0
+    }
+
+    /// `fps` scaled by the current share, at least 1.
+    pub fn scale_fps(&self, fps: u32) -> u32 {
+        (fps * self.percent() / 100).max(1)
+    }
+
+    fn set_percent(&self, percent: u32) {
+        self.0.store(percent, Ordering::Relaxed);
+    }
+}
+
+/// Picks the level from the CPU usage of each check.
+#[derive(Debug)]
+pub struct Governor {
+    threshold: f32,
+    /// Checks in a row needed before the level changes.
+    sustain: u32,
+    above: u32,
+    below: u32,
+    level: usize,
+}
+
+impl Governor {
+    /// `threshold` in percent of all cores; the usage must stay past it for `sustain`.
+    pub fn new(threshold: f32, sustain: Duration) -> Self {
+        Self {
+            threshold,
+            sustain: sustain.div_duration_f32(CHECK_INTERVAL).ceil().max(1.0) as u32,
+            above: 0,
+            below: 0,
+            level: 0,
+        }
+    }
+
+    pub fn percent(&self) -> u32 {
+        LEVELS[self.level]
+    }
+
+    /// Feeds the usage of the last check and returns the new share if it changed.
+    pub fn update(&mut self, cpu_percent: f32) -> Option<u32> {
+        if cpu_percent > self.threshold {
+            self.above += 1;
+            self.below = 0;
+        } else if cpu_percent < self.threshold - RECOVER_MARGIN {
+            self.below += 1;
+            self.above = 0;
+        } else {
+            self.above = 0;
+            self.below = 0;
+        }
+        if self.above >= self.sustain && self.level + 1 < LEVELS.len() {
+            self.level += 1;
+        } else if self.below >= self.sustain && self.level > 0 {
+            self.level -= 1;
+        } else {
+            return None;
+        }
+        // The next step needs another sustained stretch
+        self.above = 0;
+        self.below = 0;
+        Some(self.percent())
+    }
+}
+
+/// Reads the CPU usage of this process every [`CHECK_INTERVAL`] and sets `load` from it.
+pub fn spawn(threshold: u8, sustain: Duration, load: Arc<LoadFactor>) -> anyhow::Result<()> {
+    let pid = sysinfo::get_current_pid().map_err(anyhow::Error::msg)?;
+    let cores = std::thread::available_parallelism().map_or(1, |n| n.get()) as f32;
+    std::thread::Builder::new()
+        .name("cpu-governor".into())
+        .spawn(move || {
+            log::info!(
+                "CPU governor: shedding load above {threshold}% CPU for {}s",
+                sustain.as_secs()
+            );
+            let mut governor = Governor::new(f32::from(threshold), sustain);
+            let mut system = System::new();
+            loop {
+                std::thread::sleep(CHECK_INTERVAL);
+                system.refresh_processes_specifics(
+                    ProcessesToUpdate::Some(&[pid]),
+                    true,
+                    ProcessRefreshKind::nothing().with_cpu(),
+                );
+                let Some(usage) = system.process(pid).map(|p| p.cpu_usage() / cores) else {
+                    continue;
+                };
+                let previous = governor.percent();
+                let Some(percent) = governor.update(usage) else {
+                    continue;
+                };
+                load.set_percent(percent);
+                if percent < previous {
+                    log::warn!(
+                        "🔥 CPU at {usage:.0}%, above {threshold}% for {}s: publish fps and \
+                        bitrates lowered to {percent}%",
+                        sustain.as_secs()
+                    );
+                } else {
+                    log::info!(
+                        "CPU at {usage:.0}%, recovered for {}s: publish fps and bitrates \
+                        raised to {percent}%",
+                        sustain.as_secs()
+                    );
+                }
+            }
+        })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_steps_down_under_sustained_load_and_back_up() {
+        let mut governor = Governor::new(80.0, Duration::from_secs(6));
+        // A short spike doesn't count
+        assert_eq!(governor.update(95.0), None);
+        assert_eq!(governor.update(95.0), None);
+        assert_eq!(governor.update(50.0), None);
+        assert_eq!(governor.update(95.0), None);
+        assert_eq!(governor.update(95.0), None);
+        assert_eq!(governor.update(95.0), Some(75));
+        // Each further step needs its own stretch
+        assert_eq!(governor.update(95.0), None);
+        assert_eq!(governor.update(95.0), None);
+        assert_eq!(governor.update(95.0), Some(50));
+        for _ in 0..9 {
+            governor.update(95.0);
+        }
+        assert_eq!(governor.percent(), 25);
+        // Just under the threshold is within the margin: no recovery
+        for _ in 0..5 {
+            assert_eq!(governor.update(75.0), None);
+        }
+        assert_eq!(governor.update(40.0), None);
+        assert_eq!(governor.update(40.0), None);
+        assert_eq!(governor.update(40.0), Some(50));
+    }
+
+    #[test]
+    fn test_scaled_fps_is_at_least_one() {
+        let load = LoadFactor::default();
+        assert_eq!(load.scale_fps(30), 30);
+        load.set_percent(25);
+        assert_eq!(load.scale_fps(30), 7);
+        assert_eq!(load.scale_fps(2), 1);
+        assert_eq!(load.factor(), 0.25);
+    }
+}
@@ -0,0 +1,467 @@
+use std::{
+    sync::Arc,
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use kinect_v2::depth_capture::{DepthFrameCapture, DepthFrameCaptureIter, DepthFrameData};
+
+use crate::affinity::pin_current_thread;
+use crate::frame_queue::{FrameHandoff, FrameSender, frame_queue, inline_sender};
+use crate::priority::lower_current_thread;
+use crate::rtsp_publisher::{CaptureSource, CaptureStop, RtspPublisher};
+use crate::throttle::{PROGRESS_LOG_INTERVAL, STALL_WARN_INTERVAL, Throttle};
+use crate::warmup::Warmup;
+
+/// Width of the Kinect V2 depth frame.
+pub const DEPTH_WIDTH: usize = 512;
+/// Height of the Kinect V2 depth frame.
+pub const DEPTH_HEIGHT: usize = 424;
+
+// Nominal Kinect V2 depth camera intrinsics, in pixels. The SDK reports calibrated
+// per-device values through `ICoordinateMapper::GetDepthCameraIntrinsics`; these
+// reproduce the specified 70x60 degree field of view, and a sensor's deviation from
+// them only skews the preview by a few centimeters.
+
+/// Horizontal focal length: `256 / tan(35°)`.
+const DEPTH_FX: f32 = 365.5;
+/// Vertical focal length; the pixels are square.
+const DEPTH_FY: f32 = 365.5;
+/// Principal point, taken as the center of the 512x424 frame.
+const DEPTH_CX: f32 = DEPTH_WIDTH as f32 / 2.0;
+const DEPTH_CY: f32 = DEPTH_HEIGHT as f32 / 2.0;
+
+/// Farthest distance (millimeters) the Kinect V2 reports; the top of the `gray8` scale.
+const DEPTH_MAX_MM: u32 = 8000;
+
+/// How depth values are written into the BGRA frames of the `/depth` stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DepthEncoding {
+    /// Gray level, 0-8000 mm scaled linearly to 0-255 (about 31 mm per step)
+    Gray8,
+    /// Depth in millimeters split over two channels: R = high byte, G = low byte, B = 0
+    RgbPacked,
+}
+
+impl DepthEncoding {
+    /// Writes `depth` (millimeters, 0 = not measured) into `bgra`, one pixel per value.
+    pub fn encode(self, depth: &[u16], bgra: &mut [u8]) {
+        for (&d, px) in depth.iter().zip(bgra.chunks_exact_mut(4)) {
+            let (b, g, r) = match self {
+                DepthEncoding::Gray8 => {
+                    let v = (d as u32 * 255 / DEPTH_MAX_MM).min(255) as u8;
+                    (v, v, v)
+                }
+                DepthEncoding::RgbPacked => {
+                    let [high, low] = d.to_be_bytes();
+                    (0, low, high)
+                }
+            };
+            px.copy_from_slice(&[b, g, r, 255]);
+        }
+    }
+}
+
+/// Side of the square of pixels `GET /depth/distance` averages over.
+pub const DISTANCE_WINDOW: usize = 5;
+
+/// Mean depth in millimeters of the measured pixels in the `DISTANCE_WINDOW` square
+/// centered on (`x`, `y`), clipped to the frame; `None` if none of them was measured.
+pub fn distance_at(depth: &[u16], width: usize, x: usize, y: usize) -> Option<u16> {
+    if width == 0 {
+        return None;
+    }
+    let height = depth.len() / width;
+    let half = DISTANCE_WINDOW / 2;
+    let (mut sum, mut count) = (0u32, 0u32);
+    for row in y.saturating_sub(half)..(y + half + 1).min(height) {
+        for col in x.saturating_sub(half)..(x + half + 1).min(width) {
+            let d = depth[row * width + col];
+            if d != 0 {
+                sum += u32::from(d);
+                count += 1;
+            }
+        }
+    }
+    (count > 0).then(|| (sum / count) as u16)
+}
+
+/// Caps of the `--shm-depth` output: one [`depth_to_meters`] frame per buffer.
+pub const DEPTH_METERS_CAPS: &str =
+    "application/x-kinect-depth,format=F32LE,width=512,height=424,framerate=30/1";
+
+/// Writes `depth` (millimeters) into `out` as row-major little-endian f32 meters,
+/// NaN where the sensor measured nothing. `out` holds 4 bytes per pixel.
+pub fn depth_to_meters(depth: &[u16], out: &mut [u8]) {
+    for (&d, px) in depth.iter().zip(out.chunks_exact_mut(4)) {
+        let meters = if d == 0 {
+            f32::NAN
+        } else {
+            f32::from(d) / 1000.0
+        };
+        px.copy_from_slice(&meters.to_le_bytes());
+    }
+}
+
+/// The point-cloud preview is rendered at most this often to bound CPU usage.
+pub const POINTCLOUD_FPS: u32 = 10;
+
+/// Horizontal extent (meters, centered on the camera) shown in the top-down view.
+const VIEW_HALF_WIDTH_M: f32 = 2.5;
+/// Distance range (meters) shown in the top-down view.
+const VIEW_NEAR_M: f32 = 0.5;
+const VIEW_FAR_M: f32 = 4.5;
+/// Height range (meters, relative to the camera) mapped onto the color ramp.
+const HEIGHT_MIN_M: f32 = -1.5;
+const HEIGHT_MAX_M: f32 = 1.5;
+
+/// Projects a depth pixel (in millimeters) to camera-space XYZ in meters.
+/// X grows to the right, Y grows upwards and Z points away from the sensor.
+fn depth_to_camera_space(u: usize, v: usize, depth_mm: u16) -> (f32, f32, f32) {
+    let z = depth_mm as f32 / 1000.0;
+    let x = (u as f32 - DEPTH_CX) * z / DEPTH_FX;
+    let y = (DEPTH_CY - v as f32) * z / DEPTH_FY;
+    (x, y, z)
+}
+
+/// Maps a normalized value to a blue → green → red ramp, as BGRA.
+fn ramp_bgra(t: f32) -> [u8; 4] {
+    let t = t.clamp(0.0, 1.0);
+    let (r, g, b) = if t < 0.5 {
+        let k = t * 2.0;
+        (0.0, k, 1.0 - k)
+    } else {
+        let k = (t - 0.5) * 2.0;
+        (k, 1.0 - k, 0.0)
+    };
+    [(b * 255.0) as u8, (g * 255.0) as u8, (r * 255.0) as u8, 255]
+}
+
+/// Renders a top-down orthographic view of the depth frame into `bgra`
+/// (`DEPTH_WIDTH` x `DEPTH_HEIGHT`): the camera sits at the bottom center, distance
+/// grows upwards, and each point is colored by its height.
+fn render_top_down(depth: &[u16], width: usize, bgra: &mut [u8]) {
+    bgra.fill(0);
+    for chunk in bgra.chunks_exact_mut(4) {
+        chunk[3] = 255;
+    }
+    // A frame with corrupt dimensions has no pixel positions to project
+    if width == 0 {
+        return;
+    }
+
+    for (i, &d) in depth.iter().enumerate() {
+        // 0 marks a pixel the sensor couldn't measure: it has no position to plot
+        if d == 0 {
+            continue;
+        }
+        let (x, y, z) = depth_to_camera_space(i % width, i / width, d);
+        if !(VIEW_NEAR_M..VIEW_FAR_M).contains(&z) || x.abs() >= VIEW_HALF_WIDTH_M {
+            continue;
+        }
+
+        let px =
+            ((x + VIEW_HALF_WIDTH_M) / (2.0 * VIEW_HALF_WIDTH_M) * DEPTH_WIDTH as f32) as usize;
+        let py = ((VIEW_FAR_M - z) / (VIEW_FAR_M - VIEW_NEAR_M) * DEPTH_HEIGHT as f32) as usize;
+        let (px, py) = (px.min(DEPTH_WIDTH - 1), py.min(DEPTH_HEIGHT - 1));
+
+        let t = (y - HEIGHT_MIN_M) / (HEIGHT_MAX_M - HEIGHT_MIN_M);
+        let offset = (py * DEPTH_WIDTH + px) * 4;
+        bgra[offset..offset + 4].copy_from_slice(&ramp_bgra(t));
+    }
+}
+
+fn depth_frame_capture(
+    rtsp: Arc<RtspPublisher>,
+    warmup_frames: u32,
+    stop: &CaptureStop,
+    frames: &FrameSender<(Instant, DepthFrameData)>,
+) -> anyhow::Result<()> {
+    let mut depth_capture: Option<DepthFrameCapture> = None;
+    let mut iter: Option<DepthFrameCaptureIter> = None;
+    let mut warmup = Warmup::new(warmup_frames);
+
+    let mut frame_count = 0;
+    let mut progress_log = Throttle::new(PROGRESS_LOG_INTERVAL);
+    let mut stall_warning = Throttle::new(STALL_WARN_INTERVAL);
+    let always_on = rtsp.is_always_on(CaptureSource::Depth);
+
+    while !rtsp.should_stop(stop) {
+        if !always_on && !rtsp.is_depth_active() {
+            // No depth consumers, release Kinect resources.
+            if iter.is_some() {
+                iter = None;
+                log::info!("Kinect depth capture paused (no active subscribers)");
+            }
+            if depth_capture.take().is_some() {
+                log::debug!("Kinect depth capture resources released");
+            }
+
+            std::thread::sleep(Duration::from_millis(30));
+            continue;
+        }
+
+        if iter.is_none() {
+            if depth_capture.is_none() {
+                log::info!("Kinect depth capture starting...");
+                depth_capture =
+                    Some(DepthFrameCapture::new().context("Failed to create depth capture")?);
+            }
+
+            if let Some(capture) = depth_capture.as_ref() {
+                iter = Some(
+                    capture
+                        .iter()
+                        .context("Failed to create depth capture iterator")?,
+                );
+                warmup.restart();
+                stall_warning.reset();
+            } else {
+                std::thread::sleep(Duration::from_millis(30));
+                continue;
+            }
+        }
+
+        if let Some(iter) = &mut iter {
+            match iter.next() {
+                Some(Ok(data)) => {
+                    if warmup.discard() {
+                        log::trace!("Discarding depth warmup frame");
+                        continue;
+                    }
+                    frame_count += 1;
+                    stall_warning.reset();
+                    if progress_log.should_log() {
+                        log::debug!(
+                            "✅ Captured depth frame #{}: {}x{}",
+                            frame_count,
+                            data.width,
+                            data.height
+                        );
+                    }
+
+                    if !frames.push((Instant::now(), data)) {
+                        log::debug!("❌ Depth frame buffer full, dropping frame");
+                    }
+                }
+                Some(Err(e)) => {
+                    log::warn!("⚠️ Error capturing depth frame: {e}");
+                }
+                None => {
+                    if stall_warning.should_log() {
+                        log::warn!(
+                            "🔍 No depth frames available from Kinect - is the device connected?"
+                        );
+                    }
+                    std::thread::sleep(Duration::from_millis(5));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The publish step of one captured frame: encodes it for `/depth` and renders the
+/// point cloud, for whichever of them has clients.
+fn depth_frame_publisher(
+    rtsp: Arc<RtspPublisher>,
+    encoding: Option<DepthEncoding>,
+) -> impl FnMut((Instant, DepthFrameData)) + Send + 'static {
+    let render_interval = Duration::from_secs(1) / POINTCLOUD_FPS;
+    let mut last_render: Option<std::time::Instant> = None;
+    let mut bgra_data = vec![0u8; DEPTH_WIDTH * DEPTH_HEIGHT * 4];
+    let mut encoded_data = vec![0u8; DEPTH_WIDTH * DEPTH_HEIGHT * 4];
+    let mut meters_data = vec![0u8; DEPTH_WIDTH * DEPTH_HEIGHT * 4];
+
+    move |(captured, depth_frame)| {
+        if depth_frame.data.is_empty() {
+            return;
+        }
+
+        if let Some(encoding) = encoding
+            && rtsp.is_depth_stream_active()
+        {
+            encoding.encode(&depth_frame.data, &mut encoded_data);
+            rtsp.send_depth_bgra(
+                captured,
+                depth_frame.width,
+                depth_frame.height,
+                &encoded_data,
+            );
+        }
+
+        if rtsp.has_depth_shm() {
+            depth_to_meters(&depth_frame.data, &mut meters_data);
+            rtsp.send_depth_meters(captured, &meters_data);
+        }
+
+        // The point cloud is expensive to render, cap it to POINTCLOUD_FPS.
+        if rtsp.is_pointcloud_active() && last_render.is_none_or(|t| t.elapsed() >= render_interval)
+        {
+            last_render = Some(std::time::Instant::now());
+            render_top_down(
+                &depth_frame.data,
+                depth_frame.width as usize,
+                &mut bgra_data,
+            );
+            rtsp.send_pointcloud_bgra(
+                captured,
+                DEPTH_WIDTH as u32,
+                DEPTH_HEIGHT as u32,
+                &bgra_data,
+            );
+        }
+
+        rtsp.set_latest_depth(captured, depth_frame);
+    }
+}
+
+/// Starts depth capture feeding the point-cloud preview, `GET /depth/distance` and,
+/// when `encoding` is set, the `/depth` and `/combined` streams.
+pub fn spawn_depth_pipeline(
+    rtsp: Arc<RtspPublisher>,
+    encoding: Option<DepthEncoding>,
+    warmup_frames: u32,
+    handoff: FrameHandoff,
+) -> Vec<JoinHandle<()>> {
+    let publish = depth_frame_publisher(rtsp.clone(), encoding);
+    let stop = rtsp.capture_stop(CaptureSource::Depth);
+    let mut threads = Vec::new();
+    let frames_tx = match handoff {
+        FrameHandoff::Inline => inline_sender(publish),
+        FrameHandoff::Queue(queue) => {
+            let (frames_tx, frames_rx) = frame_queue(8, queue.full);
+            let frames_tx = frames_tx.monitor(
+                "depth",
+                rtsp.metrics().depth_queue.clone(),
+                queue.high_water_percent,
+            );
+            let rtsp = rtsp.clone();
+            let stop = stop.clone();
+            // Depth frame publish thread
+            let thread = std::thread::Builder::new()
+                .name("depth-publish".into())
+                .spawn(move || {
+                    pin_current_thread();
+                    lower_current_thread();
+                    let wakeups = &rtsp.metrics().depth_publish_wakeups;
+                    frames_rx.for_each(|| rtsp.should_stop(&stop), wakeups, publish);
+                })
+                .expect("Failed to spawn depth publish thread");
+            threads.push(thread);
+            frames_tx
+        }
+    };
+
+    // Depth frame capture thread
+    let capture = std::thread::Builder::new()
+        .name("depth-capture".into())
+        .spawn(move || {
+            pin_current_thread();
+            lower_current_thread();
+            if let Err(e) = depth_frame_capture(rtsp.clone(), warmup_frames, &stop, &frames_tx) {
+                log::error!("Error capturing depth frames: {e}");
+                rtsp.set_capture_failed(CaptureSource::Depth);
+            }
+        })
+        .expect("Failed to spawn depth capture thread");
+    threads.push(capture);
+
+    threads
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_principal_point_projects_to_optical_axis() {
+        let (x, y, z) = depth_to_camera_space(256, 212, 2000);
+        assert_eq!((x, y, z), (0.0, 0.0, 2.0));
+    }
+
+    #[test]
+    fn test_distance_averages_measured_pixels_in_window() {
+        let mut depth = vec![0u16; DEPTH_WIDTH * DEPTH_HEIGHT];
+        depth[212 * DEPTH_WIDTH + 256] = 1000;
+        depth[214 * DEPTH_WIDTH + 258] = 2000;
+        // Just outside the window
+        depth[212 * DEPTH_WIDTH + 259] = 9000;
+        assert_eq!(distance_at(&depth, DEPTH_WIDTH, 256, 212), Some(1500));
+        assert_eq!(distance_at(&depth, DEPTH_WIDTH, 100, 100), None);
+        // The window is clipped at the frame corner
+        depth[0] = 700;
+        assert_eq!(distance_at(&depth, DEPTH_WIDTH, 0, 0), Some(700));
+        assert_eq!(distance_at(&depth, 0, 0, 0), None);
+    }
+
+    #[test]
+    fn test_depth_to_meters() {
+        let mut out = [0u8; 12];
+        depth_to_meters(&[1500, 0, 8000], &mut out);
+        let meters: Vec<f32> = out
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+            .collect();
+        assert_eq!(meters[0], 1.5);
+        assert!(meters[1].is_nan());
+        assert_eq!(meters[2], 8.0);
+    }
+
+    #[test]
+    fn test_projection_axes() {
+        let (x, y, _) = depth_to_camera_space(400, 100, 1000);
+        assert!(x > 0.0, "pixels right of center map to +X");
+        assert!(y > 0.0, "pixels above center map to +Y");
+    }
+
+    #[test]
+    fn test_render_places_point_in_view() {
+        let mut depth = vec![0u16; DEPTH_WIDTH * DEPTH_HEIGHT];
+        // A single point straight ahead at 2.5 m
+        depth[212 * DEPTH_WIDTH + 256] = 2500;
+        let mut bgra = vec![0u8; DEPTH_WIDTH * DEPTH_HEIGHT * 4];
+        render_top_down(&depth, DEPTH_WIDTH, &mut bgra);
+
+        let lit: Vec<usize> = bgra
+            .chunks_exact(4)
+            .enumerate()
+            .filter(|(_, px)| px[..3] != [0, 0, 0])
+            .map(|(i, _)| i)
+            .collect();
+        assert_eq!(lit.len(), 1);
+        let (px, py) = (lit[0] % DEPTH_WIDTH, lit[0] / DEPTH_WIDTH);
+        assert_eq!(px, DEPTH_WIDTH / 2);
+        assert_eq!(py, DEPTH_HEIGHT / 2);
+    }
+
+    #[test]
+    fn test_depth_encodings() {
+        let depth = [0u16, 1000, 8000, 0x1234];
+        let mut bgra = [0u8; 16];
+
+        DepthEncoding::Gray8.encode(&depth, &mut bgra);
+        assert_eq!(bgra[..4], [0, 0, 0, 255]);
+        assert_eq!(bgra[4..8], [31, 31, 31, 255]);
+        assert_eq!(bgra[8..12], [255, 255, 255, 255]);
+        assert_eq!(bgra[12..], [148, 148, 148, 255]);
+
+        DepthEncoding::RgbPacked.encode(&depth, &mut bgra);
+        assert_eq!(bgra[4..8], [0, 0xe8, 0x03, 255]);
+        assert_eq!(bgra[12..], [0, 0x34, 0x12, 255]);
+    }
+
+    #[test]
+    fn test_out_of_range_points_are_skipped() {
+        let depth = vec![8000u16; DEPTH_WIDTH * DEPTH_HEIGHT];
+        let mut bgra = vec![0u8; DEPTH_WIDTH * DEPTH_HEIGHT * 4];
+        render_top_down(&depth, DEPTH_WIDTH, &mut bgra);
+        assert!(bgra.chunks_exact(4).all(|px| px == [0, 0, 0, 255]));
+
+        // A zero width leaves the view empty instead of dividing by zero
+        render_top_down(&[1500; 16], 0, &mut bgra);
+        assert!(bgra.chunks_exact(4).all(|px| px == [0, 0, 0, 255]));
+    }
+}
@@ -0,0 +1,93 @@
+//! `diagnose` subcommand: collects what a bug report needs into one text file, i.e.
+//! the system, GStreamer and its key elements, the effective configuration, the
+//! self-test, and the state and log tail of an instance running on this host.
+
+use std::fmt::Write as _;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use gstreamer as gst;
+
+use crate::color::ColorCaptureFormat;
+use crate::encoder::VideoEncoder;
+use crate::{http, log_tail, rtsp_publisher, selftest};
+
+/// How long each request to a running instance may take.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+fn section(out: &mut String, title: &str, body: &str) {
+    let _ = writeln!(out, "== {title} ==");
+    out.push_str(body.trim_end());
+    out.push_str("\n\n");
+}
+
+fn system_info() -> String {
+    let os_version = std::process::Command::new("cmd")
+        .args(["/C", "ver"])
+        .output()
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+        .unwrap_or_else(|e| format!("unknown ({e})"));
+    let cpu = std::env::var("PROCESSOR_IDENTIFIER").unwrap_or_else(|_| "unknown".into());
+    let threads = std::thread::available_parallelism().map_or(0, |n| n.get());
+    format!(
+        "OS: {} {} ({os_version})\nCPU: {cpu}, {threads} logical cores",
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    )
+}
+
+fn gstreamer_info() -> String {
+    match gst::init() {
+        Ok(()) => rtsp_publisher::gst_version_lines().join("\n"),
+        Err(e) => format!("GStreamer failed to initialize: {e}"),
+    }
+}
+
+/// State of the instance whose control API listens on `port`, if one does.
+fn running_instance(port: u16) -> String {
+    let mut out = String::new();
+    for path in ["/health", "/streams", "/logs"] {
+        let body = http::get("127.0.0.1", port, path, REQUEST_TIMEOUT)
+            .unwrap_or_else(|e| format!("unreachable: {e:#}"));
+        let _ = writeln!(out, "GET {path}\n{}\n", body.trim_end());
+    }
+    out
+}
+
+/// Writes the report to `output`. `config` is the redacted `--print-config` JSON
+/// and `control_port` the control API port of an instance that may be running.
+pub fn run(
+    output: &Path,
+    config: &str,
+    encoder: VideoEncoder,
+    color_format: ColorCaptureFormat,
+    control_port: u16,
+) -> anyhow::Result<()> {
+    let mut out = String::new();
+    let created = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let _ = writeln!(
+        out,
+        "kinect-rtsp {} diagnostic report, created at unix time {created}\n",
+        env!("CARGO_PKG_VERSION")
+    );
+    section(&mut out, "System", &system_info());
+    section(&mut out, "GStreamer", &gstreamer_info());
+    section(&mut out, "Effective configuration", config);
+
+    log::info!("Running the self-test...");
+    let (report, _) = selftest::report(encoder, color_format);
+    section(&mut out, "Self-test", &report);
+    section(
+        &mut out,
+        &format!("Running instance (control API on port {control_port})"),
+        &running_instance(control_port),
+    );
+    section(&mut out, "Log of this run", &log_tail::lines().join("\n"));
+
+    std::fs::write(output, out).with_context(|| format!("Failed to write {}", output.display()))?;
+    println!("Diagnostic report written to {}", output.display());
+    Ok(())
+}
@@ -0,0 +1,696 @@
+use anyhow::Context;
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use gstreamer_rtsp_server as rtsp;
+use gstreamer_rtsp_server::prelude::*;
+
+/// Software H.264 encoder used by the default video branch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum VideoEncoder {
+    /// `openh264enc` (gst-plugins-bad), always available in the official runtime
+    #[default]
+    Openh264,
+    /// `x264enc` (gst-plugins-ugly), better quality at the same bitrate
+    X264,
+}
+
+impl VideoEncoder {
+    pub fn element_name(self) -> &'static str {
+        match self {
+            VideoEncoder::Openh264 => "openh264enc",
+            VideoEncoder::X264 => "x264enc",
+        }
+    }
+
+    /// Value of the encoder's `bitrate` property for `bitrate` bits/s.
+    pub fn bitrate_property_value(self, bitrate: u32) -> u32 {
+        match self {
+            VideoEncoder::Openh264 => bitrate,
+            // x264enc takes kbit/s
+            VideoEncoder::X264 => bitrate / 1000,
+        }
+    }
+
+    /// Maps our bitrate (bits/s), GOP size, preset and B-frames onto the encoder's
+    /// own properties. `b_frames` is expected to be supported, see [`effective_b_frames`].
+    pub fn encoder_fragment(
+        self,
+        video_bitrate: u32,
+        gop_size: u32,
+        preset: EncoderPreset,
+        b_frames: BFrames,
+    ) -> String {
+        match self {
+            VideoEncoder::Openh264 => format!(
+                "openh264enc bitrate={video_bitrate} gop-size={gop_size} complexity={}",
+                preset.openh264_complexity()
+            ),
+            // x264enc takes kbit/s. tune=zerolatency turns B-frames and the lookahead
+            // off; an explicit count turns the B-frames back on without the lookahead.
+            // Only `auto` leaves both to the preset
+            VideoEncoder::X264 => {
+                let tuning = match b_frames {
+                    BFrames::Count(0) => "tune=zerolatency".to_string(),
+                    BFrames::Count(n) => format!("tune=zerolatency bframes={n}"),
+                    BFrames::Auto => String::new(),
+                };
+                format!(
+                    "x264enc bitrate={} key-int-max={gop_size} speed-preset={} {tuning}",
+                    video_bitrate / 1000,
+                    preset.x264_speed_preset()
+                )
+                .trim_end()
+                .to_string()
+            }
+        }
+    }
+
+    /// Whether the encoder can code B-frames.
+    fn supports_b_frames(self) -> bool {
+        match self {
+            // openh264 only encodes the Constrained Baseline profile
+            VideoEncoder::Openh264 => false,
+            VideoEncoder::X264 => true,
+        }
+    }
+
+    /// GStreamer plugin set that ships the encoder element.
+    fn plugin_set(self) -> &'static str {
+        match self {
+            VideoEncoder::Openh264 => "gst-plugins-bad",
+            VideoEncoder::X264 => "gst-plugins-ugly",
+        }
+    }
+}
+
+/// Software encoders in order of preference when `--software-encoder` isn't given.
+const VIDEO_ENCODERS: [VideoEncoder; 2] = [VideoEncoder::Openh264, VideoEncoder::X264];
+
+/// Picks the software encoder: `requested` as given (its element is checked at
+/// startup like the others), otherwise the first of [`VIDEO_ENCODERS`] that is
+/// installed. Fails, listing every encoder that would do, when none is.
+pub fn select_video_encoder(requested: Option<VideoEncoder>) -> anyhow::Result<VideoEncoder> {
+    if let Some(encoder) = requested {
+        return Ok(encoder);
+    }
+    gst::init()?;
+    let preferred = VIDEO_ENCODERS[0];
+    match VIDEO_ENCODERS
+        .into_iter()
+        .find(|e| gst::ElementFactory::find(e.element_name()).is_some())
+    {
+        Some(encoder) => {
+            if encoder != preferred {
+                log::warn!(
+                    "⚠️ Video encoder '{}' not available, falling back to '{}'",
+                    preferred.element_name(),
+                    encoder.element_name()
+                );
+            }
+            log::info!("✅ Using video encoder: {}", encoder.element_name());
+            Ok(encoder)
+        }
+        None => {
+            let candidates: Vec<String> = VIDEO_ENCODERS
+                .iter()
+                .map(|e| format!("{} ({})", e.element_name(), e.plugin_set()))
+                .collect();
+            Err(anyhow::anyhow!(
+                "No H.264 software encoder found, install one of: {}",
+                candidates.join(", ")
+            ))
+        }
+    }
+}
+
+/// Encoder quality/CPU trade-off, mapped onto each encoder's own knob.
+///
+/// | preset   | openh264enc `complexity` | x264enc `speed-preset` |
+/// |----------|--------------------------|------------------------|
+/// | `low`    | `low`                    | `ultrafast`            |
+/// | `medium` | `medium`                 | `veryfast`             |
+/// | `high`   | `high`                   | `medium`               |
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Default,
+    clap::ValueEnum,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum EncoderPreset {
+    /// Least CPU, lowest quality at a given bitrate
+    #[default]
+    Low,
+    Medium,
+    /// Most CPU, best quality at a given bitrate
+    High,
+}
+
+impl EncoderPreset {
+    pub fn openh264_complexity(self) -> &'static str {
+        match self {
+            EncoderPreset::Low => "low",
+            EncoderPreset::Medium => "medium",
+            EncoderPreset::High => "high",
+        }
+    }
+
+    pub fn x264_speed_preset(self) -> &'static str {
+        match self {
+            EncoderPreset::Low => "ultrafast",
+            EncoderPreset::Medium => "veryfast",
+            EncoderPreset::High => "medium",
+        }
+    }
+}
+
+/// openh264enc `rate-control` mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RateControl {
+    /// Quality first, the bitrate may overshoot
+    Quality,
+    /// Hold the target bitrate
+    Bitrate,
+    /// Follow the target bitrate through the encoder's buffer
+    Buffer,
+    /// No rate control
+    Off,
+}
+
+impl RateControl {
+    pub fn nick(self) -> &'static str {
+        match self {
+            RateControl::Quality => "quality",
+            RateControl::Bitrate => "bitrate",
+            RateControl::Buffer => "buffer",
+            RateControl::Off => "off",
+        }
+    }
+}
+
+/// Strength of the optional color denoiser.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DenoiseStrength {
+    /// 5-tap median on luma only, cheapest
+    Light,
+    /// 5-tap median on luma and chroma
+    Medium,
+    /// 9-tap median on luma and chroma, most CPU
+    Strong,
+}
+
+impl DenoiseStrength {
+    /// `videomedian` filter working on the I420 frames before the encoder.
+    pub fn filter_fragment(self) -> &'static str {
+        match self {
+            DenoiseStrength::Light => "videomedian filtersize=5 lum-only=true",
+            DenoiseStrength::Medium => "videomedian filtersize=5 lum-only=false",
+            DenoiseStrength::Strong => "videomedian filtersize=9 lum-only=false",
+        }
+    }
+}
+
+/// Interpolation of every `videoscale` (`--scale-method`), by rising CPU cost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ScaleMethod {
+    /// Nearest neighbour, cheapest but blocky
+    Nearest,
+    /// Bilinear, videoscale's own default
+    #[default]
+    Bilinear,
+    /// Lanczos, sharpest downscales at the highest CPU cost
+    Lanczos,
+}
+
+impl ScaleMethod {
+    /// `videoscale` to `width`x`height` with this interpolation.
+    pub fn filter_fragment(self, width: u32, height: u32) -> String {
+        let method = match self {
+            ScaleMethod::Nearest => "nearest-neighbour",
+            ScaleMethod::Bilinear => "bilinear",
+            ScaleMethod::Lanczos => "lanczos",
+        };
+        format!("videoscale method={method} ! video/x-raw,width={width},height={height}")
+    }
+}
+
+/// Largest `--color-sharpen` amount; beyond it edges ring and noise is amplified.
+pub const MAX_COLOR_SHARPEN: f32 = 2.0;
+
+/// Parses `--color-sharpen`, a non-negative amount; see [`sharpen_filter`].
+pub fn parse_color_sharpen(s: &str) -> Result<f32, String> {
+    let amount: f32 = s
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid amount '{s}': expected a number like 0.5"))?;
+    if !(amount.is_finite() && amount >= 0.0) {
+        return Err(format!("invalid amount {amount}: must be 0 or more"));
+    }
+    Ok(amount)
+}
+
+/// Unsharp mask on the color frames before the encoder: `gaussianblur` with a
+/// negative sigma subtracts the blurred image. `amount` is clamped to
+/// [0, [`MAX_COLOR_SHARPEN`]]. gaussianblur only takes AYUV, hence the conversions.
+pub fn sharpen_filter(amount: f32) -> String {
+    let sigma = amount.clamp(0.0, MAX_COLOR_SHARPEN);
+    format!("videoconvert ! gaussianblur sigma=-{sigma:.2} ! videoconvert")
+}
+
+/// Drops the chroma of the I420 frames before the encoder; the encoder then spends
+/// next to nothing on the flat chroma planes.
+pub const GRAYSCALE_FILTER: &str = "videobalance saturation=0";
+
+/// Region of interest cut out of the 1920x1080 color frame before encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct ColorCrop {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl ColorCrop {
+    /// Parses `x,y,w,h`. Values must be even (I420 chroma is subsampled 2x2) and the
+    /// region must lie within the 1920x1080 frame.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let values = s
+            .split(',')
+            .map(|v| v.trim().parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("invalid crop '{s}': {e}"))?;
+        let [x, y, width, height] = values[..] else {
+            return Err(format!("invalid crop '{s}': expected x,y,w,h"));
+        };
+        if width == 0 || height == 0 {
+            return Err(format!("invalid crop '{s}': empty region"));
+        }
+        let exceeds =
+            |start: u32, len: u32, max: u32| start.checked_add(len).is_none_or(|end| end > max);
+        if exceeds(x, width, 1920) || exceeds(y, height, 1080) {
+            return Err(format!("invalid crop '{s}': region exceeds 1920x1080"));
+        }
+        if [x, y, width, height].iter().any(|v| !v.is_multiple_of(2)) {
+            return Err(format!("invalid crop '{s}': values must be even"));
+        }
+        Ok(Self {
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+
+    /// `videocrop` filter for the I420 frames before the encoder.
+    pub fn filter_fragment(&self) -> String {
+        format!(
+            "videocrop left={} top={} right={} bottom={}",
+            self.x,
+            self.y,
+            1920 - self.x - self.width,
+            1080 - self.y - self.height
+        )
+    }
+}
+
+/// Most B-frames x264 puts between two reference frames.
+pub const MAX_B_FRAMES: u32 = 16;
+
+/// B-frames between the reference frames of the encoded video (`--b-frames`). They
+/// save bits, but a decoder has to wait for the next reference frame to show them,
+/// and some low-latency decoders and WebRTC bridges don't handle them at all.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BFrames {
+    Count(u32),
+    /// As many as the encoder preset picks.
+    Auto,
+}
+
+impl Default for BFrames {
+    fn default() -> Self {
+        BFrames::Count(0)
+    }
+}
+
+impl BFrames {
+    /// Parses `auto` or a count up to [`MAX_B_FRAMES`].
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("auto") {
+            return Ok(BFrames::Auto);
+        }
+        match s.parse::<u32>() {
+            Ok(n) if n <= MAX_B_FRAMES => Ok(BFrames::Count(n)),
+            _ => Err(format!(
+                "invalid B-frames '{s}': expected auto or 0 to {MAX_B_FRAMES}"
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for BFrames {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BFrames::Count(n) => write!(f, "{n}"),
+            BFrames::Auto => f.write_str("auto"),
+        }
+    }
+}
+
+impl serde::Serialize for BFrames {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// The B-frames `encoder` codes for `requested`, and why they differ if they do:
+/// openh264enc has no B-frames, and the Constrained Baseline profile forbids them.
+pub fn effective_b_frames(
+    encoder: VideoEncoder,
+    profile: Option<H264Profile>,
+    requested: BFrames,
+) -> (BFrames, Option<String>) {
+    if requested == BFrames::Count(0) {
+        return (requested, None);
+    }
+    let reason = if !encoder.supports_b_frames() {
+        format!("{} doesn't encode B-frames", encoder.element_name())
+    } else if profile == Some(H264Profile::Baseline) {
+        "the baseline H.264 profile has no B-frames".to_string()
+    } else {
+        return (requested, None);
+    };
+    (
+        BFrames::Count(0),
+        Some(format!("--b-frames {requested} ignored: {reason}")),
+    )
+}
+
+/// H.264 profile forced on the encoder output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum H264Profile {
+    /// Constrained Baseline, playable by the most constrained hardware decoders
+    Baseline,
+    Main,
+    High,
+}
+
+impl H264Profile {
+    /// Caps name of the profile, as used in `video/x-h264,profile=...`.
+    pub fn caps_name(self) -> &'static str {
+        match self {
+            H264Profile::Baseline => "constrained-baseline",
+            H264Profile::Main => "main",
+            H264Profile::High => "high",
+        }
+    }
+}
+
+/// AAC profile, only used when the audio encoder is an AAC one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AacProfile {
+    /// Low Complexity, decoded everywhere
+    #[default]
+    Lc,
+    /// HE-AAC v1 (AAC + SBR), needs fdkaacenc
+    He,
+}
+
+#[cfg(feature = "audio")]
+impl AacProfile {
+    /// Caps name of the profile, as used in `audio/mpeg,profile=...`.
+    pub fn caps_name(self) -> &'static str {
+        match self {
+            AacProfile::Lc => "lc",
+            AacProfile::He => "he-aac-v1",
+        }
+    }
+}
+
+/// RTP framing of AAC audio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AacPayload {
+    /// MP4A-LATM (RFC 3016), LOAS/LATM framed
+    #[default]
+    Latm,
+    /// mpeg4-generic (RFC 3640), raw access units as expected by ADTS-minded clients
+    Generic,
+}
+
+#[cfg(feature = "audio")]
+impl AacPayload {
+    pub fn payloader(self) -> &'static str {
+        match self {
+            AacPayload::Latm => "rtpmp4apay",
+            AacPayload::Generic => "rtpmp4gpay",
+        }
+    }
+}
+
+/// Checks that the encoder's source pad template can produce the requested profile.
+pub fn check_encoder_profile(encoder: VideoEncoder, profile: H264Profile) -> anyhow::Result<()> {
+    let name = encoder.element_name();
+    let wanted = gst::Caps::builder("video/x-h264")
+        .field("profile", profile.caps_name())
+        .build();
+    let supported = gst::ElementFactory::find(name).is_some_and(|factory| {
+        factory
+            .static_pad_templates()
+            .iter()
+            .filter(|t| t.direction() == gst::PadDirection::Src)
+            .any(|t| t.caps().can_intersect(&wanted))
+    });
+    if supported {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "Encoder '{name}' does not support H.264 profile '{}'",
+            profile.caps_name()
+        ))
+    }
+}
+
+/// Quantization range of the encoded color stream.
+///
+/// Players that don't read the H.264 VUI (VLC among them) assume `limited`, so a
+/// stream encoded as full range but decoded as limited looks washed out/greyish.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColorRange {
+    /// BT.709, 16-235 luma; what VLC and most players assume by default
+    Limited,
+    /// BT.709, 0-255 luma
+    Full,
+}
+
+impl ColorRange {
+    /// GStreamer colorimetry string (`range:matrix:transfer:primaries`) for the encoder input.
+    pub fn colorimetry(self) -> &'static str {
+        match self {
+            ColorRange::Limited => "bt709",
+            ColorRange::Full => "1:3:5:1",
+        }
+    }
+}
+
+/// Encoder parameters for one video branch.
+#[derive(Debug, Clone, Copy)]
+pub struct EncoderSettings {
+    pub encoder: VideoEncoder,
+    /// Target bitrate in bits/s.
+    pub bitrate: u32,
+    pub gop_size: u32,
+    pub profile: Option<H264Profile>,
+    pub preset: EncoderPreset,
+    /// Colorimetry forced on the encoder input; `None` keeps whatever videoconvert negotiates.
+    pub color_range: Option<ColorRange>,
+    /// Seconds between the SPS/PPS h264parse repeats in the stream, `-1` with every
+    /// IDR frame, `0` only at the start.
+    pub sps_pps_interval: i32,
+    pub b_frames: BFrames,
+}
+
+/// Default of `--sps-pps-interval`.
+pub const DEFAULT_SPS_PPS_INTERVAL: i32 = 1;
+
+/// Name of the default branch's encoder, whose bitrate can be changed while playing.
+pub const ENCODER_NAME: &str = "encoder";
+
+/// Name of the default branch's `h264parse`, where encoded buffers can be probed.
+pub const H264_PARSE_NAME: &str = "parse";
+
+/// Puts a tee between the media's h264parse and what it fed, with `branch` linked
+/// in a chain after the tee's second pad, e.g. for `--tee-record`.
+pub fn tee_after_parse(media: &rtsp::RTSPMedia, branch: &[&gst::Element]) -> anyhow::Result<()> {
+    let bin = media
+        .element()
+        .downcast::<gst::Bin>()
+        .map_err(|_| anyhow::anyhow!("the media element isn't a bin"))?;
+    let parse = bin
+        .by_name(H264_PARSE_NAME)
+        .with_context(|| format!("no '{H264_PARSE_NAME}' element, e.g. a custom pipeline"))?;
+    let parse_src = parse
+        .static_pad("src")
+        .context("h264parse has no src pad")?;
+    let payloader_sink = parse_src.peer().context("h264parse isn't linked")?;
+
+    let tee = gst::ElementFactory::make("tee").build()?;
+    let elements: Vec<&gst::Element> = std::iter::once(&tee)
+        .chain(branch.iter().copied())
+        .collect();
+    bin.add_many(&elements)?;
+    let linked = link_branch(&parse, &payloader_sink, &elements);
+    if linked.is_err() {
+        // Leave the media as it was
+        parse.unlink(&tee);
+        let _ = bin.remove_many(&elements);
+        let _ = parse_src.link(&payloader_sink);
+    }
+    linked
+}
+
+/// Links `parse` to the tee that starts `branch`, and the tee to `payloader_sink`.
+fn link_branch(
+    parse: &gst::Element,
+    payloader_sink: &gst::Pad,
+    branch: &[&gst::Element],
+) -> anyhow::Result<()> {
+    let tee = branch[0];
+    parse
+        .static_pad("src")
+        .context("h264parse has no src pad")?
+        .unlink(payloader_sink)?;
+    parse.link(tee)?;
+    tee.request_pad_simple("src_%u")
+        .context("tee has no src pad")?
+        .link(payloader_sink)?;
+    gst::Element::link_many(branch)?;
+    Ok(())
+}
+
+/// Builds the default H.264 video branch placed between the appsrc and `pay0`.
+/// `filters` are gst-launch fragments inserted, in order, on the I420 frames
+/// right before the encoder.
+///
+/// When a profile is set it's forced with a caps filter after the encoder; h264parse
+/// and rtph264pay then carry the matching `profile-level-id` into the SDP.
+///
+/// When a color range is set videoconvert converts the levels into it, and the
+/// encoder signals it in the VUI (x264enc does; openh264enc leaves the VUI empty,
+/// so players fall back to limited).
+pub fn default_video_branch(settings: &EncoderSettings, filters: &[&str]) -> String {
+    let encoder = settings.encoder.encoder_fragment(
+        settings.bitrate,
+        settings.gop_size,
+        settings.preset,
+        settings.b_frames,
+    );
+    let filters: String = filters.iter().map(|f| format!("! {f} ")).collect();
+    let colorimetry = settings
+        .color_range
+        .map(|r| format!(",colorimetry={}", r.colorimetry()))
+        .unwrap_or_default();
+    let profile = settings
+        .profile
+        .map(|p| format!("! video/x-h264,profile={} ", p.caps_name()))
+        .unwrap_or_default();
+    format!(
+        "queue leaky=downstream max-size-buffers=1 max-size-bytes=0 max-size-time=0 \
+        ! videoconvert ! video/x-raw,format=I420{colorimetry} \
+        {filters}! queue leaky=downstream max-size-buffers=1 max-size-bytes=0 max-size-time=0 \
+        ! {encoder} name={ENCODER_NAME} \
+        {profile}! h264parse name={H264_PARSE_NAME} config-interval={}",
+        settings.sps_pps_interval
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_color_crop_parses_and_validates() {
+        let crop = ColorCrop::parse("100, 200, 640,480").unwrap();
+        assert_eq!(
+            crop.filter_fragment(),
+            "videocrop left=100 top=200 right=1180 bottom=400"
+        );
+        assert!(ColorCrop::parse("0,0,1920,1080").is_ok());
+
+        assert!(ColorCrop::parse("0,0,1920").is_err());
+        assert!(ColorCrop::parse("0,0,0,480").is_err());
+        assert!(ColorCrop::parse("1280,0,1280,720").is_err());
+        assert!(ColorCrop::parse("1,0,640,480").is_err());
+        assert!(ColorCrop::parse("a,0,640,480").is_err());
+        assert!(ColorCrop::parse("4294967295,0,2,2").is_err());
+        assert!(ColorCrop::parse("0,4294967294,2,2").is_err());
+    }
+
+    #[test]
+    fn test_color_sharpen_is_clamped() {
+        assert_eq!(parse_color_sharpen(" 0.5 "), Ok(0.5));
+        assert!(parse_color_sharpen("-1").is_err());
+        assert!(parse_color_sharpen("NaN").is_err());
+        assert_eq!(
+            sharpen_filter(0.5),
+            "videoconvert ! gaussianblur sigma=-0.50 ! videoconvert"
+        );
+        assert!(sharpen_filter(10.0).contains("sigma=-2.00 "));
+    }
+
+    #[test]
+    fn test_b_frames_map_onto_the_encoder() {
+        assert_eq!(BFrames::parse("0"), Ok(BFrames::default()));
+        assert_eq!(BFrames::parse(" Auto "), Ok(BFrames::Auto));
+        assert_eq!(BFrames::parse("3"), Ok(BFrames::Count(3)));
+        assert!(BFrames::parse("17").is_err());
+        assert!(BFrames::parse("-1").is_err());
+
+        let x264 = |b_frames| {
+            VideoEncoder::X264.encoder_fragment(2_000_000, 30, EncoderPreset::Low, b_frames)
+        };
+        assert!(x264(BFrames::Count(0)).ends_with(" tune=zerolatency"));
+        assert!(x264(BFrames::Count(2)).ends_with(" tune=zerolatency bframes=2"));
+        assert!(x264(BFrames::Auto).ends_with(" speed-preset=ultrafast"));
+
+        assert_eq!(
+            effective_b_frames(VideoEncoder::X264, None, BFrames::Auto),
+            (BFrames::Auto, None)
+        );
+        let (b_frames, warning) = effective_b_frames(VideoEncoder::Openh264, None, BFrames::Auto);
+        assert_eq!(b_frames, BFrames::Count(0));
+        assert!(warning.unwrap().contains("openh264enc"));
+        let (b_frames, warning) = effective_b_frames(
+            VideoEncoder::X264,
+            Some(H264Profile::Baseline),
+            BFrames::Count(2),
+        );
+        assert_eq!(b_frames, BFrames::Count(0));
+        assert!(warning.unwrap().contains("baseline"));
+    }
+
+    #[test]
+    fn test_scale_method_sets_videoscale_method() {
+        assert_eq!(
+            ScaleMethod::default().filter_fragment(960, 540),
+            "videoscale method=bilinear ! video/x-raw,width=960,height=540"
+        );
+        assert!(
+            ScaleMethod::Nearest
+                .filter_fragment(1024, 848)
+                .starts_with("videoscale method=nearest-neighbour ")
+        );
+    }
+}
@@ -0,0 +1,181 @@
+//! Color encoder settings read from a JSON file and re-applied whenever the file
+//! changes, so the encoder can be tuned while watching the stream. `POST
+//! /config/reload` of the control API applies it right away instead.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Context;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::encoder::{EncoderPreset, RateControl, VideoEncoder};
+use crate::events::Event;
+use crate::rtsp_publisher::RtspPublisher;
+
+/// How often the file's modification time is checked.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Encoder settings of the color stream. Absent fields keep the startup values.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VideoEncoderConfig {
+    /// Target bitrate in bits/s.
+    pub bitrate: Option<u32>,
+    /// openh264enc `complexity` / x264enc `speed-preset`, as with `--encoder-preset`.
+    pub preset: Option<EncoderPreset>,
+    /// openh264enc `rate-control`; ignored by x264enc.
+    pub rate_control: Option<RateControl>,
+}
+
+impl VideoEncoderConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.bitrate == Some(0) {
+            anyhow::bail!("bitrate must be above 0");
+        }
+        Ok(())
+    }
+
+    /// Encoder element properties (name and value as gst-launch would parse it).
+    pub fn properties(&self, encoder: VideoEncoder) -> Vec<(&'static str, String)> {
+        let mut properties = Vec::new();
+        if let Some(bitrate) = self.bitrate {
+            let value = encoder.bitrate_property_value(bitrate);
+            properties.push(("bitrate", value.to_string()));
+        }
+        match (encoder, self.preset) {
+            (VideoEncoder::Openh264, Some(preset)) => {
+                properties.push(("complexity", preset.openh264_complexity().to_string()))
+            }
+            (VideoEncoder::X264, Some(preset)) => {
+                properties.push(("speed-preset", preset.x264_speed_preset().to_string()))
+            }
+            (_, None) => {}
+        }
+        if let Some(rate_control) = self.rate_control {
+            if encoder == VideoEncoder::Openh264 {
+                properties.push(("rate-control", rate_control.nick().to_string()));
+            } else {
+                log::warn!("rate_control only applies to openh264enc, ignoring it");
+            }
+        }
+        properties
+    }
+}
+
+fn load(path: &Path) -> anyhow::Result<VideoEncoderConfig> {
+    let text = fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    let config: VideoEncoderConfig =
+        serde_json::from_str(&text).with_context(|| format!("parsing {}", path.display()))?;
+    config.validate()?;
+    Ok(config)
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// The `--encoder-config` file and the color encoder it's applied to, shared by the
+/// watcher thread and the control API.
+pub struct EncoderConfigFile {
+    path: PathBuf,
+    rtsp: Arc<RtspPublisher>,
+    encoder: VideoEncoder,
+    /// Modification time at the last load; `None` until the first one, so a missing
+    /// file is reported once too.
+    last_modified: Mutex<Option<Option<SystemTime>>>,
+}
+
+impl EncoderConfigFile {
+    pub fn new(path: PathBuf, rtsp: Arc<RtspPublisher>, encoder: VideoEncoder) -> Arc<Self> {
+        Arc::new(Self {
+            path,
+            rtsp,
+            encoder,
+            last_modified: Mutex::new(None),
+        })
+    }
+
+    /// Loads the file and applies it to the color encoder, returning the applied
+    /// settings. On error the current settings stay in effect.
+    pub fn reload(&self) -> anyhow::Result<VideoEncoderConfig> {
+        *self.last_modified.lock() = Some(modified(&self.path));
+        let config = load(&self.path)?;
+        log::info!("Applying encoder config from {}", self.path.display());
+        self.rtsp
+            .set_color_encoder_properties(config.bitrate, config.properties(self.encoder));
+        self.rtsp.events().publish(Event::ConfigReloaded {
+            path: self.path.display().to_string(),
+        });
+        Ok(config)
+    }
+
+    /// Whether the file changed since the last load.
+    fn changed(&self) -> bool {
+        *self.last_modified.lock() != Some(modified(&self.path))
+    }
+}
+
+/// Loads the file now and again whenever its modification time changes, applying
+/// each valid version to the color encoder. An invalid edit is logged and the
+/// previous settings stay in effect.
+pub fn spawn_encoder_config_watcher(file: Arc<EncoderConfigFile>) -> std::thread::JoinHandle<()> {
+    std::thread::Builder::new()
+        .name("encoder-config".into())
+        .spawn(move || {
+            while !file.rtsp.is_stopping() {
+                if file.changed()
+                    && let Err(e) = file.reload()
+                {
+                    log::warn!(
+                        "⚠️ Encoder config {} unusable, keeping the current settings: {e:#}",
+                        file.path.display()
+                    );
+                }
+                std::thread::sleep(POLL_INTERVAL);
+            }
+        })
+        .expect("Failed to spawn encoder config thread")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_properties_follow_the_encoder() {
+        let config: VideoEncoderConfig = serde_json::from_str(
+            r#"{"bitrate": 4000000, "preset": "medium", "rate_control": "buffer"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.properties(VideoEncoder::Openh264),
+            [
+                ("bitrate", "4000000".to_string()),
+                ("complexity", "medium".to_string()),
+                ("rate-control", "buffer".to_string()),
+            ]
+        );
+        assert_eq!(
+            config.properties(VideoEncoder::X264),
+            [
+                ("bitrate", "4000".to_string()),
+                ("speed-preset", "veryfast".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rejects_unknown_fields_and_zero_bitrate() {
+        assert!(serde_json::from_str::<VideoEncoderConfig>(r#"{"bitrat": 1}"#).is_err());
+        let config: VideoEncoderConfig = serde_json::from_str(r#"{"bitrate": 0}"#).unwrap();
+        assert!(config.validate().is_err());
+        assert!(
+            VideoEncoderConfig::default()
+                .properties(VideoEncoder::X264)
+                .is_empty()
+        );
+    }
+}
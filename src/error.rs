@@ -0,0 +1,67 @@
+//! Error returned by the startup path ([`crate::start_kinect_capture`] and
+//! [`crate::rtsp_publisher::RtspPublisher::start`]), so embedding code can match on
+//! the failure class, e.g. retry a missing device but report a busy port.
+//! `main` turns it into an `anyhow::Error` like every other error.
+
+use std::time::Duration;
+
+#[derive(Debug, thiserror::Error)]
+pub enum KinectRtspError {
+    /// No Kinect showed up within the device wait time.
+    #[error(
+        "Kinect device is not available after waiting {:.1}s (see --device-wait-secs)",
+        waited.as_secs_f32()
+    )]
+    DeviceUnavailable { waited: Duration },
+    /// The Kinect runtime couldn't be opened or queried.
+    #[error(transparent)]
+    Device(anyhow::Error),
+    /// A GStreamer element the configuration needs isn't installed.
+    #[error(
+        "Missing GStreamer element '{element}'. Please ensure GStreamer and the required plugins are installed correctly and accessible in your system's PATH."
+    )]
+    MissingElement { element: String },
+    #[error("GStreamer failed to initialize: {0}")]
+    GstInit(#[from] glib::Error),
+    /// The RTSP server couldn't listen, usually because the port is taken.
+    #[error("Failed to start the RTSP server on port {port}, is it already in use?")]
+    PortInUse { port: u16 },
+    /// An option value that can't be used, e.g. an unsupported capture format.
+    #[error("{0}")]
+    InvalidConfig(String),
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+impl From<anyhow::Error> for KinectRtspError {
+    fn from(e: anyhow::Error) -> Self {
+        // An error of ours passed up through an anyhow helper keeps its class; once
+        // wrapped in context it stays `Other`, so the added message isn't lost
+        if e.chain().count() == 1 && e.is::<KinectRtspError>() {
+            return e.downcast().expect("checked by is()");
+        }
+        KinectRtspError::Other(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_class_survives_anyhow_unless_wrapped_in_context() {
+        let passed_up = anyhow::Error::from(KinectRtspError::PortInUse { port: 8554 });
+        assert!(matches!(
+            KinectRtspError::from(passed_up),
+            KinectRtspError::PortInUse { port: 8554 }
+        ));
+
+        let wrapped = Err::<(), _>(KinectRtspError::PortInUse { port: 8554 })
+            .context("Starting the server")
+            .unwrap_err();
+        let err = KinectRtspError::from(wrapped);
+        assert!(matches!(err, KinectRtspError::Other(_)));
+        assert!(format!("{:#}", anyhow::Error::from(err)).starts_with("Starting the server: "));
+    }
+}
@@ -0,0 +1,104 @@
+//! Bounded broadcast bus for server events: producers (factory callbacks, the
+//! watchdog, the control API) publish, any number of consumers subscribe.
+
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// Events kept per subscriber; a slower subscriber loses the oldest ones.
+const EVENT_CAPACITY: usize = 256;
+
+/// Something that happened in the server. Serialized as `{"event": "...", ...}`.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A client set up `mount`; `clients` counts the sessions after it joined.
+    ClientConnected { mount: String, clients: usize },
+    /// A client's session on `mount` ended; `clients` counts the remaining ones.
+    ClientDisconnected { mount: String, clients: usize },
+    /// A stream was enabled or disabled through the control API.
+    StreamToggled { stream: String, enabled: bool },
+    /// The Kinect appeared or went away.
+    DeviceAvailability { available: bool },
+    /// The watchdog tore down the stalled media of `mount`.
+    MediaRestarted { mount: String },
+    /// The capture feeding `stream` and its other streams was restarted through the
+    /// control API.
+    CaptureRestarted { stream: String },
+    /// A config file was re-read and applied.
+    ConfigReloaded { path: String },
+}
+
+/// Cheap to clone; every clone publishes to the same subscribers.
+#[derive(Clone)]
+pub struct EventBus {
+    tx: broadcast::Sender<Event>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self {
+            tx: broadcast::channel(EVENT_CAPACITY).0,
+        }
+    }
+}
+
+impl EventBus {
+    /// Never blocks, so it's safe to call from GStreamer callbacks and capture threads.
+    /// Events published while nobody is subscribed are dropped.
+    pub fn publish(&self, event: Event) {
+        let _ = self.tx.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.tx.subscribe()
+    }
+}
+
+/// Consumer that writes every event to the debug log as JSON.
+pub async fn log_events(mut rx: broadcast::Receiver<Event>) {
+    loop {
+        match rx.recv().await {
+            Ok(event) => match serde_json::to_string(&event) {
+                Ok(json) => log::debug!("Event: {json}"),
+                Err(e) => log::warn!("Failed to serialize event {event:?}: {e}"),
+            },
+            Err(RecvError::Lagged(missed)) => {
+                log::warn!("Event log fell behind, {missed} events missed")
+            }
+            Err(RecvError::Closed) => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subscribers_see_events_published_after_they_joined() {
+        let bus = EventBus::default();
+        bus.publish(Event::DeviceAvailability { available: true });
+
+        let mut rx = bus.subscribe();
+        bus.clone().publish(Event::ClientConnected {
+            mount: "/color".into(),
+            clients: 1,
+        });
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            Event::ClientConnected {
+                mount: "/color".into(),
+                clients: 1
+            }
+        );
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_events_serialize_with_a_tag() {
+        let json = serde_json::to_string(&Event::MediaRestarted {
+            mount: "/infrared".into(),
+        })
+        .unwrap();
+        assert_eq!(json, r#"{"event":"media_restarted","mount":"/infrared"}"#);
+    }
+}
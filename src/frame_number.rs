@@ -0,0 +1,150 @@
+//! `--overlay-frame-number`: a frame number shared by the color and infrared streams,
+//! burned into the bottom-left corner of their frames so they can be aligned in post.
+//!
+//! The two streams come from separate Kinect readers, so a counter per stream would
+//! drift apart with the first dropped frame. The number is instead the count of
+//! Kinect frame periods since the first numbered frame of either stream: frames
+//! captured within half a period of the same tick get the same number.
+
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use crate::frame_queue::KINECT_FPS;
+
+/// Rows of the 3x5 digit glyphs, 3 bits per row.
+pub const DIGITS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b001, 0b001, 0b001],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+/// Numbers frames by their capture time, shared by all the numbered streams.
+#[derive(Debug, Default)]
+pub struct FrameClock {
+    /// Capture time of the first numbered frame, number 0.
+    origin: OnceLock<Instant>,
+}
+
+impl FrameClock {
+    /// Number of the Kinect frame period `captured` falls in; frames captured before
+    /// the first numbered one get 0.
+    pub fn number(&self, captured: Instant) -> u64 {
+        let origin = *self.origin.get_or_init(|| captured);
+        let period = (Duration::from_secs(1) / KINECT_FPS).as_nanos();
+        let elapsed = captured.saturating_duration_since(origin).as_nanos();
+        ((elapsed + period / 2) / period) as u64
+    }
+}
+
+/// A frame's number and the layout [`burn_in`] draws it with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stamp {
+    pub number: u64,
+    pub width: usize,
+    pub height: usize,
+    pub bytes_per_pixel: usize,
+}
+
+impl Stamp {
+    /// Draws the number into `frame`, see [`burn_in`].
+    pub fn burn_in(&self, frame: &mut [u8]) {
+        burn_in(
+            frame,
+            self.width,
+            self.height,
+            self.bytes_per_pixel,
+            self.number,
+        );
+    }
+}
+
+/// Draws `number` white on a black box in the bottom-left corner of a `width` x
+/// `height` frame of `bytes_per_pixel`: 4 for BGRA, RGBA and BGRx, 2 for YUY2.
+/// Frames too small for the box, or shorter than their size, are left as they are.
+pub fn burn_in(frame: &mut [u8], width: usize, height: usize, bytes_per_pixel: usize, number: u64) {
+    // Even, so the glyph pixels cover whole YUY2 macropixels
+    let scale = (height / 360).max(1) * 2;
+    let text = number.to_string();
+    // One glyph pixel of padding around the text, the gap after the last digit on the right
+    let (box_width, box_height) = ((text.len() * 4 + 1) * scale, 7 * scale);
+    if box_width > width || box_height > height || frame.len() < width * height * bytes_per_pixel {
+        return;
+    }
+    let top = height - box_height;
+    let mut fill = |left: usize, top: usize, w: usize, h: usize, white: bool| {
+        for y in top..top + h {
+            for x in left..left + w {
+                let i = (y * width + x) * bytes_per_pixel;
+                if bytes_per_pixel == 4 {
+                    let value = if white { 255 } else { 0 };
+                    frame[i..i + 4].copy_from_slice(&[value, value, value, 255]);
+                } else {
+                    // Luma, then the chroma byte of the pair, kept neutral
+                    frame[i] = if white { 235 } else { 16 };
+                    frame[i + 1] = 128;
+                }
+            }
+        }
+    };
+    fill(0, top, box_width, box_height, false);
+    for (n, digit) in text.bytes().enumerate() {
+        for (row, bits) in DIGITS[(digit - b'0') as usize].iter().enumerate() {
+            for col in 0..3 {
+                if bits & (0b100 >> col) != 0 {
+                    let x = (1 + n * 4 + col) * scale;
+                    let y = top + (1 + row) * scale;
+                    fill(x, y, scale, scale, true);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames_of_the_same_period_share_a_number() {
+        let clock = FrameClock::default();
+        let origin = Instant::now();
+        let ms = Duration::from_millis;
+        assert_eq!(clock.number(origin + ms(10)), 0);
+        // The origin is the first numbered frame, earlier frames clamp to it
+        assert_eq!(clock.number(origin), 0);
+        assert_eq!(clock.number(origin + ms(27)), 1);
+        assert_eq!(clock.number(origin + ms(43)), 1);
+        assert_eq!(clock.number(origin + ms(10) + ms(1000)), 30);
+    }
+
+    #[test]
+    fn test_burn_in_draws_the_digits_on_a_box() {
+        let (width, height) = (32, 20);
+        let mut frame = vec![100u8; width * height * 4];
+        burn_in(&mut frame, width, height, 4, 7);
+        let pixel = |x: usize, y: usize| &frame[(y * width + x) * 4..(y * width + x) * 4 + 4];
+        // The box is 10x14 at the bottom left, the 7's top row starts at (2, 8)
+        assert_eq!(pixel(0, 6), [0, 0, 0, 255]);
+        assert_eq!(pixel(2, 8), [255, 255, 255, 255]);
+        assert_eq!(pixel(6, 10), [255, 255, 255, 255]);
+        assert_eq!(pixel(2, 10), [0, 0, 0, 255]);
+        assert_eq!(pixel(10, 19), [100, 100, 100, 100]);
+        assert_eq!(pixel(0, 5), [100, 100, 100, 100]);
+
+        let mut yuy2 = vec![100u8; width * height * 2];
+        burn_in(&mut yuy2, width, height, 2, 7);
+        assert_eq!(&yuy2[(8 * width + 2) * 2..][..4], [235, 128, 235, 128]);
+
+        // Too small for the box
+        let mut tiny = vec![100u8; 8 * 8 * 4];
+        burn_in(&mut tiny, 8, 8, 4, 7);
+        assert!(tiny.iter().all(|&b| b == 100));
+    }
+}
@@ -0,0 +1,371 @@
+//! Bounded frame queue between a capture thread and its publish thread. The publish
+//! side blocks until a frame arrives instead of polling on a fixed sleep.
+//!
+//! A queue can report its fill level through a [`QueueLevel`] and warn when it
+//! crosses a high-water mark, ahead of the drops of a full queue.
+//!
+//! With `--single-thread-pipeline` there is no queue: the capture thread runs the
+//! publish step itself for every frame.
+
+use std::cell::RefCell;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender, TrySendError};
+
+use crate::throttle::Throttle;
+
+/// Longest single wait, so an idle publish loop still notices shutdown.
+const IDLE_WAIT: Duration = Duration::from_millis(100);
+
+/// What a push does when the queue is full because the publish thread fell behind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum QueueFullPolicy {
+    /// Drop the new frame and keep the queued ones.
+    #[default]
+    DropNewest,
+    /// Drop the oldest queued frame, so the freshest frames get through.
+    DropOldest,
+}
+
+/// Default of `--queue-high-water`.
+pub const DEFAULT_HIGH_WATER_PERCENT: u8 = 80;
+
+/// Time between two high-water warnings of the same queue.
+const HIGH_WATER_WARN_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Settings of the frame queues.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueSettings {
+    pub full: QueueFullPolicy,
+    /// Fill level, in percent of the capacity, from which a queue warns.
+    pub high_water_percent: u8,
+}
+
+/// How a capture thread hands its frames to the publish step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameHandoff {
+    /// Through a bounded queue drained by a separate publish thread.
+    Queue(QueueSettings),
+    /// The capture thread publishes each frame before capturing the next one.
+    Inline,
+}
+
+/// Publish step run by the capture thread itself under [`FrameHandoff::Inline`].
+type InlinePublish<T> = RefCell<Box<dyn FnMut(T) + Send>>;
+
+pub struct FrameSender<T> {
+    kind: SenderKind<T>,
+    monitor: Option<QueueMonitor>,
+}
+
+/// Frames in a queue right after its latest push, and its capacity.
+#[derive(Debug, Default)]
+pub struct QueueLevel {
+    frames: AtomicU64,
+    capacity: AtomicU64,
+}
+
+impl QueueLevel {
+    /// (frames, capacity); both 0 for a queue that never got a frame.
+    pub fn get(&self) -> (u64, u64) {
+        (
+            self.frames.load(Ordering::Relaxed),
+            self.capacity.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// See [`FrameSender::monitor`].
+struct QueueMonitor {
+    label: &'static str,
+    level: Arc<QueueLevel>,
+    high_water: usize,
+    warning: RefCell<Throttle>,
+}
+
+/// Number of queued frames from which a queue of `capacity` is at `percent` or
+/// above, at least 1.
+fn high_water_mark(capacity: usize, percent: u8) -> usize {
+    (capacity * usize::from(percent)).div_ceil(100).max(1)
+}
+
+enum SenderKind<T> {
+    Queue {
+        tx: Sender<T>,
+        /// Only used to evict the oldest frame under [`QueueFullPolicy::DropOldest`].
+        rx: Receiver<T>,
+        policy: QueueFullPolicy,
+    },
+    Inline(InlinePublish<T>),
+}
+
+impl<T> FrameSender<T> {
+    /// Queues `frame`, waking the publish thread, or publishes it right away when
+    /// inline. Returns false if a frame was dropped because the queue was full.
+    pub fn push(&self, frame: T) -> bool {
+        let (tx, rx, policy) = match &self.kind {
+            SenderKind::Queue { tx, rx, policy } => (tx, rx, *policy),
+            SenderKind::Inline(publish) => {
+                (publish.borrow_mut())(frame);
+                return true;
+            }
+        };
+        let pushed = match tx.try_send(frame) {
+            Ok(()) => true,
+            Err(TrySendError::Full(frame)) => {
+                if policy == QueueFullPolicy::DropOldest {
+                    let _ = rx.try_recv();
+                    // Single producer: only the publish thread could have taken a
+                    // frame meanwhile, which leaves room all the same
+                    let _ = tx.try_send(frame);
+                }
+                false
+            }
+            Err(TrySendError::Disconnected(_)) => return false,
+        };
+        if let Some(monitor) = &self.monitor {
+            let frames = tx.len();
+            monitor.level.frames.store(frames as u64, Ordering::Relaxed);
+            if frames >= monitor.high_water && monitor.warning.borrow_mut().should_log() {
+                let capacity = tx.capacity().unwrap_or(frames);
+                log::warn!(
+                    "⚠️ {} frame queue at {frames}/{capacity} frames, above its high-water \
+                    mark: the publish thread is falling behind, consider a lower bitrate or fps",
+                    monitor.label
+                );
+            }
+        }
+        pushed
+    }
+
+    /// Records the fill level in `level` after every push and warns, at most every
+    /// [`HIGH_WATER_WARN_INTERVAL`], while at least `high_water_percent` of the queue
+    /// is filled. Does nothing for an inline sender, which has no queue.
+    pub fn monitor(
+        mut self,
+        label: &'static str,
+        level: Arc<QueueLevel>,
+        high_water_percent: u8,
+    ) -> Self {
+        if let SenderKind::Queue { tx, .. } = &self.kind {
+            let capacity = tx.capacity().unwrap_or(usize::MAX);
+            level.capacity.store(capacity as u64, Ordering::Relaxed);
+            self.monitor = Some(QueueMonitor {
+                label,
+                level,
+                high_water: high_water_mark(capacity, high_water_percent),
+                warning: RefCell::new(Throttle::new(HIGH_WATER_WARN_INTERVAL)),
+            });
+        }
+        self
+    }
+}
+
+pub struct FrameReceiver<T> {
+    rx: Receiver<T>,
+}
+
+impl<T> FrameReceiver<T> {
+    /// Waits up to [`IDLE_WAIT`] for the next frame.
+    pub fn recv(&self) -> Option<T> {
+        match self.rx.recv_timeout(IDLE_WAIT) {
+            Ok(frame) => Some(frame),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => {
+                // The capture thread exited; keep the publish loop from spinning
+                std::thread::sleep(IDLE_WAIT);
+                None
+            }
+        }
+    }
+
+    /// Publish loop: passes each frame to `publish` until `stopping` returns true,
+    /// counting every wakeup in `wakeups`.
+    pub fn for_each(
+        &self,
+        stopping: impl Fn() -> bool,
+        wakeups: &AtomicU64,
+        mut publish: impl FnMut(T),
+    ) {
+        while !stopping() {
+            let frame = self.recv();
+            wakeups.fetch_add(1, Ordering::Relaxed);
+            if let Some(frame) = frame {
+                publish(frame);
+            }
+        }
+    }
+}
+
+/// Frame rate the Kinect delivers color, infrared and depth at; a publish cap at or
+/// above it keeps every frame.
+pub const KINECT_FPS: u32 = 30;
+
+/// Caps how often a publish loop pushes frames (`--color-publish-fps`,
+/// `--infra-publish-fps`), dropping the rest before any conversion or encoding.
+pub struct PublishRateCap {
+    /// `None` when the cap is at or above [`KINECT_FPS`].
+    interval: Option<Duration>,
+    /// Earliest time of the next push.
+    next: Option<Instant>,
+}
+
+impl PublishRateCap {
+    pub fn new(fps: u32) -> Self {
+        Self {
+            interval: Self::interval(fps),
+            next: None,
+        }
+    }
+
+    fn interval(fps: u32) -> Option<Duration> {
+        (fps < KINECT_FPS).then(|| Duration::from_secs(1) / fps.max(1))
+    }
+
+    /// Changes the cap, e.g. when `--cpu-governor` sheds load; the next push keeps
+    /// its slot.
+    pub fn set_fps(&mut self, fps: u32) {
+        self.interval = Self::interval(fps);
+    }
+
+    /// Whether to push the frame arriving now.
+    pub fn admit(&mut self) -> bool {
+        self.admit_at(Instant::now())
+    }
+
+    fn admit_at(&mut self, now: Instant) -> bool {
+        let Some(interval) = self.interval else {
+            return true;
+        };
+        // Frames arrive with some jitter: accept one up to half a capture interval
+        // early, or e.g. a 15 fps cap would let only every third frame through
+        let tolerance = Duration::from_secs(1) / KINECT_FPS / 2;
+        if let Some(next) = self.next
+            && now + tolerance < next
+        {
+            return false;
+        }
+        // Slots advance from the previous one, not from `now`, so the rate stays at
+        // the cap; after a gap in the frames they start over
+        let slot = match self.next {
+            Some(next) if now.saturating_duration_since(next) < interval => next,
+            _ => now,
+        };
+        self.next = Some(slot + interval);
+        true
+    }
+}
+
+/// Creates a queue holding up to `capacity` frames.
+pub fn frame_queue<T>(
+    capacity: usize,
+    policy: QueueFullPolicy,
+) -> (FrameSender<T>, FrameReceiver<T>) {
+    let (tx, rx) = crossbeam_channel::bounded(capacity);
+    (
+        FrameSender {
+            kind: SenderKind::Queue {
+                tx,
+                rx: rx.clone(),
+                policy,
+            },
+            monitor: None,
+        },
+        FrameReceiver { rx },
+    )
+}
+
+/// Creates a sender that runs `publish` on the pushing thread instead of queueing.
+pub fn inline_sender<T>(publish: impl FnMut(T) + Send + 'static) -> FrameSender<T> {
+    FrameSender {
+        kind: SenderKind::Inline(RefCell::new(Box::new(publish))),
+        monitor: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_full_queue_drops_per_policy() {
+        let (tx, rx) = frame_queue(2, QueueFullPolicy::DropNewest);
+        assert!(tx.push(1));
+        assert!(tx.push(2));
+        assert!(!tx.push(3));
+        assert_eq!((rx.recv(), rx.recv()), (Some(1), Some(2)));
+
+        let (tx, rx) = frame_queue(2, QueueFullPolicy::DropOldest);
+        assert!(tx.push(1));
+        assert!(tx.push(2));
+        assert!(!tx.push(3));
+        assert_eq!((rx.recv(), rx.recv()), (Some(2), Some(3)));
+    }
+
+    #[test]
+    fn test_monitor_records_the_fill_level() {
+        assert_eq!(high_water_mark(16, 80), 13);
+        assert_eq!(high_water_mark(8, 80), 7);
+        assert_eq!(high_water_mark(32, 100), 32);
+        assert_eq!(high_water_mark(2, 1), 1);
+
+        let level = Arc::new(QueueLevel::default());
+        let (tx, rx) = frame_queue(4, QueueFullPolicy::DropNewest);
+        let tx = tx.monitor("test", level.clone(), 80);
+        assert_eq!(level.get(), (0, 4));
+        tx.push(1);
+        tx.push(2);
+        assert_eq!(level.get(), (2, 4));
+        rx.recv();
+        tx.push(3);
+        assert_eq!(level.get(), (2, 4));
+        tx.push(4);
+        tx.push(5);
+        assert!(!tx.push(6));
+        assert_eq!(level.get(), (4, 4));
+    }
+
+    #[test]
+    fn test_publish_rate_cap() {
+        let start = Instant::now();
+        // 30 fps capture with +-3 ms of jitter
+        let frame = |n: u32| {
+            let jitter = [0, 3, 0, 0, 2, 0][n as usize % 6];
+            start + Duration::from_secs(1) * n / 30 - Duration::from_millis(jitter)
+        };
+        let pushed = |fps| {
+            let mut cap = PublishRateCap::new(fps);
+            (1..=300).filter(|&n| cap.admit_at(frame(n))).count()
+        };
+        assert_eq!(pushed(30), 300);
+        assert_eq!(pushed(15), 150);
+        assert_eq!(pushed(10), 100);
+        assert_eq!(pushed(1), 10);
+        let twenty = pushed(20);
+        assert!((195..=200).contains(&twenty), "{twenty}");
+    }
+
+    #[test]
+    fn test_recv_wakes_on_push() {
+        let (tx, rx) = frame_queue(2, QueueFullPolicy::DropNewest);
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            tx.push(7);
+        });
+        assert_eq!(rx.recv(), Some(7));
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_inline_sender_publishes_on_push() {
+        let published = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = published.clone();
+        let tx = inline_sender(move |frame| sink.lock().unwrap().push(frame));
+        assert!(tx.push(1));
+        assert!(tx.push(2));
+        assert_eq!(*published.lock().unwrap(), [1, 2]);
+    }
+}
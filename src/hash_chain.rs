@@ -0,0 +1,306 @@
+//! Hash-chained manifest of the PNGs written by `--snapshot-sequence-dir`
+//! (`--snapshot-manifest`) and the `verify` subcommand that checks it.
+//!
+//! Every file gets a manifest line with its SHA-256 and a chain hash, which covers
+//! the file's name, time and hash and the chain hash of the line before it. Editing,
+//! replacing or deleting a file, or editing, reordering or removing a line, breaks
+//! the chain from there on. Removing lines at the end can't be seen in the
+//! directory alone; compare the last chain hash with the one logged when the
+//! sequence finished. The chain isn't keyed, so whoever can rewrite the manifest can
+//! also rebuild it.
+
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use sha2::{Digest, Sha256};
+
+/// Manifest file in each stream directory.
+pub const MANIFEST_NAME: &str = "frames.manifest";
+const HEADER: &str = "# kinect-rtsp frame manifest v1: index, unix time ms, file, sha256, chain";
+
+type Hash = [u8; 32];
+
+/// Chain hash before the first line.
+const GENESIS: Hash = [0; 32];
+
+fn to_hex(hash: &Hash) -> String {
+    hash.iter().fold(String::with_capacity(64), |mut out, b| {
+        let _ = write!(out, "{b:02x}");
+        out
+    })
+}
+
+fn from_hex(s: &str) -> Option<Hash> {
+    if s.len() != 64 || !s.is_ascii() {
+        return None;
+    }
+    let mut hash = GENESIS;
+    for (byte, pair) in hash.iter_mut().zip(s.as_bytes().chunks_exact(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(hash)
+}
+
+fn chain_hash(prev: &Hash, index: u64, unix_ms: u64, name: &str, file_hash: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(prev);
+    hasher.update(index.to_be_bytes());
+    hasher.update(unix_ms.to_be_bytes());
+    hasher.update(name.as_bytes());
+    hasher.update(file_hash);
+    hasher.finalize().into()
+}
+
+struct Entry {
+    index: u64,
+    unix_ms: u64,
+    name: String,
+    file_hash: Hash,
+    chain: Hash,
+}
+
+fn parse_line(line: &str) -> Option<Entry> {
+    let mut fields = line.split('\t');
+    let entry = Entry {
+        index: fields.next()?.parse().ok()?,
+        unix_ms: fields.next()?.parse().ok()?,
+        name: fields.next()?.to_string(),
+        file_hash: from_hex(fields.next()?)?,
+        chain: from_hex(fields.next()?)?,
+    };
+    fields.next().is_none().then_some(entry)
+}
+
+/// Lines of the manifest at `path`, without the header and blank lines.
+fn read_lines(path: &Path) -> anyhow::Result<Vec<String>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    BufReader::new(file)
+        .lines()
+        .filter(|line| {
+            !line
+                .as_ref()
+                .is_ok_and(|l| l.is_empty() || l.starts_with('#'))
+        })
+        .collect::<Result<_, _>>()
+        .with_context(|| format!("Failed to read {}", path.display()))
+}
+
+/// Appends to the manifest of one stream directory.
+pub struct ManifestWriter {
+    path: PathBuf,
+    file: File,
+    next_index: u64,
+    head: Hash,
+}
+
+impl ManifestWriter {
+    /// Opens the manifest in `dir`, continuing the chain of an earlier run.
+    pub fn open(dir: &Path) -> anyhow::Result<Self> {
+        let path = dir.join(MANIFEST_NAME);
+        let (next_index, head) = if path.exists() {
+            let lines = read_lines(&path)?;
+            match lines.last() {
+                Some(line) => {
+                    let last = parse_line(line).with_context(|| {
+                        format!(
+                            "Can't continue {}, its last line is malformed",
+                            path.display()
+                        )
+                    })?;
+                    (last.index + 1, last.chain)
+                }
+                None => (1, GENESIS),
+            }
+        } else {
+            std::fs::write(&path, format!("{HEADER}\n"))
+                .with_context(|| format!("Failed to create {}", path.display()))?;
+            (1, GENESIS)
+        };
+        let file = OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        Ok(Self {
+            path,
+            file,
+            next_index,
+            head,
+        })
+    }
+
+    /// Adds `name`, captured at `unix_ms`, whose content is `data`.
+    pub fn append(&mut self, name: &str, unix_ms: u64, data: &[u8]) -> anyhow::Result<()> {
+        let file_hash: Hash = Sha256::digest(data).into();
+        let chain = chain_hash(&self.head, self.next_index, unix_ms, name, &file_hash);
+        writeln!(
+            self.file,
+            "{}\t{unix_ms}\t{name}\t{}\t{}",
+            self.next_index,
+            to_hex(&file_hash),
+            to_hex(&chain)
+        )
+        .and_then(|()| self.file.flush())
+        .with_context(|| format!("Failed to append to {}", self.path.display()))?;
+        self.next_index += 1;
+        self.head = chain;
+        Ok(())
+    }
+
+    /// Path of the manifest and its latest chain hash, in hex.
+    pub fn head(&self) -> (&Path, String) {
+        (&self.path, to_hex(&self.head))
+    }
+}
+
+/// Result of checking one manifest.
+struct Verification {
+    manifest: PathBuf,
+    entries: u64,
+    /// Chain hash of the last line, to compare with the logged one.
+    head: String,
+    /// What doesn't match, in manifest order; empty if the chain is intact.
+    problems: Vec<String>,
+}
+
+/// Recomputes the chain of the manifest in `dir` and the hash of every file in it.
+fn verify_manifest(dir: &Path) -> anyhow::Result<Verification> {
+    let manifest = dir.join(MANIFEST_NAME);
+    let mut problems = Vec::new();
+    let mut prev = GENESIS;
+    let mut entries = 0;
+    for (n, line) in read_lines(&manifest)?.iter().enumerate() {
+        let Some(entry) = parse_line(line) else {
+            problems.push(format!("line {}: malformed", n + 1));
+            continue;
+        };
+        entries += 1;
+        if entry.chain
+            != chain_hash(
+                &prev,
+                entry.index,
+                entry.unix_ms,
+                &entry.name,
+                &entry.file_hash,
+            )
+        {
+            problems.push(format!(
+                "#{} {}: chain broken, a line before it was edited, reordered or removed",
+                entry.index, entry.name
+            ));
+        }
+        prev = entry.chain;
+        if entry.name.contains(['/', '\\']) {
+            problems.push(format!(
+                "#{} {}: not a plain file name",
+                entry.index, entry.name
+            ));
+            continue;
+        }
+        match std::fs::read(dir.join(&entry.name)) {
+            Ok(data) if Hash::from(Sha256::digest(&data)) == entry.file_hash => {}
+            Ok(_) => problems.push(format!("#{} {}: content modified", entry.index, entry.name)),
+            Err(e) => problems.push(format!("#{} {}: {e}", entry.index, entry.name)),
+        }
+    }
+    Ok(Verification {
+        manifest,
+        entries,
+        head: to_hex(&prev),
+        problems,
+    })
+}
+
+/// Checks the manifest in `dir`, or in each of its subdirectories if it has none,
+/// e.g. the `color` and `infrared` directories of a snapshot sequence.
+fn verify(dir: &Path) -> anyhow::Result<Vec<Verification>> {
+    if dir.join(MANIFEST_NAME).is_file() {
+        return Ok(vec![verify_manifest(dir)?]);
+    }
+    let mut subdirs: Vec<PathBuf> = std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read {}", dir.display()))?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.join(MANIFEST_NAME).is_file())
+        .collect();
+    if subdirs.is_empty() {
+        anyhow::bail!(
+            "No {MANIFEST_NAME} in {} or its subdirectories",
+            dir.display()
+        );
+    }
+    subdirs.sort();
+    subdirs.iter().map(|dir| verify_manifest(dir)).collect()
+}
+
+/// `verify` subcommand: prints the result for each manifest under `dir` and
+/// returns whether all of them are intact.
+pub fn run(dir: &Path) -> anyhow::Result<bool> {
+    let mut intact = true;
+    for verification in verify(dir)? {
+        let manifest = verification.manifest.display();
+        if verification.problems.is_empty() {
+            println!(
+                "OK    {manifest}: {} files, chain hash {}",
+                verification.entries, verification.head
+            );
+        } else {
+            intact = false;
+            println!(
+                "FAIL  {manifest}: {} of {} entries don't match",
+                verification.problems.len(),
+                verification.entries
+            );
+            for problem in &verification.problems {
+                println!("      {problem}");
+            }
+        }
+    }
+    Ok(intact)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chain_detects_modified_deleted_and_removed_frames() {
+        let dir = std::env::temp_dir().join(format!("kinect-rtsp-chain-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut writer = ManifestWriter::open(&dir).unwrap();
+        for i in 1..=3u8 {
+            let name = format!("frame_{i}.png");
+            std::fs::write(dir.join(&name), [i; 4]).unwrap();
+            writer.append(&name, 1000 * u64::from(i), &[i; 4]).unwrap();
+        }
+        let head = writer.head().1;
+        drop(writer);
+        // A restart continues the same chain
+        ManifestWriter::open(&dir).unwrap();
+        let intact = verify(&dir).unwrap().remove(0);
+        assert!(intact.problems.is_empty(), "{:?}", intact.problems);
+        assert_eq!((intact.entries, intact.head), (3, head));
+
+        std::fs::write(dir.join("frame_2.png"), [9; 4]).unwrap();
+        std::fs::remove_file(dir.join("frame_3.png")).unwrap();
+        let problems = verify(&dir).unwrap().remove(0).problems;
+        assert_eq!(problems.len(), 2, "{problems:?}");
+        assert!(problems[0].contains("frame_2.png: content modified"));
+        assert!(problems[1].contains("frame_3.png"));
+
+        // Dropping the middle line breaks the chain of the next one
+        let manifest = dir.join(MANIFEST_NAME);
+        let text = std::fs::read_to_string(&manifest).unwrap();
+        let kept: Vec<&str> = text.lines().filter(|l| !l.starts_with("2\t")).collect();
+        std::fs::write(&manifest, kept.join("\n")).unwrap();
+        let problems = verify(&dir).unwrap().remove(0).problems;
+        assert!(
+            problems
+                .iter()
+                .any(|p| p.contains("#3 frame_3.png: chain broken"))
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,200 @@
+//! Minimal HTTP/1.1 listener shared by the metrics and control endpoints, and the
+//! matching client used by the `status` subcommand.
+//!
+//! Each connection carries one request; only the request line and the body, as
+//! long as its `Content-Length`, are looked at.
+
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+pub struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    pub fn ok(content_type: &'static str, body: String) -> Self {
+        Self {
+            status: "200 OK",
+            content_type,
+            body,
+        }
+    }
+
+    pub fn json(value: &impl serde::Serialize) -> Self {
+        match serde_json::to_string_pretty(value) {
+            Ok(body) => Self::ok("application/json", body),
+            Err(e) => Self::error("500 Internal Server Error", &e.to_string()),
+        }
+    }
+
+    pub fn not_found() -> Self {
+        Self::error("404 Not Found", "not found")
+    }
+
+    pub fn error(status: &'static str, message: &str) -> Self {
+        Self {
+            status,
+            content_type: "text/plain",
+            body: format!("{message}\n"),
+        }
+    }
+
+    fn to_http(&self) -> String {
+        format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.status,
+            self.content_type,
+            self.body.len(),
+            self.body
+        )
+    }
+}
+
+/// Largest request accepted, headers and body included.
+const MAX_REQUEST_BYTES: usize = 8 * 1024;
+
+/// Method, path and `Content-Length` of the request whose headers are `head`.
+fn parse_head(head: &str) -> Option<(&str, &str, usize)> {
+    let mut lines = head.lines();
+    let mut request_line = lines.next()?.split_whitespace();
+    let (method, path) = (request_line.next()?, request_line.next()?);
+    let content_length = lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .map_or(Some(0), |(_, value)| value.trim().parse().ok())?;
+    Some((method, path, content_length))
+}
+
+/// End of a body of `content_length` bytes starting at `body_start`, or `None` if the
+/// request would exceed [`MAX_REQUEST_BYTES`]. `content_length` comes from the client,
+/// so the sum must not overflow.
+fn body_end(body_start: usize, content_length: usize) -> Option<usize> {
+    body_start
+        .checked_add(content_length)
+        .filter(|&end| end <= MAX_REQUEST_BYTES)
+}
+
+/// Reads one request and returns its method, path and body; `None` if it's
+/// malformed, larger than [`MAX_REQUEST_BYTES`] or the client hung up.
+async fn read_request(socket: &mut tokio::net::TcpStream) -> Option<(String, String, String)> {
+    let mut request = Vec::with_capacity(1024);
+    let mut chunk = [0u8; 1024];
+    loop {
+        if let Some(end) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+            let head = String::from_utf8_lossy(&request[..end]).into_owned();
+            let (method, path, content_length) = parse_head(&head)?;
+            let body_start = end + 4;
+            let body_end = body_end(body_start, content_length)?;
+            while request.len() < body_end {
+                let n = socket.read(&mut chunk).await.ok().filter(|&n| n > 0)?;
+                request.extend_from_slice(&chunk[..n]);
+            }
+            let body = &request[body_start..body_end];
+            return Some((
+                method.to_string(),
+                path.to_string(),
+                String::from_utf8_lossy(body).into_owned(),
+            ));
+        }
+        if request.len() >= MAX_REQUEST_BYTES {
+            return None;
+        }
+        let n = socket.read(&mut chunk).await.ok().filter(|&n| n > 0)?;
+        request.extend_from_slice(&chunk[..n]);
+    }
+}
+
+/// Serves requests on `address:port` until the process exits. `handler` gets the
+/// method, path and body of each request and runs on the blocking thread pool, so
+/// it may call into GStreamer.
+pub async fn serve<F>(name: &str, address: &str, port: u16, handler: F) -> anyhow::Result<()>
+where
+    F: Fn(&str, &str, &str) -> Response + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind((address, port)).await?;
+    log::info!("{name} listening on http://{address}:{port}");
+    let handler = Arc::new(handler);
+    loop {
+        let (mut socket, _) = listener.accept().await?;
+        let handler = handler.clone();
+        tokio::spawn(async move {
+            let Some((method, path, body)) = read_request(&mut socket).await else {
+                return;
+            };
+            let response = tokio::task::spawn_blocking(move || handler(&method, &path, &body))
+                .await
+                .unwrap_or_else(|e| Response::error("500 Internal Server Error", &e.to_string()));
+            if let Err(e) = socket.write_all(response.to_http().as_bytes()).await {
+                log::debug!("Failed to write HTTP response: {e}");
+            }
+        });
+    }
+}
+
+/// Sends `GET path` to `address:port` and returns the body of a `200 OK` response.
+pub fn get(address: &str, port: u16, path: &str, timeout: Duration) -> anyhow::Result<String> {
+    let socket_addr = (address, port)
+        .to_socket_addrs()?
+        .next()
+        .with_context(|| format!("Invalid address {address}:{port}"))?;
+    let mut stream = TcpStream::connect_timeout(&socket_addr, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    write!(
+        stream,
+        "GET {path} HTTP/1.1\r\nHost: {address}:{port}\r\nConnection: close\r\n\r\n"
+    )?;
+    let mut response = String::new();
+    stream
+        .read_to_string(&mut response)
+        .with_context(|| format!("Failed to read the response to GET {path}"))?;
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .with_context(|| format!("Malformed response to GET {path}"))?;
+    let status = head.lines().next().unwrap_or_default();
+    if status.split_whitespace().nth(1) != Some("200") {
+        anyhow::bail!("GET {path} returned '{status}'");
+    }
+    Ok(body.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_head_reads_the_request_line_and_content_length() {
+        assert_eq!(
+            parse_head("GET /streams HTTP/1.1\r\nHost: 127.0.0.1:8080"),
+            Some(("GET", "/streams", 0))
+        );
+        assert_eq!(
+            parse_head("PUT /auth/credentials HTTP/1.1\r\ncontent-length: 42\r\nHost: x"),
+            Some(("PUT", "/auth/credentials", 42))
+        );
+        assert_eq!(parse_head("PUT / HTTP/1.1\r\nContent-Length: many"), None);
+        assert_eq!(parse_head(""), None);
+    }
+
+    #[test]
+    fn test_body_end_rejects_oversized_and_overflowing_lengths() {
+        assert_eq!(body_end(100, 42), Some(142));
+        assert_eq!(
+            body_end(100, MAX_REQUEST_BYTES - 100),
+            Some(MAX_REQUEST_BYTES)
+        );
+        assert_eq!(body_end(100, MAX_REQUEST_BYTES), None);
+        let huge = parse_head("PUT / HTTP/1.1\r\nContent-Length: 18446744073709551615")
+            .unwrap()
+            .2;
+        assert_eq!(body_end(100, huge), None);
+    }
+}
@@ -1,32 +1,325 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    sync::Arc,
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
 
 use anyhow::Context;
 // no async ring buffers needed for RTSP publishing path
-use kinect_v2::infrared_capture::{
-    InfraredFrameCapture, InfraredFrameCaptureIter, InfraredFrameData,
+use kinect_v2::infrared_capture::{InfraredFrameCapture, InfraredFrameCaptureIter};
+use kinect_v2::long_exposure_infrared_capture::{
+    LongExposureInfraredFrameCapture, LongExposureInfraredFrameCaptureIter,
 };
-use once_cell::sync::Lazy;
-use ringbuf::{
-    HeapRb, SharedRb,
-    storage::Heap,
-    traits::{Consumer, Producer, Split},
-    wrap::caching::Caching,
+
+use crate::affinity::pin_current_thread;
+use crate::frame_number::DIGITS;
+use crate::frame_queue::{
+    FrameHandoff, FrameSender, KINECT_FPS, PublishRateCap, frame_queue, inline_sender,
 };
+use crate::infrared_config::InfraredConfig;
+use crate::priority::lower_current_thread;
+use crate::rtsp_publisher::{CaptureSource, CaptureStop, RtspPublisher, frame_byte_len};
+use crate::throttle::{PROGRESS_LOG_INTERVAL, STALL_WARN_INTERVAL, Throttle};
+use crate::warmup::Warmup;
+
+/// Kinect stream the infrared frames are read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum InfraredSource {
+    /// Regular infrared frames
+    #[default]
+    Standard,
+    /// Long-exposure infrared frames, less noisy in dark scenes
+    LongExposure,
+}
+
+/// Infrared stream an infrared pipeline feeds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InfraredMount {
+    /// The `--mount-infrared` stream, read from `--infrared-source`.
+    Main,
+    /// The `--infrared-long` stream, always read from the long-exposure frames.
+    Long,
+    /// The stream of the `n`th infrared config profile, read with [`Self::Main`].
+    Profile(usize),
+}
+
+impl InfraredMount {
+    fn is_active(self, rtsp: &RtspPublisher) -> bool {
+        match self {
+            Self::Main => rtsp.is_infra_active(),
+            Self::Long => rtsp.is_infra_long_active(),
+            Self::Profile(n) => rtsp.is_infra_profile_active(n),
+        }
+    }
+
+    fn set_overlay_text(self, rtsp: &RtspPublisher, text: &str) {
+        match self {
+            Self::Main => rtsp.set_infra_overlay_text(text),
+            Self::Long => rtsp.set_infra_long_overlay_text(text),
+            Self::Profile(n) => rtsp.set_infra_profile_overlay_text(n, text),
+        }
+    }
+
+    fn send(self, rtsp: &RtspPublisher, captured: Instant, width: u32, height: u32, data: &[u8]) {
+        match self {
+            Self::Main => rtsp.send_infra_bgra(captured, width, height, data),
+            Self::Long => rtsp.send_infra_long_bgra(captured, width, height, data),
+            Self::Profile(n) => rtsp.send_infra_profile_bgra(n, captured, width, height, data),
+        }
+    }
+
+    fn capture_source(self) -> CaptureSource {
+        match self {
+            Self::Main | Self::Profile(_) => CaptureSource::Infrared,
+            Self::Long => CaptureSource::InfraredLong,
+        }
+    }
+
+    /// Prefix of the pipeline's thread names.
+    fn thread_prefix(self) -> &'static str {
+        match self {
+            Self::Main | Self::Profile(_) => "infrared",
+            Self::Long => "infrared-long",
+        }
+    }
+}
 
-use crate::rtsp_publisher::RtspPublisher;
+/// Size of the Kinect V2 infrared frame.
+const INFRARED_WIDTH: u32 = 512;
+const INFRARED_HEIGHT: u32 = 424;
+
+/// Width of the margin `--ir-legend` adds to the right of the infrared frame.
+pub const IR_LEGEND_WIDTH: u32 = 48;
+
+/// Palette the tone-mapped infrared values are shown with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IrColormap {
+    /// Grey scale, black to white
+    #[default]
+    Gray,
+    /// Thermal-camera style: black, purple, red, orange, yellow to white
+    Ironbow,
+}
+
+/// Stops of the ironbow palette as (position, RGB), linearly interpolated.
+const IRONBOW_STOPS: [(f32, [u8; 3]); 7] = [
+    (0.0, [0, 0, 0]),
+    (0.15, [32, 0, 100]),
+    (0.35, [125, 0, 155]),
+    (0.55, [220, 40, 70]),
+    (0.75, [255, 140, 0]),
+    (0.9, [255, 220, 50]),
+    (1.0, [255, 255, 255]),
+];
+
+impl IrColormap {
+    /// RGB color of every tone-mapped byte.
+    fn palette(self) -> [[u8; 3]; 256] {
+        std::array::from_fn(|i| match self {
+            IrColormap::Gray => [i as u8; 3],
+            IrColormap::Ironbow => {
+                let t = i as f32 / 255.0;
+                let upper = IRONBOW_STOPS
+                    .iter()
+                    .position(|&(pos, _)| pos >= t)
+                    .unwrap_or(IRONBOW_STOPS.len() - 1)
+                    .max(1);
+                let ((p0, c0), (p1, c1)) = (IRONBOW_STOPS[upper - 1], IRONBOW_STOPS[upper]);
+                let f = (t - p0) / (p1 - p0);
+                std::array::from_fn(|ch| {
+                    (c0[ch] as f32 + (c1[ch] as f32 - c0[ch] as f32) * f).round() as u8
+                })
+            }
+        })
+    }
+}
+
+/// Glyph pixels are drawn as 2x2 blocks.
+const GLYPH_SCALE: usize = 2;
+/// Height of a label row and its gap to the bar.
+const LABEL_HEIGHT: usize = 5 * GLYPH_SCALE + 6;
+
+/// Draws `text` (digits only) centered in the BGRA `legend` of `width` at row `top`.
+fn draw_label(legend: &mut [u8], width: usize, top: usize, text: &str) {
+    let advance = 4 * GLYPH_SCALE;
+    let text_width = text.len() * advance - GLYPH_SCALE;
+    let left = width.saturating_sub(text_width) / 2;
+    for (n, digit) in text.bytes().filter(u8::is_ascii_digit).enumerate() {
+        for (row, bits) in DIGITS[(digit - b'0') as usize].iter().enumerate() {
+            for col in 0..3 {
+                if bits & (0b100 >> col) == 0 {
+                    continue;
+                }
+                for dy in 0..GLYPH_SCALE {
+                    for dx in 0..GLYPH_SCALE {
+                        let x = left + n * advance + col * GLYPH_SCALE + dx;
+                        let y = top + row * GLYPH_SCALE + dy;
+                        if x < width {
+                            let i = (y * width + x) * 4;
+                            legend[i..i + 4].copy_from_slice(&[255, 255, 255, 255]);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Renders the `--ir-legend` margin, [`IR_LEGEND_WIDTH`] x `height` BGRA: the part of
+/// the palette the LUT produces as a vertical bar, brightest at the top, labelled
+/// with the raw infrared values at its ends.
+fn render_legend(lut: &[u8; 65536], palette: &[[u8; 3]; 256], height: u32) -> Vec<u8> {
+    let (width, height) = (IR_LEGEND_WIDTH as usize, height as usize);
+    let mut legend = [0u8, 0, 0, 255].repeat(width * height);
+    let (bottom, top) = (lut[0], lut[65535]);
+    // Raw values where the mapping leaves the bottom and reaches the top of the range
+    let low = lut.iter().rposition(|&v| v == bottom).unwrap_or(0);
+    let high = lut.iter().position(|&v| v == top).unwrap_or(65535);
+
+    let bar_top = LABEL_HEIGHT;
+    let bar_bottom = height.saturating_sub(LABEL_HEIGHT);
+    let bar_rows = bar_bottom.saturating_sub(bar_top).max(2);
+    let (bar_left, bar_right) = (width / 2 - 6, width / 2 + 6);
+    for y in bar_top..bar_bottom {
+        let f = (y - bar_top) as f32 / (bar_rows - 1) as f32;
+        let value = (top as f32 + (bottom as f32 - top as f32) * f).round() as usize;
+        let [r, g, b] = palette[value];
+        for x in bar_left..bar_right {
+            let i = (y * width + x) * 4;
+            legend[i..i + 4].copy_from_slice(&[b, g, r, 255]);
+        }
+    }
+    draw_label(&mut legend, width, 3, &high.to_string());
+    draw_label(&mut legend, width, bar_bottom + 3, &low.to_string());
+    legend
+}
 
+/// Largest `--infra-scale` factor; 4x already exceeds 1080p.
+const MAX_INFRA_SCALE: f32 = 4.0;
+
+/// Size of the infrared stream scaled by `factor`. The size must come out as even
+/// whole numbers, since I420 chroma is subsampled 2x2.
+pub fn scaled_infra_size(factor: f32) -> Result<(u32, u32), String> {
+    if !(factor > 0.0 && factor <= MAX_INFRA_SCALE) {
+        return Err(format!(
+            "invalid scale {factor}: must be above 0 and at most {MAX_INFRA_SCALE}"
+        ));
+    }
+    let width = INFRARED_WIDTH as f32 * factor;
+    let height = INFRARED_HEIGHT as f32 * factor;
+    let (w, h) = (width.round() as u32, height.round() as u32);
+    if (width - w as f32).abs() > 0.01
+        || (height - h as f32).abs() > 0.01
+        || !w.is_multiple_of(2)
+        || !h.is_multiple_of(2)
+    {
+        return Err(format!(
+            "invalid scale {factor}: {width}x{height} is not an even size (try 1.5, 2 or 2.5)"
+        ));
+    }
+    Ok((w, h))
+}
+
+/// Parses `--infra-scale`, see [`scaled_infra_size`].
+pub fn parse_infra_scale(s: &str) -> Result<f32, String> {
+    let factor: f32 = s
+        .trim()
+        .parse()
+        .map_err(|e| format!("invalid scale '{s}': {e}"))?;
+    scaled_infra_size(factor)?;
+    Ok(factor)
+}
+
+/// An infrared frame from either source; both have the same 512x424 u16 layout.
+struct InfraredFrame {
+    width: u32,
+    height: u32,
+    data: Vec<u16>,
+}
+
+enum InfraredCapture {
+    Standard(InfraredFrameCapture),
+    LongExposure(LongExposureInfraredFrameCapture),
+}
+
+enum InfraredCaptureIter {
+    Standard(InfraredFrameCaptureIter),
+    LongExposure(LongExposureInfraredFrameCaptureIter),
+}
+
+impl InfraredCapture {
+    fn new(source: InfraredSource) -> anyhow::Result<Self> {
+        Ok(match source {
+            InfraredSource::Standard => Self::Standard(
+                InfraredFrameCapture::new().context("Failed to create infrared capture")?,
+            ),
+            InfraredSource::LongExposure => Self::LongExposure(
+                LongExposureInfraredFrameCapture::new()
+                    .context("Failed to create long-exposure infrared capture")?,
+            ),
+        })
+    }
+
+    fn iter(&self) -> anyhow::Result<InfraredCaptureIter> {
+        Ok(match self {
+            Self::Standard(capture) => InfraredCaptureIter::Standard(
+                capture
+                    .iter()
+                    .context("Failed to create infrared capture iterator")?,
+            ),
+            Self::LongExposure(capture) => InfraredCaptureIter::LongExposure(
+                capture
+                    .iter()
+                    .context("Failed to create long-exposure infrared capture iterator")?,
+            ),
+        })
+    }
+}
+
+impl Iterator for InfraredCaptureIter {
+    type Item = anyhow::Result<InfraredFrame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let frame = match self {
+            Self::Standard(iter) => iter.next()?.map(|d| InfraredFrame {
+                width: d.width,
+                height: d.height,
+                data: d.data,
+            }),
+            Self::LongExposure(iter) => iter.next()?.map(|d| InfraredFrame {
+                width: d.width,
+                height: d.height,
+                data: d.data,
+            }),
+        };
+        Some(frame.map_err(|e| anyhow::anyhow!("{e}")))
+    }
+}
+
+/// Captures from `source` while any of `mounts` has clients.
 fn infrared_frame_capture(
     rtsp: Arc<RtspPublisher>,
-    raw_tx: &mut Caching<Arc<SharedRb<Heap<InfraredFrameData>>>, true, false>,
+    source: InfraredSource,
+    mounts: &[InfraredMount],
+    warmup_frames: u32,
+    stop: &CaptureStop,
+    frames: &FrameSender<(Instant, InfraredFrame)>,
 ) -> anyhow::Result<()> {
-    let mut infrared_capture: Option<InfraredFrameCapture> = None;
-    let mut iter: Option<InfraredFrameCaptureIter> = None;
+    let mut infrared_capture: Option<InfraredCapture> = None;
+    let mut iter: Option<InfraredCaptureIter> = None;
+    let mut warmup = Warmup::new(warmup_frames);
 
     let mut frame_count = 0;
-    let mut last_log_time = std::time::Instant::now();
+    let mut progress_log = Throttle::new(PROGRESS_LOG_INTERVAL);
+    let mut stall_warning = Throttle::new(STALL_WARN_INTERVAL);
+    let always_on = mounts
+        .iter()
+        .any(|mount| rtsp.is_always_on(mount.capture_source()));
 
-    loop {
-        if !rtsp.is_infra_active() {
+    while !rtsp.should_stop(stop) {
+        if !always_on && !mounts.iter().any(|mount| mount.is_active(&rtsp)) {
             // RTSP infrared capture not active, release Kinect resources.
             if iter.is_some() {
                 iter = None;
@@ -42,17 +335,14 @@ fn infrared_frame_capture(
 
         if iter.is_none() {
             if infrared_capture.is_none() {
-                log::info!("Kinect infrared capture starting...");
-                infrared_capture =
-                    Some(InfraredFrameCapture::new().context("Failed to create infrared capture")?);
+                log::info!("Kinect infrared capture starting ({source:?})...");
+                infrared_capture = Some(InfraredCapture::new(source)?);
             }
 
             if let Some(capture) = infrared_capture.as_ref() {
-                iter = Some(
-                    capture
-                        .iter()
-                        .context("Failed to create infrared capture iterator")?,
-                );
+                iter = Some(capture.iter()?);
+                warmup.restart();
+                stall_warning.reset();
             } else {
                 std::thread::sleep(Duration::from_millis(30));
                 continue;
@@ -62,20 +352,22 @@ fn infrared_frame_capture(
         if let Some(iter) = &mut iter {
             match iter.next() {
                 Some(Ok(data)) => {
+                    if warmup.discard() {
+                        log::trace!("Discarding infrared warmup frame");
+                        continue;
+                    }
                     frame_count += 1;
-
-                    // Log frame capture every 30 frames (approximately once per second at 30fps)
-                    if frame_count % 30 == 0 || last_log_time.elapsed() > Duration::from_secs(5) {
+                    stall_warning.reset();
+                    if progress_log.should_log() {
                         log::debug!(
                             "✅ Captured infrared frame #{}: {}x{}",
                             frame_count,
                             data.width,
                             data.height
                         );
-                        last_log_time = std::time::Instant::now();
                     }
 
-                    if raw_tx.try_push(data).is_err() {
+                    if !frames.push((Instant::now(), data)) {
                         log::error!("❌ Infrared frame buffer full, dropping frame");
                     }
                 }
@@ -84,109 +376,591 @@ fn infrared_frame_capture(
                 }
                 None => {
                     // No new frame available yet - log periodically to show we're still trying
-                    if last_log_time.elapsed() > Duration::from_secs(10) {
+                    if stall_warning.should_log() {
                         log::warn!(
                             "🔍 No infrared frames available from Kinect - is the device connected?"
                         );
-                        last_log_time = std::time::Instant::now();
                     }
                     std::thread::sleep(Duration::from_millis(5));
                 }
             }
         }
     }
+    Ok(())
+}
+
+/// Builds a 64 KiB Lookup Table (LUT) mapping every raw infrared value to a grey byte.
+/// Every pixel then becomes just an index into that table, which is orders of magnitude
+/// faster than doing the full float pipeline per pixel. The raw value is shifted and
+/// measured against the sensor's effective range here too, at no cost per pixel.
+fn build_lut(config: &InfraredConfig, source_scale: f32) -> Box<[u8; 65536]> {
+    let mut lut = Box::new([0u8; 65536]);
+    let source_value_maximum = config.source_value_maximum();
+    for (infrared_point, grey_scale_pixel_byte) in lut.iter_mut().enumerate() {
+        // Since we are displaying the image as a normalized grey scale image, we need to convert from
+        // the u16 data (as provided by the InfraredFrame) to a value from [InfraredOutputValueMinimum, InfraredOutputValueMaximum]
+        // Normalize → clamp → byte conversion:
+        // Gamma goes on the normalized value, values past 1.0 end at the maximum anyway
+        let source_value = (infrared_point >> config.infrared_bit_shift) as f32;
+        let normalized = (source_value / source_value_maximum * source_scale)
+            .min(1.0)
+            .powf(1.0 / config.infrared_gamma);
+        let f = normalized * (1.0 - config.infrared_output_value_minimum)
+            + config.infrared_output_value_minimum;
+        let clamped = config.infrared_output_value_maximum.min(f);
+        *grey_scale_pixel_byte = (clamped * 255.0).round().clamp(0.0, 255.0) as u8;
+    }
+    lut
+}
+
+/// Percentile of the frame used as the "bright region" reference for auto scaling.
+const AUTO_SCALE_PERCENTILE: f32 = 0.95;
+/// Output level (fraction of the maximum) the reference percentile is mapped to.
+const AUTO_SCALE_TARGET: f32 = 0.9;
+/// Relative change in scale required before the LUT is regenerated.
+const AUTO_SCALE_MIN_CHANGE: f32 = 0.05;
+/// How often the frame histogram is sampled.
+const AUTO_SCALE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Adapts the infrared source scale to the scene brightness.
+struct AutoScale {
+    scale: f32,
+    last_update: Option<std::time::Instant>,
+}
+
+impl AutoScale {
+    fn new(initial_scale: f32) -> Self {
+        Self {
+            scale: initial_scale,
+            last_update: None,
+        }
+    }
+
+    /// Samples the frame at most once per [`AUTO_SCALE_INTERVAL`] and returns the new
+    /// scale when it changed enough that the LUT should be regenerated.
+    fn update(&mut self, config: &InfraredConfig, data: &[u16]) -> Option<f32> {
+        if self
+            .last_update
+            .is_some_and(|t| t.elapsed() < AUTO_SCALE_INTERVAL)
+        {
+            return None;
+        }
+        self.last_update = Some(std::time::Instant::now());
+
+        let reference = percentile(data, AUTO_SCALE_PERCENTILE)?;
+        let scale = scale_for_reference(config, reference);
+        if (scale - self.scale).abs() / self.scale > AUTO_SCALE_MIN_CHANGE {
+            self.scale = scale;
+            Some(scale)
+        } else {
+            None
+        }
+    }
+}
+
+/// Returns the approximate `p`-th percentile of `data` using a 256-bin histogram
+/// over the high byte, or None for an empty or all-black frame.
+fn percentile(data: &[u16], p: f32) -> Option<u16> {
+    let mut histogram = [0usize; 256];
+    for &v in data {
+        histogram[(v >> 8) as usize] += 1;
+    }
+    let threshold = (data.len() as f32 * p).ceil() as usize;
+    let mut seen = 0;
+    for (bin, &count) in histogram.iter().enumerate() {
+        seen += count;
+        if seen >= threshold && threshold > 0 {
+            // Use the upper edge of the bin so the reference is never zero.
+            let value = ((bin as u16) << 8) | 0xFF;
+            return if bin == 0 { None } else { Some(value) };
+        }
+    }
+    None
+}
+
+/// Computes the source scale that maps `reference` to [`AUTO_SCALE_TARGET`] of the output range.
+fn scale_for_reference(config: &InfraredConfig, reference: u16) -> f32 {
+    let min = config.infrared_output_value_minimum;
+    let target = AUTO_SCALE_TARGET * config.infrared_output_value_maximum;
+    let normalized = (target - min).max(0.0) / (1.0 - min).max(f32::EPSILON);
+    let reference = (reference >> config.infrared_bit_shift).max(1);
+    normalized * config.source_value_maximum() / reference as f32
+}
+
+/// Averages the last N infrared frames per pixel to reduce frame-to-frame noise.
+struct TemporalAverager {
+    frames: std::collections::VecDeque<Vec<u16>>,
+    sum: Vec<u32>,
+    averaged: Vec<u16>,
+    window: usize,
+}
+
+impl TemporalAverager {
+    fn new(window: usize) -> Self {
+        Self {
+            frames: std::collections::VecDeque::with_capacity(window),
+            sum: Vec::new(),
+            averaged: Vec::new(),
+            window,
+        }
+    }
+
+    /// Adds a frame and returns the per-pixel mean of the frames in the window.
+    fn push(&mut self, frame: &[u16]) -> &[u16] {
+        if self.sum.len() != frame.len() {
+            // Resolution changed (or first frame): restart the window.
+            self.frames.clear();
+            self.sum = vec![0; frame.len()];
+            self.averaged = vec![0; frame.len()];
+        }
+
+        let mut recycled = if self.frames.len() == self.window {
+            let oldest = self.frames.pop_front().unwrap_or_default();
+            for (sum, &old) in self.sum.iter_mut().zip(&oldest) {
+                *sum -= old as u32;
+            }
+            oldest
+        } else {
+            Vec::with_capacity(frame.len())
+        };
+        recycled.clear();
+        recycled.extend_from_slice(frame);
+        for (sum, &new) in self.sum.iter_mut().zip(frame) {
+            *sum += new as u32;
+        }
+        self.frames.push_back(recycled);
+
+        let n = self.frames.len() as u32;
+        for (avg, &sum) in self.averaged.iter_mut().zip(&self.sum) {
+            *avg = (sum / n) as u16;
+        }
+        &self.averaged
+    }
+}
+
+/// Text burned into the /infrared frame by `--overlay-ir-config`.
+fn overlay_text(config: &InfraredConfig, effective_scale: f32) -> String {
+    let mut text = format!(
+        "IR scale={effective_scale:.2} min={:.2} max={:.2} auto={}",
+        config.infrared_output_value_minimum,
+        config.infrared_output_value_maximum,
+        if config.infrared_auto_scale {
+            "on"
+        } else {
+            "off"
+        }
+    );
+    if config.infrared_gamma != 1.0 {
+        text.push_str(&format!(" gamma={:.2}", config.infrared_gamma));
+    }
+    if config.infrared_effective_bits != 16 || config.infrared_bit_shift != 0 {
+        text.push_str(&format!(
+            " bits={}>>{}",
+            config.infrared_effective_bits, config.infrared_bit_shift
+        ));
+    }
+    text
 }
 
-fn infrared_frame_publish(
+/// Bytes of the RGBA frame published for a `width`x`height` infrared frame widened
+/// by `legend_width`, or `None` if `samples` doesn't fill exactly that size or the
+/// size overflows, so frames with corrupt dimensions are skipped.
+fn rgba_frame_len(width: u32, height: u32, legend_width: u32, samples: usize) -> Option<usize> {
+    if frame_byte_len(width, height, 1)? != samples {
+        return None;
+    }
+    frame_byte_len(width.checked_add(legend_width)?, height, 4)
+}
+
+/// The publish step of one captured frame for one mount: averages and tone-maps it
+/// to RGBA in `colormap`, adds the scale legend if `legend` is set, and sends it to
+/// `mount`.
+fn infrared_frame_publisher(
     rtsp: Arc<RtspPublisher>,
-    raw_rx: &mut Caching<Arc<SharedRb<Heap<InfraredFrameData>>>, false, true>,
-) -> anyhow::Result<()> {
-    /// InfraredSourceValueMaximum is the highest value that can be returned in the InfraredFrame.
-    /// It is cast to a float for readability in the visualization code.
-    const INFRARED_SOURCE_VALUE_MAXIMUM: f32 = u16::MAX as f32; // 65535.0
-
-    /// The InfraredOutputValueMinimum value is used to set the lower limit, post processing, of the
-    /// infrared data that we will render.
-    /// Increasing or decreasing this value sets a brightness "wall" either closer or further away.
-    const INFRARED_OUTPUT_VALUE_MINIMUM: f32 = 0.0;
-
-    /// The InfraredOutputValueMaximum value is the upper limit, post processing, of the
-    /// infrared data that we will render.
-    const INFRARED_OUTPUT_VALUE_MAXIMUM: f32 = 1.0;
-
-    /// The value by which the infrared source data will be scaled.
-    const INFRARED_SOURCE_SCALE: f32 = 1.68;
-
-    // Build a 64 KiB Lookup Table (LUT) once.
-    // • once_cell::sync::Lazy ensures that closure runs exactly once (the first time you reference LUT), in a thread-safe way.
-    // • After that, every pixel becomes just an index into that 64 KiB table, which is orders of magnitude faster than doing the full float pipeline per pixel.
-    static LUT: Lazy<[u8; 65536]> = Lazy::new(|| {
-        let mut lut = [0u8; 65536];
-        for (infrared_point, grey_scale_pixel_byte) in lut.iter_mut().enumerate() {
-            // Since we are displaying the image as a normalized grey scale image, we need to convert from
-            // the u16 data (as provided by the InfraredFrame) to a value from [InfraredOutputValueMinimum, InfraredOutputValueMaximum]
-            // Normalize → clamp → byte conversion:
-            let f = (infrared_point as f32 / INFRARED_SOURCE_VALUE_MAXIMUM * INFRARED_SOURCE_SCALE)
-                * (1.0 - INFRARED_OUTPUT_VALUE_MINIMUM)
-                + INFRARED_OUTPUT_VALUE_MINIMUM;
-            let clamped = INFRARED_OUTPUT_VALUE_MAXIMUM.min(f);
-            *grey_scale_pixel_byte = (clamped * 255.0).round().clamp(0.0, 255.0) as u8;
-        }
-        lut
+    config: InfraredConfig,
+    mount: InfraredMount,
+    colormap: IrColormap,
+    legend: bool,
+) -> impl FnMut(Instant, &InfraredFrame) + Send + 'static {
+    let mut lut = build_lut(&config, config.infrared_source_scale);
+    let palette = colormap.palette();
+    // Rendered for the current LUT, so only redrawn when the scale changes
+    let mut legend_data = legend.then(|| render_legend(&lut, &palette, INFRARED_HEIGHT));
+    let mut auto_scale = AutoScale::new(config.infrared_source_scale);
+    if config.infrared_auto_scale {
+        log::info!("Infrared auto scale enabled (static infrared_source_scale is ignored)");
+    }
+    mount.set_overlay_text(&rtsp, &overlay_text(&config, config.infrared_source_scale));
+
+    let averaging_frames = config.temporal_average_frames();
+    let mut averager = (averaging_frames > 1).then(|| {
+        log::info!("Infrared temporal averaging over {averaging_frames} frames");
+        TemporalAverager::new(averaging_frames)
     });
 
     // pre‐allocate a single RGBA buffer. Kinect is always the same resolution,
     // so after the first frame we never re‐resize beyond the fixed frame size.
     let mut rgba_data = Vec::new();
+    let mut malformed_frames = 0u64;
 
-    loop {
-        if let Some(infrared_frame) = raw_rx.try_pop() {
-            if infrared_frame.data.is_empty() {
-                log::debug!("Skipping empty infrared frame");
-                continue; // Skip empty frames
+    move |captured, infrared_frame| {
+        if infrared_frame.data.is_empty() {
+            log::debug!("Skipping empty infrared frame");
+            return; // Skip empty frames
+        }
+        let legend_width = if legend_data.is_some() {
+            IR_LEGEND_WIDTH
+        } else {
+            0
+        };
+        let Some(bytes_len) = rgba_frame_len(
+            infrared_frame.width,
+            infrared_frame.height,
+            legend_width,
+            infrared_frame.data.len(),
+        ) else {
+            malformed_frames += 1;
+            if malformed_frames % 100 == 1 {
+                log::warn!(
+                    "Infrared frame of {} samples doesn't match {}x{}, dropped {malformed_frames} frames so far",
+                    infrared_frame.data.len(),
+                    infrared_frame.width,
+                    infrared_frame.height
+                );
             }
+            return;
+        };
+        // The untouched sensor values, before averaging and tone mapping
+        if mount == InfraredMount::Main {
+            rtsp.send_infra_raw(
+                infrared_frame.width,
+                infrared_frame.height,
+                &infrared_frame.data,
+            );
+        }
 
-            let pixel_count = (infrared_frame.width * infrared_frame.height) as usize;
-            let bytes_len = pixel_count * 4;
-            if rgba_data.len() != bytes_len {
-                rgba_data.resize(bytes_len, 0);
+        let ir_data: &[u16] = match averager.as_mut() {
+            Some(averager) => averager.push(&infrared_frame.data),
+            None => &infrared_frame.data,
+        };
+
+        if config.infrared_auto_scale
+            && let Some(scale) = auto_scale.update(&config, ir_data)
+        {
+            log::debug!("Infrared auto scale adjusted to {scale:.2}, regenerating LUT");
+            lut = build_lut(&config, scale);
+            if legend {
+                legend_data = Some(render_legend(&lut, &palette, INFRARED_HEIGHT));
             }
+            mount.set_overlay_text(&rtsp, &overlay_text(&config, scale));
+        }
 
-            // Convert infrared data to RGBA using the LUT and push to RTSP
-            for (chunk, &pt) in rgba_data
-                .chunks_exact_mut(4)
-                .zip(infrared_frame.data.iter())
-            {
-                let i = LUT[pt as usize];
-                chunk[0] = i;
-                chunk[1] = i;
-                chunk[2] = i;
-                chunk[3] = 255;
+        let width = infrared_frame.width + legend_width;
+        if rgba_data.len() != bytes_len {
+            rgba_data.resize(bytes_len, 0);
+        }
+
+        // Convert infrared data to RGBA using the LUT and palette, row by row so the
+        // legend margin can follow each row, and push to RTSP
+        let ir_rows = ir_data.chunks_exact(infrared_frame.width as usize);
+        let out_rows = rgba_data.chunks_exact_mut(width as usize * 4);
+        for (y, (out_row, ir_row)) in out_rows.zip(ir_rows).enumerate() {
+            let (picture, margin) = out_row.split_at_mut(infrared_frame.width as usize * 4);
+            for (chunk, &pt) in picture.chunks_exact_mut(4).zip(ir_row) {
+                let [r, g, b] = palette[lut[pt as usize] as usize];
+                chunk.copy_from_slice(&[b, g, r, 255]);
+            }
+            if let Some(legend) = &legend_data {
+                let row_len = legend_width as usize * 4;
+                let row = y.min(INFRARED_HEIGHT as usize - 1) * row_len;
+                margin.copy_from_slice(&legend[row..row + row_len]);
             }
-            rtsp.send_infra_bgra(infrared_frame.width, infrared_frame.height, &rgba_data);
-        } else {
-            // No frame is available, sleep briefly to avoid busy waiting
-            std::thread::sleep(Duration::from_millis(30));
         }
+        mount.send(&rtsp, captured, width, infrared_frame.height, &rgba_data);
     }
 }
 
-pub fn spawn_infra_pipeline(rtsp: Arc<RtspPublisher>) {
-    let raw_ring_buffer = HeapRb::<InfraredFrameData>::new(32);
-    let (mut raw_tx, mut raw_rx) = raw_ring_buffer.split();
+/// What an infrared pipeline captures and how every one of its mounts renders it.
+#[derive(Debug, Clone, Copy)]
+pub struct InfraredPipelineSettings {
+    pub source: InfraredSource,
+    pub colormap: IrColormap,
+    /// Draw the colormap legend next to the picture (`--ir-legend`).
+    pub legend: bool,
+    /// Frames discarded after the capture starts or resumes.
+    pub warmup_frames: u32,
+    /// Most frames published per second, per mount.
+    pub publish_fps: u32,
+}
 
-    let rtsp_clone = rtsp.clone();
-    // Infrared frame capture thread
-    std::thread::spawn(move || {
-        if let Err(e) = infrared_frame_capture(rtsp_clone, &mut raw_tx) {
-            log::error!("Error capturing infrared frames: {e}");
+/// Starts one capture of `settings.source` feeding every mount of `mounts`, each
+/// tone-mapped with its own config; the first mount names the threads.
+pub fn spawn_infra_pipeline(
+    rtsp: Arc<RtspPublisher>,
+    mounts: Vec<(InfraredMount, InfraredConfig)>,
+    settings: InfraredPipelineSettings,
+    handoff: FrameHandoff,
+) -> Vec<JoinHandle<()>> {
+    let InfraredPipelineSettings {
+        source,
+        colormap,
+        legend,
+        warmup_frames,
+        publish_fps,
+    } = settings;
+    let mount = mounts[0].0;
+    let prefix = mount.thread_prefix();
+    let capture_mounts: Vec<InfraredMount> = mounts.iter().map(|(mount, _)| *mount).collect();
+    let mut publishers: Vec<_> = mounts
+        .into_iter()
+        .map(|(mount, config)| {
+            let publish = infrared_frame_publisher(rtsp.clone(), config, mount, colormap, legend);
+            (mount, PublishRateCap::new(publish_fps), publish)
+        })
+        .collect();
+    if publish_fps < KINECT_FPS {
+        log::info!("Infrared publish rate capped at {publish_fps} fps per mount ({prefix})");
+    }
+    let publish = {
+        let rtsp = rtsp.clone();
+        move |(captured, infrared_frame): (Instant, InfraredFrame)| {
+            // A mount without clients skips the work, the capture runs for the others
+            for (mount, rate_cap, publish) in &mut publishers {
+                rate_cap.set_fps(rtsp.governed_fps(publish_fps));
+                if mount.is_active(&rtsp) && rate_cap.admit() {
+                    publish(captured, &infrared_frame);
+                }
+            }
         }
-    });
+    };
+    let stop = rtsp.capture_stop(mount.capture_source());
+    let mut threads = Vec::new();
+    let frames_tx = match handoff {
+        FrameHandoff::Inline => inline_sender(publish),
+        FrameHandoff::Queue(queue) => {
+            let (frames_tx, frames_rx) = frame_queue(32, queue.full);
+            let level = match mount {
+                InfraredMount::Long => rtsp.metrics().infra_long_queue.clone(),
+                InfraredMount::Main | InfraredMount::Profile(_) => {
+                    rtsp.metrics().infra_queue.clone()
+                }
+            };
+            let frames_tx = frames_tx.monitor(prefix, level, queue.high_water_percent);
+            let rtsp = rtsp.clone();
+            let stop = stop.clone();
+            // Infrared frame publish thread
+            let thread = std::thread::Builder::new()
+                .name(format!("{prefix}-publish"))
+                .spawn(move || {
+                    pin_current_thread();
+                    lower_current_thread();
+                    let wakeups = &rtsp.metrics().infra_publish_wakeups;
+                    frames_rx.for_each(|| rtsp.should_stop(&stop), wakeups, publish);
+                })
+                .expect("Failed to spawn infrared publish thread");
+            threads.push(thread);
+            frames_tx
+        }
+    };
+
+    // Infrared frame capture thread
+    let capture = std::thread::Builder::new()
+        .name(format!("{prefix}-capture"))
+        .spawn(move || {
+            pin_current_thread();
+            lower_current_thread();
+            if let Err(e) = infrared_frame_capture(
+                rtsp.clone(),
+                source,
+                &capture_mounts,
+                warmup_frames,
+                &stop,
+                &frames_tx,
+            ) {
+                log::error!("Error capturing infrared frames ({source:?}): {e}");
+                rtsp.set_capture_failed(mount.capture_source());
+            }
+        })
+        .expect("Failed to spawn infrared capture thread");
+    threads.push(capture);
+
+    threads
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_infra_scale_must_give_even_size() {
+        assert_eq!(scaled_infra_size(1.0), Ok((512, 424)));
+        assert_eq!(scaled_infra_size(2.0), Ok((1024, 848)));
+        assert_eq!(scaled_infra_size(2.5), Ok((1280, 1060)));
+        assert!(scaled_infra_size(1.1).is_err());
+        assert!(scaled_infra_size(0.0).is_err());
+        assert!(scaled_infra_size(8.0).is_err());
+        assert_eq!(parse_infra_scale("1.5"), Ok(1.5));
+        assert!(parse_infra_scale("x").is_err());
+    }
+
+    #[test]
+    fn test_default_lut_matches_static_scale() {
+        let config = InfraredConfig::default();
+        let lut = build_lut(&config, config.infrared_source_scale);
+        assert_eq!(lut[0], 0);
+        assert_eq!(lut[65535], 255);
+        // 1.68x scale saturates well before the top of the range
+        assert_eq!(lut[40000], 255);
+    }
+
+    #[test]
+    fn test_gamma_lifts_midtones() {
+        let linear = InfraredConfig {
+            infrared_source_scale: 1.0,
+            ..Default::default()
+        };
+        let gamma = InfraredConfig {
+            infrared_gamma: 2.0,
+            ..linear.clone()
+        };
+        let (linear_lut, gamma_lut) = (build_lut(&linear, 1.0), build_lut(&gamma, 1.0));
+        assert_eq!(linear_lut[16384], 64);
+        // sqrt(0.25) = 0.5
+        assert_eq!(gamma_lut[16384], 128);
+        assert_eq!((gamma_lut[0], gamma_lut[65535]), (0, 255));
+    }
+
+    #[test]
+    fn test_effective_bits_narrow_the_domain() {
+        let config = InfraredConfig {
+            infrared_source_scale: 1.0,
+            infrared_effective_bits: 12,
+            ..Default::default()
+        };
+        let lut = build_lut(&config, 1.0);
+        // 2048 is about half of the 12-bit range, anything past 4095 is saturated
+        assert_eq!((lut[2048], lut[4095], lut[40000]), (128, 255, 255));
+
+        let shifted = InfraredConfig {
+            infrared_bit_shift: 4,
+            ..config.clone()
+        };
+        let lut = build_lut(&shifted, 1.0);
+        // The low 4 bits are dropped, 16 raw counts per step
+        assert_eq!((lut[15], lut[16]), (0, lut[31]));
+        assert_eq!(lut[65535], 255);
+        assert_eq!(
+            overlay_text(&shifted, 1.0),
+            "IR scale=1.00 min=0.00 max=1.00 auto=off bits=12>>4"
+        );
 
-    // Infrared frame publish thread
-    std::thread::spawn(move || {
-        if let Err(e) = infrared_frame_publish(rtsp, &mut raw_rx) {
-            log::error!("Error publishing infrared frames: {e}");
+        // Auto scale maps the reference to the target in the narrowed domain too
+        let scale = scale_for_reference(&shifted, 0x8000);
+        let expected = (AUTO_SCALE_TARGET * 255.0).round() as u8;
+        assert!(build_lut(&shifted, scale)[0x8000].abs_diff(expected) <= 1);
+    }
+
+    #[test]
+    fn test_overlay_text_shows_effective_values() {
+        let config = InfraredConfig {
+            infrared_auto_scale: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            overlay_text(&config, 2.5),
+            "IR scale=2.50 min=0.00 max=1.00 auto=on"
+        );
+    }
+
+    #[test]
+    fn test_temporal_average_window() {
+        let mut averager = TemporalAverager::new(2);
+        assert_eq!(averager.push(&[10, 20]), &[10, 20]);
+        assert_eq!(averager.push(&[30, 40]), &[20, 30]);
+        // The first frame drops out of the window
+        assert_eq!(averager.push(&[50, 60]), &[40, 50]);
+    }
+
+    #[test]
+    fn test_temporal_average_resets_on_size_change() {
+        let mut averager = TemporalAverager::new(4);
+        averager.push(&[100, 100]);
+        assert_eq!(averager.push(&[7, 8, 9]), &[7, 8, 9]);
+    }
+
+    #[test]
+    fn test_percentile_empty_and_black() {
+        assert_eq!(percentile(&[], 0.95), None);
+        assert_eq!(percentile(&[0u16; 100], 0.95), None);
+    }
+
+    #[test]
+    fn test_percentile_picks_bright_tail() {
+        let mut data = vec![0x1000u16; 90];
+        data.extend([0x8000u16; 10]);
+        assert_eq!(percentile(&data, 0.95), Some(0x80FF));
+        assert_eq!(percentile(&data, 0.5), Some(0x10FF));
+    }
+
+    #[test]
+    fn test_scale_maps_reference_to_target() {
+        let config = InfraredConfig::default();
+        let reference = 0x4000;
+        let scale = scale_for_reference(&config, reference);
+        let lut = build_lut(&config, scale);
+        let expected = (AUTO_SCALE_TARGET * 255.0).round() as u8;
+        assert!(lut[reference as usize].abs_diff(expected) <= 1);
+    }
+
+    #[test]
+    fn test_palettes_span_black_to_white() {
+        for colormap in [IrColormap::Gray, IrColormap::Ironbow] {
+            let palette = colormap.palette();
+            assert_eq!((palette[0], palette[255]), ([0; 3], [255; 3]));
         }
-    });
+        let ironbow = IrColormap::Ironbow.palette();
+        // Warm midtones, red ahead of blue
+        assert!(ironbow[160][0] > ironbow[160][2]);
+    }
+
+    #[test]
+    fn test_legend_bar_and_labels() {
+        let config = InfraredConfig::default();
+        let lut = build_lut(&config, config.infrared_source_scale);
+        let palette = IrColormap::Ironbow.palette();
+        let legend = render_legend(&lut, &palette, INFRARED_HEIGHT);
+        let width = IR_LEGEND_WIDTH as usize;
+        assert_eq!(legend.len(), width * INFRARED_HEIGHT as usize * 4);
+
+        let pixel = |x: usize, y: usize| &legend[(y * width + x) * 4..(y * width + x) * 4 + 4];
+        let x = width / 2;
+        assert_eq!(pixel(x, LABEL_HEIGHT), [255, 255, 255, 255]);
+        let last_bar_row = INFRARED_HEIGHT as usize - LABEL_HEIGHT - 1;
+        assert_eq!(pixel(x, last_bar_row), [0, 0, 0, 255]);
+        // Both labels left some white pixels
+        let white_rows = |rows: std::ops::Range<usize>| {
+            rows.flat_map(|y| (0..width).map(move |x| (x, y)))
+                .filter(|&(x, y)| pixel(x, y) == [255, 255, 255, 255])
+                .count()
+        };
+        assert!(white_rows(0..LABEL_HEIGHT) > 0);
+        assert!(white_rows(last_bar_row + 1..INFRARED_HEIGHT as usize) > 0);
+    }
+
+    #[test]
+    fn test_rgba_frame_len_rejects_corrupt_dimensions() {
+        assert_eq!(rgba_frame_len(512, 424, 0, 512 * 424), Some(512 * 424 * 4));
+        assert_eq!(
+            rgba_frame_len(512, 424, IR_LEGEND_WIDTH, 512 * 424),
+            Some((512 + IR_LEGEND_WIDTH as usize) * 424 * 4)
+        );
+        // Samples that don't fill the frame, a zero width, and sizes that overflow
+        assert_eq!(rgba_frame_len(512, 424, 0, 512 * 423), None);
+        assert_eq!(rgba_frame_len(0, 424, 0, 512 * 424), None);
+        assert_eq!(rgba_frame_len(u32::MAX, u32::MAX, 0, 512 * 424), None);
+        assert_eq!(
+            rgba_frame_len(u32::MAX, 1, IR_LEGEND_WIDTH, u32::MAX as usize),
+            None
+        );
+    }
 }
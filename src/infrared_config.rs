@@ -0,0 +1,541 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+/// Tunables for mapping the raw 16-bit infrared data to an 8-bit grey scale image.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InfraredConfig {
+    /// The value by which the infrared source data will be scaled.
+    pub infrared_source_scale: f32,
+    /// The lower limit, post processing, of the infrared data that we will render.
+    /// Increasing or decreasing this value sets a brightness "wall" either closer or further away.
+    pub infrared_output_value_minimum: f32,
+    /// The upper limit, post processing, of the infrared data that we will render.
+    pub infrared_output_value_maximum: f32,
+    /// When true, the scale is derived from the scene and `infrared_source_scale`
+    /// is only used as the starting value.
+    pub infrared_auto_scale: bool,
+    /// Number of recent frames averaged per pixel before mapping, to reduce flicker.
+    /// 1 disables averaging; values are clamped to [`MAX_TEMPORAL_AVERAGE`].
+    pub infrared_temporal_average: u8,
+    /// Gamma applied to the normalized value before the byte conversion; above 1.0
+    /// brightens the midtones, below 1.0 darkens them, 1.0 is linear.
+    pub infrared_gamma: f32,
+    /// Bits of the raw value the sensor actually uses, after the shift: the mapping
+    /// spans 0 to `2^bits - 1` instead of the full u16 range.
+    pub infrared_effective_bits: u8,
+    /// Right shift applied to the raw value before the mapping, to drop unused low bits.
+    pub infrared_bit_shift: u8,
+    /// Alternate mappings, each served on its own infrared mount.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub infrared_profiles: Vec<InfraredProfile>,
+}
+
+/// A named alternate infrared mapping, served on `<--mount-infrared>-<name>`, e.g.
+/// `/infrared-flat`. Fields it leaves out take their defaults, not the values of
+/// the main config.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InfraredProfile {
+    pub name: String,
+    #[serde(flatten)]
+    pub config: InfraredConfig,
+}
+
+/// Upper bound for `infrared_temporal_average`, to bound memory and latency.
+pub const MAX_TEMPORAL_AVERAGE: u8 = 8;
+
+/// Accepted range of `infrared_effective_bits`.
+pub const EFFECTIVE_BITS_RANGE: std::ops::RangeInclusive<u8> = 10..=16;
+
+impl Default for InfraredConfig {
+    fn default() -> Self {
+        Self {
+            infrared_source_scale: 1.68,
+            infrared_output_value_minimum: 0.0,
+            infrared_output_value_maximum: 1.0,
+            infrared_auto_scale: false,
+            infrared_temporal_average: 1,
+            infrared_gamma: 1.0,
+            infrared_effective_bits: 16,
+            infrared_bit_shift: 0,
+            infrared_profiles: Vec::new(),
+        }
+    }
+}
+
+impl InfraredConfig {
+    /// Checks that the values are usable for building the LUT.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if !(self.infrared_source_scale.is_finite() && self.infrared_source_scale > 0.0) {
+            anyhow::bail!(
+                "infrared_source_scale must be a positive number, got {}",
+                self.infrared_source_scale
+            );
+        }
+        let min = self.infrared_output_value_minimum;
+        let max = self.infrared_output_value_maximum;
+        if !(0.0..=1.0).contains(&min) || !(0.0..=1.0).contains(&max) {
+            anyhow::bail!(
+                "infrared_output_value_minimum/maximum must be within [0, 1], got {min}/{max}"
+            );
+        }
+        if min >= max {
+            anyhow::bail!(
+                "infrared_output_value_minimum ({min}) must be lower than infrared_output_value_maximum ({max})"
+            );
+        }
+        if !(self.infrared_gamma.is_finite() && self.infrared_gamma > 0.0) {
+            anyhow::bail!(
+                "infrared_gamma must be a positive number, got {}",
+                self.infrared_gamma
+            );
+        }
+        if self.infrared_temporal_average == 0 {
+            anyhow::bail!("infrared_temporal_average must be at least 1 (1 disables averaging)");
+        }
+        let (bits, shift) = (self.infrared_effective_bits, self.infrared_bit_shift);
+        if !EFFECTIVE_BITS_RANGE.contains(&bits) {
+            anyhow::bail!("infrared_effective_bits must be within [10, 16], got {bits}");
+        }
+        if u32::from(bits) + u32::from(shift) > 16 {
+            anyhow::bail!(
+                "infrared_effective_bits ({bits}) plus infrared_bit_shift ({shift}) must be at most 16"
+            );
+        }
+        for (i, profile) in self.infrared_profiles.iter().enumerate() {
+            let name = &profile.name;
+            let valid_name = !name.is_empty()
+                && name
+                    .bytes()
+                    .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-');
+            if !valid_name {
+                anyhow::bail!(
+                    "infrared profile name '{name}' must be lowercase letters, digits and '-'"
+                );
+            }
+            if name == "long" {
+                anyhow::bail!(
+                    "infrared profile name 'long' is taken by the --infrared-long stream"
+                );
+            }
+            if self.infrared_profiles[..i].iter().any(|p| &p.name == name) {
+                anyhow::bail!("infrared profile '{name}' is defined twice");
+            }
+            if !profile.config.infrared_profiles.is_empty() {
+                anyhow::bail!("infrared profile '{name}' can't have profiles of its own");
+            }
+            profile
+                .config
+                .validate()
+                .with_context(|| format!("infrared profile '{name}'"))?;
+        }
+        Ok(())
+    }
+
+    /// Highest raw value after the shift, which the mapping takes as full scale.
+    pub fn source_value_maximum(&self) -> f32 {
+        ((1u32 << self.infrared_effective_bits.min(16)) - 1) as f32
+    }
+
+    /// Effective number of frames to average, clamped to [1, MAX_TEMPORAL_AVERAGE].
+    pub fn temporal_average_frames(&self) -> usize {
+        self.infrared_temporal_average
+            .clamp(1, MAX_TEMPORAL_AVERAGE) as usize
+    }
+}
+
+/// Loads the infrared config from a JSON file and keeps it resilient to bad edits.
+///
+/// Every successful load is mirrored to `<path>.bak`. When the file is missing or
+/// invalid, the backup is restored if it's usable, otherwise the default config is
+/// written out; either way startup continues with a warning, also when the file
+/// can't be written.
+#[derive(Debug)]
+pub struct InfraredConfigManager {
+    config: InfraredConfig,
+}
+
+impl InfraredConfigManager {
+    /// Default location of the infrared config file.
+    pub const DEFAULT_PATH: &'static str = "infrared_config.json";
+
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let backup = backup_path(&path);
+
+        let config = match load(&path) {
+            Ok(config) => {
+                if let Err(e) = save(&backup, &config) {
+                    log::warn!(
+                        "Failed to back up infrared config to {}: {e:#}",
+                        backup.display()
+                    );
+                }
+                log::info!("Loaded infrared config from {}", path.display());
+                config
+            }
+            Err(e) => {
+                log::warn!("⚠️ Infrared config {} unusable: {e:#}", path.display());
+                let config = match load(&backup) {
+                    Ok(config) => {
+                        log::warn!(
+                            "Restoring last-good infrared config from {}",
+                            backup.display()
+                        );
+                        config
+                    }
+                    Err(_) => {
+                        log::warn!("Writing default infrared config to {}", path.display());
+                        InfraredConfig::default()
+                    }
+                };
+                // A read-only location shouldn't stop the server, the config is in memory
+                if let Err(e) = save(&path, &config) {
+                    log::warn!(
+                        "Failed to write infrared config to {}, running with it unsaved: {e:#}",
+                        path.display()
+                    );
+                }
+                config
+            }
+        };
+
+        Self { config }
+    }
+
+    /// The currently effective config.
+    pub fn config(&self) -> &InfraredConfig {
+        &self.config
+    }
+}
+
+/// Explanation of each field, written as `_help` into generated config files.
+/// serde ignores the unknown `_help` key when the file is loaded back.
+const FIELD_HELP: &[(&str, &str)] = &[
+    (
+        "infrared_source_scale",
+        "Multiplier applied to the raw 16-bit IR value before mapping to grey. Range: > 0, typical 1.0-4.0. Higher = brighter image, more saturation.",
+    ),
+    (
+        "infrared_output_value_minimum",
+        "Lowest output brightness as a fraction of white. Range: [0, 1), must be below the maximum. Raising it lifts the shadows (a brightness 'wall').",
+    ),
+    (
+        "infrared_output_value_maximum",
+        "Highest output brightness as a fraction of white. Range: (0, 1], must be above the minimum. Lowering it caps highlights.",
+    ),
+    (
+        "infrared_auto_scale",
+        "true/false. When true the scale adapts to the scene about once a second (95th percentile mapped near white); infrared_source_scale is then only the starting value.",
+    ),
+    (
+        "infrared_temporal_average",
+        "Number of recent frames averaged per pixel to reduce flicker in static scenes. Range: 1-8, 1 disables it. Higher = smoother but more motion blur and latency.",
+    ),
+    (
+        "infrared_gamma",
+        "Gamma of the grey mapping; the normalized value is raised to 1/gamma. Range: > 0, typical 0.5-2.5, 1.0 = linear. Above 1 brings out detail in dark areas, below 1 in bright ones.",
+    ),
+    (
+        "infrared_effective_bits",
+        "Bits of the raw IR value the sensor really uses; the mapping spans 0 to 2^bits - 1 instead of the full 16-bit range. Range: 10-16, 16 = full range. Lower = brighter image with the same scale.",
+    ),
+    (
+        "infrared_bit_shift",
+        "Right shift of the raw IR value before the mapping, to drop noisy or unused low bits. Range: 0-6, effective bits + shift at most 16. 0 = no shift.",
+    ),
+    (
+        "infrared_profiles",
+        "Optional array of alternate mappings, e.g. [{\"name\": \"flat\", \"infrared_gamma\": 0.7}]. Each is served on its own mount, /infrared-<name>; fields left out take the defaults above. Names: lowercase letters, digits and '-'.",
+    ),
+];
+
+/// Writes the default config, with an explanation of every field, to `path`.
+/// Refuses to replace an existing file unless `force` is set.
+pub fn write_default_config(path: &Path, force: bool) -> anyhow::Result<()> {
+    if path.exists() && !force {
+        anyhow::bail!(
+            "{} already exists, pass --force to overwrite it",
+            path.display()
+        );
+    }
+
+    let mut value = serde_json::to_value(InfraredConfig::default())?;
+    let help: serde_json::Map<String, serde_json::Value> = FIELD_HELP
+        .iter()
+        .map(|(field, text)| (field.to_string(), serde_json::Value::from(*text)))
+        .collect();
+    if let Some(object) = value.as_object_mut() {
+        object.insert("_help".to_string(), serde_json::Value::Object(help));
+    }
+
+    let text = serde_json::to_string_pretty(&value)?;
+    fs::write(path, text).with_context(|| format!("writing {}", path.display()))
+}
+
+fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".bak");
+    PathBuf::from(name)
+}
+
+fn load(path: &Path) -> anyhow::Result<InfraredConfig> {
+    let text = fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    let config: InfraredConfig =
+        serde_json::from_str(&text).with_context(|| format!("parsing {}", path.display()))?;
+    config.validate()?;
+    Ok(config)
+}
+
+fn save(path: &Path, config: &InfraredConfig) -> anyhow::Result<()> {
+    let text = serde_json::to_string_pretty(config)?;
+    fs::write(path, text).with_context(|| format!("writing {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_config_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("kinect-rtsp-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("infrared_config.json")
+    }
+
+    #[test]
+    fn test_missing_file_writes_default() {
+        let path = temp_config_path("missing");
+        let manager = InfraredConfigManager::new(&path);
+        assert_eq!(manager.config(), &InfraredConfig::default());
+        assert_eq!(load(&path).unwrap(), InfraredConfig::default());
+    }
+
+    #[test]
+    fn test_unwritable_file_falls_back_to_default() {
+        let path = temp_config_path("unwritable").join("missing-dir/infrared_config.json");
+        let manager = InfraredConfigManager::new(&path);
+        assert_eq!(manager.config(), &InfraredConfig::default());
+    }
+
+    #[test]
+    fn test_valid_file_is_backed_up() {
+        let path = temp_config_path("valid");
+        let config = InfraredConfig {
+            infrared_source_scale: 2.5,
+            ..Default::default()
+        };
+        save(&path, &config).unwrap();
+        let manager = InfraredConfigManager::new(&path);
+        assert_eq!(manager.config(), &config);
+        assert_eq!(load(&backup_path(&path)).unwrap(), config);
+    }
+
+    #[test]
+    fn test_invalid_file_restores_backup() {
+        let path = temp_config_path("restore");
+        let config = InfraredConfig {
+            infrared_source_scale: 3.0,
+            ..Default::default()
+        };
+        save(&path, &config).unwrap();
+        InfraredConfigManager::new(&path);
+
+        fs::write(&path, "{ not json").unwrap();
+        let manager = InfraredConfigManager::new(&path);
+        assert_eq!(manager.config(), &config);
+        assert_eq!(load(&path).unwrap(), config);
+    }
+
+    #[test]
+    fn test_init_config_round_trips_and_refuses_overwrite() {
+        let path = temp_config_path("init");
+        write_default_config(&path, false).unwrap();
+        assert_eq!(load(&path).unwrap(), InfraredConfig::default());
+        assert!(write_default_config(&path, false).is_err());
+        write_default_config(&path, true).unwrap();
+    }
+
+    #[test]
+    fn test_init_config_documents_every_field() {
+        let value = serde_json::to_value(InfraredConfig::default()).unwrap();
+        for field in value.as_object().unwrap().keys() {
+            assert!(
+                FIELD_HELP.iter().any(|(name, _)| name == field),
+                "missing help for {field}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_profiles_parse_and_names_are_checked() {
+        let config: InfraredConfig = serde_json::from_str(
+            r#"{"infrared_gamma": 1.2, "infrared_profiles": [
+                {"name": "flat", "infrared_gamma": 0.7},
+                {"name": "high-contrast", "infrared_source_scale": 3.0}
+            ]}"#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.infrared_profiles.len(), 2);
+        assert_eq!(config.infrared_profiles[0].config.infrared_gamma, 0.7);
+        // Left-out fields are defaults, not the main config's
+        assert_eq!(config.infrared_profiles[1].config.infrared_gamma, 1.0);
+
+        for name in ["", "High", "long", "a/b"] {
+            let mut config = config.clone();
+            config.infrared_profiles[0].name = name.to_string();
+            assert!(config.validate().is_err(), "accepted '{name}'");
+        }
+        let mut twice = config.clone();
+        twice.infrared_profiles[1].name = "flat".to_string();
+        assert!(twice.validate().is_err());
+    }
+
+    #[test]
+    fn test_out_of_range_values_are_rejected() {
+        let config = InfraredConfig {
+            infrared_output_value_minimum: 0.8,
+            infrared_output_value_maximum: 0.2,
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+
+        let config = InfraredConfig {
+            infrared_gamma: 0.0,
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    /// The full error chain of validating `json`, which must parse.
+    fn validation_error(json: &str) -> String {
+        let config: InfraredConfig = serde_json::from_str(json).unwrap();
+        format!("{:#}", config.validate().unwrap_err())
+    }
+
+    #[test]
+    fn test_every_rejection_has_its_message() {
+        let cases = [
+            (
+                r#"{"infrared_source_scale": 0.0}"#,
+                "infrared_source_scale must be a positive number, got 0",
+            ),
+            (
+                r#"{"infrared_source_scale": -1.5}"#,
+                "infrared_source_scale must be a positive number, got -1.5",
+            ),
+            (
+                r#"{"infrared_output_value_minimum": -0.1}"#,
+                "infrared_output_value_minimum/maximum must be within [0, 1], got -0.1/1",
+            ),
+            (
+                r#"{"infrared_output_value_maximum": 1.5}"#,
+                "infrared_output_value_minimum/maximum must be within [0, 1], got 0/1.5",
+            ),
+            (
+                r#"{"infrared_output_value_minimum": 0.5, "infrared_output_value_maximum": 0.5}"#,
+                "infrared_output_value_minimum (0.5) must be lower than infrared_output_value_maximum (0.5)",
+            ),
+            (
+                r#"{"infrared_gamma": -2.0}"#,
+                "infrared_gamma must be a positive number, got -2",
+            ),
+            (
+                r#"{"infrared_temporal_average": 0}"#,
+                "infrared_temporal_average must be at least 1 (1 disables averaging)",
+            ),
+            (
+                r#"{"infrared_effective_bits": 9}"#,
+                "infrared_effective_bits must be within [10, 16], got 9",
+            ),
+            (
+                r#"{"infrared_effective_bits": 12, "infrared_bit_shift": 5}"#,
+                "infrared_effective_bits (12) plus infrared_bit_shift (5) must be at most 16",
+            ),
+            (
+                r#"{"infrared_profiles": [{"name": "Flat"}]}"#,
+                "infrared profile name 'Flat' must be lowercase letters, digits and '-'",
+            ),
+            (
+                r#"{"infrared_profiles": [{"name": "long"}]}"#,
+                "infrared profile name 'long' is taken by the --infrared-long stream",
+            ),
+            (
+                r#"{"infrared_profiles": [{"name": "flat"}, {"name": "flat"}]}"#,
+                "infrared profile 'flat' is defined twice",
+            ),
+            (
+                r#"{"infrared_profiles": [{"name": "flat", "infrared_profiles": [{"name": "x"}]}]}"#,
+                "infrared profile 'flat' can't have profiles of its own",
+            ),
+            (
+                r#"{"infrared_profiles": [{"name": "flat", "infrared_gamma": 0.0}]}"#,
+                "infrared profile 'flat': infrared_gamma must be a positive number, got 0",
+            ),
+        ];
+        for (json, message) in cases {
+            assert_eq!(validation_error(json), message, "for {json}");
+        }
+    }
+
+    #[test]
+    fn test_range_bounds_are_inclusive() {
+        let config: InfraredConfig = serde_json::from_str(
+            r#"{"infrared_output_value_minimum": 0.0, "infrared_output_value_maximum": 1.0,
+                "infrared_source_scale": 0.001, "infrared_gamma": 0.001,
+                "infrared_temporal_average": 255,
+                "infrared_effective_bits": 10, "infrared_bit_shift": 6}"#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+        assert_eq!(
+            config.temporal_average_frames(),
+            MAX_TEMPORAL_AVERAGE as usize
+        );
+    }
+
+    #[test]
+    fn test_valid_config_round_trips() {
+        let config = InfraredConfig {
+            infrared_source_scale: 2.25,
+            infrared_output_value_minimum: 0.1,
+            infrared_output_value_maximum: 0.9,
+            infrared_auto_scale: true,
+            infrared_temporal_average: 4,
+            infrared_gamma: 1.8,
+            infrared_effective_bits: 12,
+            infrared_bit_shift: 2,
+            infrared_profiles: vec![InfraredProfile {
+                name: "flat".to_string(),
+                config: InfraredConfig {
+                    infrared_gamma: 0.7,
+                    ..Default::default()
+                },
+            }],
+        };
+        config.validate().unwrap();
+        let text = serde_json::to_string(&config).unwrap();
+        let parsed: InfraredConfig = serde_json::from_str(&text).unwrap();
+        assert_eq!(parsed, config);
+        parsed.validate().unwrap();
+    }
+
+    #[test]
+    fn test_load_rejects_invalid_and_unparsable_files() {
+        let path = temp_config_path("load-invalid");
+        fs::write(&path, r#"{"infrared_gamma": 0.0}"#).unwrap();
+        assert_eq!(
+            format!("{:#}", load(&path).unwrap_err()),
+            "infrared_gamma must be a positive number, got 0"
+        );
+        fs::write(&path, r#"{"infrared_gamma": "high"}"#).unwrap();
+        assert!(
+            format!("{:#}", load(&path).unwrap_err())
+                .starts_with(&format!("parsing {}", path.display()))
+        );
+    }
+}
@@ -0,0 +1,322 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use gstreamer::prelude::*;
+use gstreamer::{self as gst, FlowError};
+use gstreamer_app as gst_app;
+
+use parking_lot::Mutex;
+
+use crate::frame_number::Stamp;
+use crate::priority;
+use crate::rtsp_publisher::copy_numbered_to_buffer;
+
+/// Name of the optional second appsrc of a sink fragment, fed by [`LocalSink::push_audio`].
+pub const AUDIO_SRC_NAME: &str = "audiosrc";
+
+/// A standalone local GStreamer pipeline fed with raw frames through an appsrc.
+///
+/// Unlike the RTSP factories, this pipeline runs for the whole lifetime of the
+/// process, independently of connected RTSP clients.
+pub struct LocalSink {
+    label: String,
+    pipeline: gst::Pipeline,
+    appsrc: gst_app::AppSrc,
+    /// The [`AUDIO_SRC_NAME`] appsrc, if the sink fragment has one.
+    audio_appsrc: Option<gst_app::AppSrc>,
+    /// Frame size in the appsrc caps, width in the high half; 0 for caps without a
+    /// size, e.g. encoded input.
+    frame_size: AtomicU64,
+}
+
+/// Packs a frame size into one value for [`LocalSink::frame_size`].
+fn pack_size(width: u32, height: u32) -> u64 {
+    (u64::from(width) << 32) | u64::from(height)
+}
+
+impl LocalSink {
+    /// Builds and starts `appsrc caps=<caps> ! queue ! <sink_fragment>`. A fragment
+    /// that also muxes audio declares its own [`AUDIO_SRC_NAME`] appsrc.
+    pub fn start(label: &str, caps: &str, sink_fragment: &str) -> Result<Self> {
+        let launch = format!(
+            "appsrc name=src is-live=true format=time do-timestamp=true caps={caps} \
+            ! queue leaky=downstream max-size-buffers=2 max-size-bytes=0 max-size-time=0 \
+            ! {sink_fragment}"
+        );
+        let pipeline = gst::parse::launch(&launch)
+            .with_context(|| format!("Failed to build {label} pipeline: {launch}"))?
+            .downcast::<gst::Pipeline>()
+            .map_err(|_| anyhow::anyhow!("{label} pipeline is not a gst::Pipeline"))?;
+
+        let appsrc = pipeline
+            .by_name("src")
+            .and_then(|e| e.downcast::<gst_app::AppSrc>().ok())
+            .with_context(|| format!("{label} pipeline has no appsrc"))?;
+        appsrc.set_format(gst::Format::Time);
+        priority::lower_streaming_threads(&pipeline);
+        let audio_appsrc = pipeline
+            .by_name(AUDIO_SRC_NAME)
+            .and_then(|e| e.downcast::<gst_app::AppSrc>().ok());
+
+        pipeline
+            .set_state(gst::State::Playing)
+            .with_context(|| format!("Failed to start {label} pipeline"))?;
+        log::info!("{label} pipeline started: {sink_fragment}");
+
+        let frame_size = appsrc
+            .caps()
+            .as_ref()
+            .and_then(|caps| caps.structure(0))
+            .and_then(|s| {
+                let width = s.get::<i32>("width").ok()?;
+                let height = s.get::<i32>("height").ok()?;
+                Some(pack_size(width as u32, height as u32))
+            })
+            .unwrap_or(0);
+        Ok(Self {
+            label: label.to_string(),
+            pipeline,
+            appsrc,
+            audio_appsrc,
+            frame_size: AtomicU64::new(frame_size),
+        })
+    }
+
+    /// Element `name` of the pipeline, e.g. an appsink in the sink fragment.
+    pub fn by_name(&self, name: &str) -> Option<gst::Element> {
+        self.pipeline.by_name(name)
+    }
+
+    /// Whether the pipeline takes audio through [`Self::push_audio`].
+    pub fn has_audio(&self) -> bool {
+        self.audio_appsrc.is_some()
+    }
+
+    /// Swaps the appsrc caps to `width`x`height` when the frames change size, so
+    /// downstream renegotiates like the RTSP mounts do. No-op for caps without a size.
+    pub fn set_frame_size(&self, width: u32, height: u32) {
+        let size = pack_size(width, height);
+        let current = self.frame_size.load(Ordering::Relaxed);
+        if current == 0 || current == size {
+            return;
+        }
+        let Some(mut caps) = self.appsrc.caps() else {
+            return;
+        };
+        if let Some(s) = caps.make_mut().structure_mut(0) {
+            s.set("width", width as i32);
+            s.set("height", height as i32);
+        }
+        log::info!(
+            "{} frame size changed to {width}x{height}, renegotiating caps",
+            self.label
+        );
+        self.appsrc.set_caps(Some(&caps));
+        self.frame_size.store(size, Ordering::Relaxed);
+    }
+
+    /// Pushes a copy of `data` into the pipeline.
+    pub fn push(&self, data: &[u8]) {
+        self.push_numbered(data, None);
+    }
+
+    /// [`Self::push`], with `stamp` burned into the copy.
+    pub fn push_numbered(&self, data: &[u8], stamp: Option<Stamp>) {
+        if let Some(buffer) = copy_numbered_to_buffer(&self.label, data, stamp) {
+            self.push_to(&self.appsrc, buffer);
+        }
+    }
+
+    /// Pushes an already-filled buffer, e.g. encoded data from another pipeline.
+    pub fn push_buffer(&self, buffer: gst::Buffer) {
+        self.push_to(&self.appsrc, buffer);
+    }
+
+    /// Pushes an audio buffer into the [`AUDIO_SRC_NAME`] appsrc, if there is one.
+    pub fn push_audio(&self, buffer: gst::Buffer) {
+        if let Some(appsrc) = &self.audio_appsrc {
+            self.push_to(appsrc, buffer);
+        }
+    }
+
+    fn push_to(&self, appsrc: &gst_app::AppSrc, buffer: gst::Buffer) {
+        if let Err(e) = appsrc.push_buffer(buffer) {
+            if e == FlowError::Flushing {
+                log::debug!("{} appsrc is flushing, ignoring push error", self.label);
+            } else {
+                log::warn!("Failed to push {} buffer: {e:?}", self.label);
+            }
+        }
+    }
+
+    /// Calls `handler` with the message of every error the pipeline posts, from the
+    /// streaming thread. The messages stay on the bus for [`Self::finish`].
+    pub fn on_error(&self, handler: impl Fn(String) + Send + Sync + 'static) {
+        let Some(bus) = self.pipeline.bus() else {
+            return;
+        };
+        bus.set_sync_handler(move |_, msg| {
+            if let gst::MessageView::Error(err) = msg.view() {
+                handler(err.error().to_string());
+            }
+            gst::BusSyncReply::Pass
+        });
+    }
+
+    /// Sends EOS and waits up to `timeout` for it to reach the sink.
+    pub fn finish(&self, timeout: std::time::Duration) {
+        if self.appsrc.end_of_stream().is_err() {
+            return;
+        }
+        // A muxer only forwards EOS once every input has ended
+        if let Some(audio) = &self.audio_appsrc {
+            let _ = audio.end_of_stream();
+        }
+        let Some(bus) = self.pipeline.bus() else {
+            return;
+        };
+        let timeout = gst::ClockTime::from_nseconds(timeout.as_nanos() as u64);
+        match bus.timed_pop_filtered(timeout, &[gst::MessageType::Eos, gst::MessageType::Error]) {
+            Some(msg) if msg.type_() == gst::MessageType::Eos => {
+                log::debug!("{} pipeline drained", self.label);
+            }
+            _ => log::warn!("{} pipeline did not finish cleanly", self.label),
+        }
+    }
+}
+
+impl Drop for LocalSink {
+    fn drop(&mut self) {
+        let _ = self.pipeline.set_state(gst::State::Null);
+    }
+}
+
+/// First wait before a failed [`RestartingSink`] is rebuilt.
+const MIN_RESTART_DELAY: Duration = Duration::from_secs(1);
+
+/// Longest wait between rebuilds. A sink that ran this long before failing starts
+/// over at [`MIN_RESTART_DELAY`].
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(30);
+
+/// A [`LocalSink`] that is torn down on its first pipeline error, e.g. a network sink
+/// whose receiver went away, and rebuilt with growing delays. While it's down,
+/// pushes are dropped without a warning each.
+pub struct RestartingSink {
+    label: String,
+    sink: Arc<Mutex<Option<LocalSink>>>,
+    has_audio: bool,
+    stopping: Arc<AtomicBool>,
+}
+
+impl RestartingSink {
+    /// Starts the sink `build` returns; an error of this first build is returned.
+    /// `build` runs again on the restart thread for every rebuild.
+    pub fn start(
+        label: &str,
+        build: impl Fn() -> Result<LocalSink> + Send + 'static,
+    ) -> Result<Self> {
+        let first = build()?;
+        let has_audio = first.has_audio();
+        let (errors_tx, errors) = crossbeam_channel::unbounded::<String>();
+        let watch = move |sink: &LocalSink| {
+            let errors_tx = errors_tx.clone();
+            sink.on_error(move |message| {
+                let _ = errors_tx.send(message);
+            });
+        };
+        watch(&first);
+        let sink = Arc::new(Mutex::new(Some(first)));
+        let stopping = Arc::new(AtomicBool::new(false));
+        let (current, stop) = (sink.clone(), stopping.clone());
+        let label = label.to_string();
+        let thread_label = label.clone();
+        std::thread::Builder::new()
+            .name(format!("{label} restart"))
+            .spawn(move || {
+                let label = thread_label;
+                let mut delay = MIN_RESTART_DELAY;
+                let mut started = Instant::now();
+                while let Ok(message) = errors.recv() {
+                    if stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    // Dropped outside the lock, stopping the pipeline can take a moment
+                    let failed = current.lock().take();
+                    drop(failed);
+                    // A failing pipeline posts more than one error
+                    while errors.try_recv().is_ok() {}
+                    if started.elapsed() >= MAX_RESTART_DELAY {
+                        delay = MIN_RESTART_DELAY;
+                    }
+                    log::warn!(
+                        "{label} pipeline failed: {message}; rebuilding it in {}s",
+                        delay.as_secs()
+                    );
+                    loop {
+                        std::thread::sleep(delay);
+                        delay = (delay * 2).min(MAX_RESTART_DELAY);
+                        if stop.load(Ordering::SeqCst) {
+                            return;
+                        }
+                        match build() {
+                            Ok(sink) => {
+                                watch(&sink);
+                                *current.lock() = Some(sink);
+                                started = Instant::now();
+                                log::info!("{label} pipeline rebuilt");
+                                break;
+                            }
+                            Err(e) => log::warn!(
+                                "Failed to rebuild the {label} pipeline, retrying in {}s: {e:#}",
+                                delay.as_secs()
+                            ),
+                        }
+                    }
+                }
+            })?;
+        Ok(Self {
+            label,
+            sink,
+            has_audio,
+            stopping,
+        })
+    }
+
+    /// Whether the pipeline takes audio, see [`LocalSink::has_audio`].
+    pub fn has_audio(&self) -> bool {
+        self.has_audio
+    }
+
+    /// [`LocalSink::push_numbered`], unless the sink is down.
+    pub fn push_numbered(&self, data: &[u8], stamp: Option<Stamp>) {
+        if let Some(sink) = &*self.sink.lock() {
+            sink.push_numbered(data, stamp);
+        }
+    }
+
+    /// [`LocalSink::set_frame_size`], unless the sink is down. A rebuilt sink starts
+    /// with the size it was built for and follows the next frame.
+    pub fn set_frame_size(&self, width: u32, height: u32) {
+        if let Some(sink) = &*self.sink.lock() {
+            sink.set_frame_size(width, height);
+        }
+    }
+
+    /// [`LocalSink::push_audio`], unless the sink is down.
+    pub fn push_audio(&self, buffer: gst::Buffer) {
+        if let Some(sink) = &*self.sink.lock() {
+            sink.push_audio(buffer);
+        }
+    }
+
+    /// Stops rebuilding and finishes the running sink, see [`LocalSink::finish`].
+    pub fn finish(&self, timeout: Duration) {
+        self.stopping.store(true, Ordering::SeqCst);
+        match &*self.sink.lock() {
+            Some(sink) => sink.finish(timeout),
+            None => log::debug!("{} pipeline is down, nothing to finish", self.label),
+        }
+    }
+}
@@ -0,0 +1,241 @@
+//! Current location of a mobile rig (`--embed-location`), embedded in the video as a
+//! periodic SEI (see [`crate::sei::location_sei`]).
+//!
+//! Any source can report a fix with [`set`]: the control API (`POST /location`) and an
+//! NMEA 0183 receiver read by [`spawn_nmea_reader`] (`--location-nmea`) do. A fix older
+//! than [`MAX_AGE`] is dropped, so the metadata is omitted while no source reports one.
+
+use std::io::{BufRead, BufReader};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+/// A fix older than this is considered lost and no longer embedded.
+pub const MAX_AGE: Duration = Duration::from_secs(10);
+
+/// Delay before reopening the NMEA source after it failed or ended.
+const NMEA_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static CURRENT: Mutex<Option<(Instant, Location)>> = parking_lot::const_mutex(None);
+
+/// A WGS 84 position.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Location {
+    /// Latitude in degrees, positive north.
+    pub lat: f64,
+    /// Longitude in degrees, positive east.
+    pub lon: f64,
+    /// Altitude above mean sea level in meters, if known.
+    #[serde(default)]
+    pub alt: Option<f64>,
+}
+
+impl Location {
+    /// Rejects coordinates outside the globe and non-finite values.
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.lat.is_finite() || !(-90.0..=90.0).contains(&self.lat) {
+            return Err(format!("lat {} is outside -90..=90", self.lat));
+        }
+        if !self.lon.is_finite() || !(-180.0..=180.0).contains(&self.lon) {
+            return Err(format!("lon {} is outside -180..=180", self.lon));
+        }
+        if self.alt.is_some_and(|alt| !alt.is_finite()) {
+            return Err("alt must be a finite number".into());
+        }
+        Ok(())
+    }
+}
+
+/// Accepts fixes from now on; [`current`] returns `None` until then.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Whether location embedding was enabled with `--embed-location`.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Records the latest fix from `source`, logging the first one after a gap.
+pub fn set(location: Location, source: &str) {
+    let mut current = CURRENT.lock();
+    if !current.is_some_and(|(at, _)| at.elapsed() <= MAX_AGE) {
+        log::info!(
+            "Location fix from {source}: {:.6}, {:.6}",
+            location.lat,
+            location.lon
+        );
+    }
+    *current = Some((Instant::now(), location));
+}
+
+/// The latest fix, or `None` if there's none or it's older than [`MAX_AGE`].
+pub fn current() -> Option<Location> {
+    if !is_enabled() {
+        return None;
+    }
+    CURRENT
+        .lock()
+        .filter(|(at, _)| at.elapsed() <= MAX_AGE)
+        .map(|(_, location)| location)
+}
+
+/// Reads NMEA 0183 sentences from `path` (a serial port such as `COM3`, a named pipe
+/// or a file) on a background thread and reports every GGA fix. The port is reopened
+/// whenever it fails or ends, e.g. when a USB receiver is unplugged. A regular file is
+/// read once: replaying it would keep its last fix current forever.
+pub fn spawn_nmea_reader(path: String) -> std::io::Result<()> {
+    std::thread::Builder::new()
+        .name("location-nmea".into())
+        .spawn(move || {
+            let source = format!("NMEA {path}");
+            loop {
+                let device_path = nmea_path(&path);
+                match std::fs::File::open(&device_path) {
+                    Ok(file) => {
+                        log::info!("Reading location from {source}");
+                        // Windows reports ports and pipes as files, but they live in
+                        // the `\\.\` device namespace
+                        let regular_file = !device_path.starts_with(r"\\.\")
+                            && file.metadata().is_ok_and(|m| m.is_file());
+                        for line in BufReader::new(file).lines() {
+                            match line {
+                                Ok(line) => {
+                                    if let Some(location) = parse_nmea_gga(&line) {
+                                        set(location, &source);
+                                    }
+                                }
+                                Err(e) => {
+                                    log::warn!("Failed to read {source}: {e}");
+                                    break;
+                                }
+                            }
+                        }
+                        if regular_file {
+                            log::info!("Reached the end of {source}, its last fix expires");
+                            return;
+                        }
+                    }
+                    Err(e) => log::warn!("Failed to open {source}: {e}"),
+                }
+                std::thread::sleep(NMEA_RETRY_INTERVAL);
+            }
+        })?;
+    Ok(())
+}
+
+/// Serial ports above COM9 are only reachable through the `\\.\` device namespace.
+fn nmea_path(path: &str) -> String {
+    let upper = path.to_ascii_uppercase();
+    match upper.strip_prefix("COM") {
+        Some(n) if !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()) => {
+            format!(r"\\.\{upper}")
+        }
+        _ => path.to_string(),
+    }
+}
+
+/// Parses a GGA sentence from any talker (`$GPGGA`, `$GNGGA`, ...). Returns `None` for
+/// other sentences, a bad checksum or a sentence without a fix.
+pub fn parse_nmea_gga(line: &str) -> Option<Location> {
+    let sentence = line.trim().strip_prefix('$')?;
+    let (body, checksum) = match sentence.split_once('*') {
+        Some((body, checksum)) => (body, Some(checksum)),
+        None => (sentence, None),
+    };
+    if let Some(checksum) = checksum {
+        let expected = u8::from_str_radix(checksum, 16).ok()?;
+        if body.bytes().fold(0, |sum, b| sum ^ b) != expected {
+            return None;
+        }
+    }
+    let fields: Vec<&str> = body.split(',').collect();
+    if fields.len() < 10 || fields[0].len() != 5 || !fields[0].ends_with("GGA") {
+        return None;
+    }
+    // Fix quality 0 means no fix; the position fields are then empty or stale
+    if fields[6].parse::<u8>().ok()? == 0 {
+        return None;
+    }
+    let lat = nmea_degrees(fields[2], 2)? * hemisphere(fields[3], "N", "S")?;
+    let lon = nmea_degrees(fields[4], 3)? * hemisphere(fields[5], "E", "W")?;
+    let location = Location {
+        lat,
+        lon,
+        alt: fields[9].parse().ok(),
+    };
+    location.validate().ok()?;
+    Some(location)
+}
+
+/// Converts NMEA `(d)ddmm.mmmm` with `degree_digits` degree digits to decimal degrees.
+fn nmea_degrees(value: &str, degree_digits: usize) -> Option<f64> {
+    let degrees: f64 = value.get(..degree_digits)?.parse().ok()?;
+    let minutes: f64 = value.get(degree_digits..)?.parse().ok()?;
+    Some(degrees + minutes / 60.0)
+}
+
+fn hemisphere(value: &str, positive: &str, negative: &str) -> Option<f64> {
+    match value {
+        v if v == positive => Some(1.0),
+        v if v == negative => Some(-1.0),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_nmea_gga() {
+        let location =
+            parse_nmea_gga("$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47")
+                .unwrap();
+        assert!((location.lat - 48.1173).abs() < 1e-6);
+        assert!((location.lon - 11.516_666).abs() < 1e-5);
+        assert_eq!(location.alt, Some(545.4));
+
+        let south_west =
+            parse_nmea_gga("$GNGGA,123519,3352.128,S,15112.558,W,1,08,0.9,,M,,M,,").unwrap();
+        assert!(south_west.lat < 0.0 && south_west.lon < 0.0);
+        assert_eq!(south_west.alt, None);
+    }
+
+    #[test]
+    fn test_parse_nmea_gga_rejects() {
+        // Bad checksum
+        assert!(
+            parse_nmea_gga("$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*48")
+                .is_none()
+        );
+        // No fix
+        assert!(parse_nmea_gga("$GPGGA,123519,,,,,0,00,,,M,,M,,").is_none());
+        // Other sentence
+        assert!(
+            parse_nmea_gga("$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W")
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let location = Location {
+            lat: 91.0,
+            lon: 0.0,
+            alt: None,
+        };
+        assert!(location.validate().is_err());
+        assert!(
+            Location {
+                lat: -33.8,
+                lon: 151.2,
+                alt: Some(12.0)
+            }
+            .validate()
+            .is_ok()
+        );
+    }
+}
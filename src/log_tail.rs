@@ -0,0 +1,90 @@
+//! Keeps the most recent log lines in memory for `GET /logs` and the `diagnose`
+//! report. The log output itself still goes to stderr unchanged.
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+
+use parking_lot::Mutex;
+
+/// Lines kept; older ones are dropped.
+const CAPACITY: usize = 500;
+
+static TAIL: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+/// Log target for `env_logger`: writes to stderr and records every complete line.
+struct TeeWriter {
+    /// Bytes of a line whose newline hasn't been written yet.
+    partial: Vec<u8>,
+}
+
+pub fn writer() -> Box<dyn Write + Send> {
+    Box::new(TeeWriter {
+        partial: Vec::new(),
+    })
+}
+
+/// The recorded lines, oldest first.
+pub fn lines() -> Vec<String> {
+    TAIL.lock().iter().cloned().collect()
+}
+
+/// Removes ANSI escape sequences, e.g. the level colors on a terminal.
+fn strip_ansi(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // CSI sequences end with a byte in @..~, e.g. the `m` of `\x1b[31m`
+            for c in chars.by_ref() {
+                if ('@'..='~').contains(&c) && c != '[' {
+                    break;
+                }
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+fn push_line(tail: &mut VecDeque<String>, line: String, capacity: usize) {
+    if tail.len() == capacity {
+        tail.pop_front();
+    }
+    tail.push_back(line);
+}
+
+impl Write for TeeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        io::stderr().write_all(buf)?;
+        self.partial.extend_from_slice(buf);
+        while let Some(end) = self.partial.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.partial.drain(..=end).collect();
+            let line = strip_ansi(String::from_utf8_lossy(&line[..end]).trim_end());
+            push_line(&mut TAIL.lock(), line, CAPACITY);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tail_keeps_the_last_lines_without_colors() {
+        assert_eq!(
+            strip_ansi("[\x1b[32mINFO \x1b[0m kinect_rtsp] ready"),
+            "[INFO  kinect_rtsp] ready"
+        );
+        let mut tail = VecDeque::new();
+        for i in 0..5 {
+            push_line(&mut tail, i.to_string(), 3);
+        }
+        assert_eq!(tail, ["2", "3", "4"]);
+    }
+}
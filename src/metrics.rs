@@ -0,0 +1,290 @@
+//! Prometheus metrics exposed over a minimal HTTP endpoint at `/metrics`. The
+//! publisher always records them; the endpoint needs the `metrics` feature.
+
+#[cfg(feature = "metrics")]
+use std::fmt::Write as _;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::frame_queue::QueueLevel;
+
+#[cfg(feature = "metrics")]
+use crate::http::{self, Response};
+#[cfg(feature = "metrics")]
+use crate::rtp_stats::RtpStats;
+#[cfg(feature = "metrics")]
+use crate::rtsp_publisher::RtspPublisher;
+
+/// Upper bounds (seconds) of the frame latency buckets, ~1 ms to a few hundred ms.
+const LATENCY_BUCKETS: [f64; 10] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0];
+
+/// Lock-free histogram of durations with the fixed [`LATENCY_BUCKETS`].
+#[derive(Default)]
+pub struct LatencyHistogram {
+    /// Non-cumulative counts; the last slot is the `+Inf` bucket.
+    buckets: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    sum_micros: AtomicU64,
+    count: AtomicU64,
+}
+
+impl LatencyHistogram {
+    pub fn observe(&self, latency: Duration) {
+        let secs = latency.as_secs_f64();
+        let index = LATENCY_BUCKETS
+            .iter()
+            .position(|&bound| secs <= bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[index].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Appends the histogram in Prometheus text format with the given label set.
+    #[cfg(feature = "metrics")]
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let mut cumulative = 0;
+        for (bound, bucket) in LATENCY_BUCKETS.iter().zip(&self.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{name}_bucket{{{labels},le=\"{bound}\"}} {cumulative}");
+        }
+        cumulative += self.buckets[LATENCY_BUCKETS.len()].load(Ordering::Relaxed);
+        let _ = writeln!(out, "{name}_bucket{{{labels},le=\"+Inf\"}} {cumulative}");
+        let sum = self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let _ = writeln!(out, "{name}_sum{{{labels}}} {sum}");
+        let _ = writeln!(
+            out,
+            "{name}_count{{{labels}}} {}",
+            self.count.load(Ordering::Relaxed)
+        );
+    }
+}
+
+/// Metrics recorded by the publisher.
+#[derive(Default)]
+pub struct Metrics {
+    /// Kinect capture to appsrc push latency per video stream.
+    pub color_latency: LatencyHistogram,
+    pub infra_latency: LatencyHistogram,
+    pub pointcloud_latency: LatencyHistogram,
+    pub depth_latency: LatencyHistogram,
+    /// Times each publish loop woke up: for a queued frame or, while idle, every 100 ms.
+    pub color_publish_wakeups: AtomicU64,
+    pub infra_publish_wakeups: AtomicU64,
+    pub depth_publish_wakeups: AtomicU64,
+    pub audio_publish_wakeups: AtomicU64,
+    /// Fill level of each frame queue, see `--queue-high-water`.
+    pub color_queue: Arc<QueueLevel>,
+    pub infra_queue: Arc<QueueLevel>,
+    pub infra_long_queue: Arc<QueueLevel>,
+    pub depth_queue: Arc<QueueLevel>,
+    pub audio_queue: Arc<QueueLevel>,
+}
+
+#[cfg(feature = "metrics")]
+impl Metrics {
+    /// Renders all metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        const NAME: &str = "kinect_frame_latency_seconds";
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "# HELP {NAME} Time from Kinect frame capture to appsrc push."
+        );
+        let _ = writeln!(out, "# TYPE {NAME} histogram");
+        self.color_latency
+            .render(&mut out, NAME, "stream=\"color\"");
+        self.infra_latency
+            .render(&mut out, NAME, "stream=\"infrared\"");
+        self.pointcloud_latency
+            .render(&mut out, NAME, "stream=\"pointcloud\"");
+        self.depth_latency
+            .render(&mut out, NAME, "stream=\"depth\"");
+
+        const WAKEUPS: &str = "kinect_publish_wakeups_total";
+        let _ = writeln!(
+            out,
+            "# HELP {WAKEUPS} Times a publish thread woke up to wait for its next frame."
+        );
+        let _ = writeln!(out, "# TYPE {WAKEUPS} counter");
+        for (stream, wakeups) in [
+            ("color", &self.color_publish_wakeups),
+            ("infrared", &self.infra_publish_wakeups),
+            ("depth", &self.depth_publish_wakeups),
+            ("audio", &self.audio_publish_wakeups),
+        ] {
+            let _ = writeln!(
+                out,
+                "{WAKEUPS}{{stream=\"{stream}\"}} {}",
+                wakeups.load(Ordering::Relaxed)
+            );
+        }
+
+        const QUEUE: &str = "kinect_frame_queue_frames";
+        const CAPACITY: &str = "kinect_frame_queue_capacity";
+        let _ = writeln!(
+            out,
+            "# HELP {QUEUE} Frames waiting in a frame queue after its latest push."
+        );
+        let _ = writeln!(out, "# TYPE {QUEUE} gauge");
+        let _ = writeln!(
+            out,
+            "# HELP {CAPACITY} Frames a frame queue holds before dropping."
+        );
+        let _ = writeln!(out, "# TYPE {CAPACITY} gauge");
+        for (queue, level) in [
+            ("color", &self.color_queue),
+            ("infrared", &self.infra_queue),
+            ("infrared-long", &self.infra_long_queue),
+            ("depth", &self.depth_queue),
+            ("audio", &self.audio_queue),
+        ] {
+            let (frames, capacity) = level.get();
+            // A queue that isn't in use (inline handoff, stream disabled) isn't reported
+            if capacity == 0 {
+                continue;
+            }
+            let _ = writeln!(out, "{QUEUE}{{queue=\"{queue}\"}} {frames}");
+            let _ = writeln!(out, "{CAPACITY}{{queue=\"{queue}\"}} {capacity}");
+        }
+        out
+    }
+}
+
+/// Renders the per-stream QoS message counts as the `kinect_qos_events_total` counter.
+#[cfg(feature = "metrics")]
+pub fn render_qos_events(out: &mut String, counts: &[(&str, u64)]) {
+    const NAME: &str = "kinect_qos_events_total";
+    let _ = writeln!(
+        out,
+        "# HELP {NAME} QoS messages from the stream's pipelines; rising means the host can't keep up."
+    );
+    let _ = writeln!(out, "# TYPE {NAME} counter");
+    for (stream, count) in counts {
+        let _ = writeln!(out, "{NAME}{{stream=\"{stream}\"}} {count}");
+    }
+}
+
+/// Renders the latest RTP statistics per stream (`--rtp-stats-secs`). The sent
+/// counters start over with every new media; the loss needs receiver reports.
+#[cfg(feature = "metrics")]
+pub fn render_rtp_stats(out: &mut String, stats: &[(&str, RtpStats)]) {
+    if stats.is_empty() {
+        return;
+    }
+    const PACKETS: &str = "kinect_rtp_packets_sent_total";
+    const BYTES: &str = "kinect_rtp_bytes_sent_total";
+    const LOST: &str = "kinect_rtp_packets_lost";
+    const FRACTION: &str = "kinect_rtp_fraction_lost";
+    let _ = writeln!(
+        out,
+        "# HELP {PACKETS} RTP packets sent by the stream's current media."
+    );
+    let _ = writeln!(out, "# TYPE {PACKETS} counter");
+    for (stream, s) in stats {
+        let _ = writeln!(out, "{PACKETS}{{stream=\"{stream}\"}} {}", s.packets_sent);
+    }
+    let _ = writeln!(
+        out,
+        "# HELP {BYTES} RTP payload bytes sent by the stream's current media."
+    );
+    let _ = writeln!(out, "# TYPE {BYTES} counter");
+    for (stream, s) in stats {
+        let _ = writeln!(out, "{BYTES}{{stream=\"{stream}\"}} {}", s.bytes_sent);
+    }
+    let _ = writeln!(
+        out,
+        "# HELP {LOST} Packets lost in total according to the latest RTCP receiver reports."
+    );
+    let _ = writeln!(out, "# TYPE {LOST} gauge");
+    for (stream, s) in stats.iter().filter(|(_, s)| s.has_reports) {
+        let _ = writeln!(out, "{LOST}{{stream=\"{stream}\"}} {}", s.packets_lost);
+    }
+    let _ = writeln!(
+        out,
+        "# HELP {FRACTION} Fraction of packets lost since the previous RTCP receiver report."
+    );
+    let _ = writeln!(out, "# TYPE {FRACTION} gauge");
+    for (stream, s) in stats.iter().filter(|(_, s)| s.has_reports) {
+        let _ = writeln!(out, "{FRACTION}{{stream=\"{stream}\"}} {}", s.fraction_lost);
+    }
+}
+
+/// Serves `GET /metrics` on `port` until the process exits.
+#[cfg(feature = "metrics")]
+pub async fn serve(port: u16, rtsp: Arc<RtspPublisher>) -> anyhow::Result<()> {
+    http::serve(
+        "Metrics endpoint",
+        "0.0.0.0",
+        port,
+        move |method, path, _body| match (method, path) {
+            ("GET", "/metrics") => {
+                let mut body = rtsp.metrics().render();
+                render_qos_events(&mut body, &rtsp.qos_events());
+                render_rtp_stats(&mut body, &rtsp.rtp_stats());
+                Response::ok("text/plain; version=0.0.4", body)
+            }
+            _ => Response::not_found(),
+        },
+    )
+    .await
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_buckets_are_cumulative() {
+        let histogram = LatencyHistogram::default();
+        histogram.observe(Duration::from_micros(500));
+        histogram.observe(Duration::from_millis(20));
+        histogram.observe(Duration::from_secs(2));
+
+        let mut out = String::new();
+        histogram.render(&mut out, "latency", "stream=\"color\"");
+        assert!(out.contains("latency_bucket{stream=\"color\",le=\"0.001\"} 1\n"));
+        assert!(out.contains("latency_bucket{stream=\"color\",le=\"0.01\"} 1\n"));
+        assert!(out.contains("latency_bucket{stream=\"color\",le=\"0.025\"} 2\n"));
+        assert!(out.contains("latency_bucket{stream=\"color\",le=\"1\"} 2\n"));
+        assert!(out.contains("latency_bucket{stream=\"color\",le=\"+Inf\"} 3\n"));
+        assert!(out.contains("latency_count{stream=\"color\"} 3\n"));
+        assert!(out.contains("latency_sum{stream=\"color\"} 2.0205\n"));
+    }
+
+    #[test]
+    fn test_qos_events_counter() {
+        let mut out = String::new();
+        render_qos_events(&mut out, &[("color", 3), ("infrared", 0)]);
+        assert!(out.contains("# TYPE kinect_qos_events_total counter\n"));
+        assert!(out.contains("kinect_qos_events_total{stream=\"color\"} 3\n"));
+        assert!(out.contains("kinect_qos_events_total{stream=\"infrared\"} 0\n"));
+    }
+
+    #[test]
+    fn test_rtp_stats_report_loss_only_with_receiver_reports() {
+        let mut out = String::new();
+        render_rtp_stats(&mut out, &[]);
+        assert!(out.is_empty());
+
+        let reported = RtpStats {
+            packets_sent: 120,
+            bytes_sent: 90_000,
+            has_reports: true,
+            packets_lost: 4,
+            fraction_lost: 0.25,
+        };
+        let unreported = RtpStats {
+            packets_sent: 10,
+            ..Default::default()
+        };
+        render_rtp_stats(&mut out, &[("color", reported), ("audio", unreported)]);
+        assert!(out.contains("kinect_rtp_packets_sent_total{stream=\"color\"} 120\n"));
+        assert!(out.contains("kinect_rtp_packets_sent_total{stream=\"audio\"} 10\n"));
+        assert!(out.contains("kinect_rtp_bytes_sent_total{stream=\"color\"} 90000\n"));
+        assert!(out.contains("kinect_rtp_packets_lost{stream=\"color\"} 4\n"));
+        assert!(out.contains("kinect_rtp_fraction_lost{stream=\"color\"} 0.25\n"));
+        assert!(!out.contains("kinect_rtp_packets_lost{stream=\"audio\"}"));
+    }
+}
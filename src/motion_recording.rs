@@ -0,0 +1,592 @@
+//! Event recording (`--record-on-motion`): clips of the color stream, in the
+//! container of `--record-format`, that start when motion is detected and end a
+//! post-roll after it stops.
+//!
+//! The raw color frames are encoded continuously by a dedicated [`LocalSink`]; its
+//! output fills a [`PreRoll`] ring, so a clip opens with the seconds before the
+//! motion. A failing encoder is rebuilt by a [`RestartingSink`], closing the clip. Motion is a change of the mean luma of enough cells of a coarse grid
+//! between two sampled frames, see [`MotionDetector`].
+
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use gstreamer_app as gst_app;
+use parking_lot::Mutex;
+
+use crate::color::ColorCaptureFormat;
+use crate::local_sink::{LocalSink, RestartingSink};
+
+/// Cells of the grid compared between frames, horizontally and vertically.
+const GRID_WIDTH: usize = 64;
+const GRID_HEIGHT: usize = 36;
+/// Pixels averaged per cell, in each direction.
+const CELL_SAMPLES: usize = 4;
+/// Mean luma change (0-255) of a cell that counts it as changed, well above sensor noise.
+const CELL_DELTA: f32 = 12.0;
+/// Only every Nth color frame is compared, 10 checks per second at 30 fps.
+const CHECK_INTERVAL: u64 = 3;
+
+/// Default of `--motion-threshold`, percent of the grid cells that must change.
+pub const DEFAULT_MOTION_THRESHOLD: f32 = 1.0;
+/// Default of `--motion-pre-roll-secs`.
+pub const DEFAULT_PRE_ROLL_SECS: u32 = 5;
+/// Default of `--motion-post-roll-secs`.
+pub const DEFAULT_POST_ROLL_SECS: u32 = 10;
+
+/// Name of the appsink collecting the encoded frames.
+const ENCODED_SINK_NAME: &str = "encoded";
+/// How long a finished clip may take to be written out.
+const CLIP_FINISH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Container of the recorded clips (`--record-format`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RecordFormat {
+    /// `mp4mux`; plays everywhere, but a file cut off before its index is written is lost
+    Mp4,
+    /// `matroskamux`; a file cut off by a crash or power loss stays playable
+    #[default]
+    Mkv,
+    /// `mpegtsmux`; no index at all, every cut-off file plays up to the cut
+    Ts,
+}
+
+impl RecordFormat {
+    /// Muxer element writing the container.
+    pub fn muxer(self) -> &'static str {
+        match self {
+            RecordFormat::Mp4 => "mp4mux",
+            RecordFormat::Mkv => "matroskamux",
+            RecordFormat::Ts => "mpegtsmux",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            RecordFormat::Mp4 => "mp4",
+            RecordFormat::Mkv => "mkv",
+            RecordFormat::Ts => "ts",
+        }
+    }
+}
+
+/// Settings of `--record-on-motion`.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct MotionRecording {
+    /// Directory the clips are written to.
+    pub dir: PathBuf,
+    /// Container of the clips.
+    pub format: RecordFormat,
+    /// Percent of the grid cells that must change for motion.
+    pub threshold: f32,
+    /// Seconds of video kept before the motion.
+    pub pre_roll_secs: u32,
+    /// Seconds recorded after the last motion.
+    pub post_roll_secs: u32,
+}
+
+/// Parses a `--motion-threshold` percentage.
+pub fn parse_motion_threshold(s: &str) -> Result<f32, String> {
+    let percent: f32 = s
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid threshold '{s}': expected a percentage like 1.5"))?;
+    if !(percent > 0.0 && percent <= 100.0) {
+        return Err(format!(
+            "invalid threshold {percent}: must be above 0 and at most 100"
+        ));
+    }
+    Ok(percent)
+}
+
+/// Detects motion by comparing the mean luma of a coarse grid of cells between
+/// sampled frames.
+pub struct MotionDetector {
+    format: ColorCaptureFormat,
+    /// Fraction (0-1) of the cells that must change.
+    threshold: f32,
+    previous: Vec<f32>,
+    current: Vec<f32>,
+    frames: u64,
+}
+
+impl MotionDetector {
+    pub fn new(format: ColorCaptureFormat, threshold_percent: f32) -> Self {
+        Self {
+            format,
+            threshold: threshold_percent / 100.0,
+            previous: Vec::new(),
+            current: Vec::with_capacity(GRID_WIDTH * GRID_HEIGHT),
+            frames: 0,
+        }
+    }
+
+    /// Whether `frame` moved compared to the last sampled frame. Frames between
+    /// samples, and the first one, report no motion.
+    pub fn detect(&mut self, frame: &[u8], width: usize) -> bool {
+        self.frames += 1;
+        if !(self.frames - 1).is_multiple_of(CHECK_INTERVAL) {
+            return false;
+        }
+        if !self.sample_grid(frame, width) {
+            return false;
+        }
+        let motion = self.previous.len() == self.current.len() && {
+            let changed = self
+                .previous
+                .iter()
+                .zip(&self.current)
+                .filter(|(a, b)| (*a - *b).abs() > CELL_DELTA)
+                .count();
+            changed as f32 >= self.threshold * self.current.len() as f32
+        };
+        std::mem::swap(&mut self.previous, &mut self.current);
+        motion
+    }
+
+    /// Fills `current` with the mean luma of every cell; false if `frame` is smaller
+    /// than the grid.
+    fn sample_grid(&mut self, frame: &[u8], width: usize) -> bool {
+        let bytes_per_pixel = match self.format {
+            ColorCaptureFormat::Yuy2 => 2,
+            ColorCaptureFormat::Bgra | ColorCaptureFormat::Rgba => 4,
+        };
+        let stride = width * bytes_per_pixel;
+        if stride == 0 {
+            return false;
+        }
+        let height = frame.len() / stride;
+        if width < GRID_WIDTH * CELL_SAMPLES || height < GRID_HEIGHT * CELL_SAMPLES {
+            return false;
+        }
+        let samples_x = GRID_WIDTH * CELL_SAMPLES;
+        let samples_y = GRID_HEIGHT * CELL_SAMPLES;
+        self.current.clear();
+        self.current.resize(GRID_WIDTH * GRID_HEIGHT, 0.0);
+        for sy in 0..samples_y {
+            let y = (2 * sy + 1) * height / (2 * samples_y);
+            let row = &frame[y * stride..(y + 1) * stride];
+            for sx in 0..samples_x {
+                let x = (2 * sx + 1) * width / (2 * samples_x);
+                let luma = match self.format {
+                    // Every pixel's Y is at an even byte of the Y0 U Y1 V macropixel
+                    ColorCaptureFormat::Yuy2 => f32::from(row[x * 2]),
+                    ColorCaptureFormat::Bgra | ColorCaptureFormat::Rgba => {
+                        let px = &row[x * 4..x * 4 + 3];
+                        (f32::from(px[0]) + 2.0 * f32::from(px[1]) + f32::from(px[2])) / 4.0
+                    }
+                };
+                self.current[(sy / CELL_SAMPLES) * GRID_WIDTH + sx / CELL_SAMPLES] += luma;
+            }
+        }
+        let per_cell = (CELL_SAMPLES * CELL_SAMPLES) as f32;
+        self.current.iter_mut().for_each(|sum| *sum /= per_cell);
+        true
+    }
+}
+
+/// Recent encoded frames, trimmed so they start with the keyframe at or just before
+/// `length` ago: a clip opened from them starts decodable with the full pre-roll.
+pub struct PreRoll<T> {
+    length: Duration,
+    /// (timestamp, keyframe, frame)
+    frames: VecDeque<(Duration, bool, T)>,
+}
+
+impl<T> PreRoll<T> {
+    pub fn new(length: Duration) -> Self {
+        Self {
+            length,
+            frames: VecDeque::new(),
+        }
+    }
+
+    /// Adds a frame. Frames before the first keyframe are dropped, nothing can
+    /// decode them.
+    pub fn push(&mut self, timestamp: Duration, keyframe: bool, frame: T) {
+        if self.frames.is_empty() && !keyframe {
+            return;
+        }
+        self.frames.push_back((timestamp, keyframe, frame));
+        let cutoff = timestamp.saturating_sub(self.length);
+        // The newest keyframe no later than the cutoff, other than the first frame
+        if let Some(start) = self
+            .frames
+            .iter()
+            .rposition(|&(ts, keyframe, _)| keyframe && ts <= cutoff)
+            .filter(|&i| i > 0)
+        {
+            self.frames.drain(..start);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(Duration, bool, T)> {
+        self.frames.iter()
+    }
+}
+
+/// `<prefix>-<UTC start time>.<extension>`, e.g. `motion-20260301-142305Z.mkv`.
+pub fn clip_file_name(prefix: &str, start: SystemTime, format: RecordFormat) -> String {
+    let secs = start.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let time = secs % 86_400;
+    format!(
+        "{prefix}-{year:04}{month:02}{day:02}-{:02}{:02}{:02}Z.{}",
+        time / 3600,
+        time / 60 % 60,
+        time % 60,
+        format.extension()
+    )
+}
+
+/// Gregorian (year, month, day) of a count of days since 1970-01-01, after Howard
+/// Hinnant's `civil_from_days`.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// One clip being written: `appsrc ! h264parse ! <muxer> ! filesink`.
+struct Clip {
+    path: PathBuf,
+    pipeline: gst::Pipeline,
+    appsrc: gst_app::AppSrc,
+    /// Timestamp of the clip's first frame, subtracted from every frame.
+    base: gst::ClockTime,
+}
+
+impl Clip {
+    fn open(path: PathBuf, format: RecordFormat, base: gst::ClockTime) -> Result<Self> {
+        let launch = format!(
+            "appsrc name=src format=time caps=video/x-h264,stream-format=byte-stream,alignment=au \
+            ! h264parse ! {} ! filesink location=\"{}\" sync=false",
+            format.muxer(),
+            path.display().to_string().replace('\\', "\\\\")
+        );
+        let pipeline = gst::parse::launch(&launch)
+            .with_context(|| format!("Failed to build motion clip pipeline: {launch}"))?
+            .downcast::<gst::Pipeline>()
+            .map_err(|_| anyhow::anyhow!("Motion clip pipeline is not a gst::Pipeline"))?;
+        let appsrc = pipeline
+            .by_name("src")
+            .and_then(|e| e.downcast::<gst_app::AppSrc>().ok())
+            .context("Motion clip pipeline has no appsrc")?;
+        pipeline
+            .set_state(gst::State::Playing)
+            .with_context(|| format!("Failed to start writing {}", path.display()))?;
+        Ok(Self {
+            path,
+            pipeline,
+            appsrc,
+            base,
+        })
+    }
+
+    /// Pushes an encoded frame, retimed relative to the clip start.
+    fn push(&self, frame: &gst::Buffer) {
+        let mut frame = frame.copy();
+        {
+            let frame = frame.get_mut().unwrap();
+            let pts = frame.pts().map(|pts| pts.saturating_sub(self.base));
+            let dts = frame.dts().map(|dts| dts.saturating_sub(self.base));
+            frame.set_pts(pts);
+            frame.set_dts(dts);
+        }
+        if let Err(e) = self.appsrc.push_buffer(frame) {
+            log::warn!("Failed to write to {}: {e:?}", self.path.display());
+        }
+    }
+
+    /// Sends EOS so the muxer writes its index, and waits for the file to be complete.
+    fn finish(self, timeout: Duration) {
+        let complete = self.appsrc.end_of_stream().is_ok()
+            && self.pipeline.bus().is_some_and(|bus| {
+                let timeout = gst::ClockTime::from_nseconds(timeout.as_nanos() as u64);
+                bus.timed_pop_filtered(timeout, &[gst::MessageType::Eos, gst::MessageType::Error])
+                    .is_some_and(|msg| msg.type_() == gst::MessageType::Eos)
+            });
+        let _ = self.pipeline.set_state(gst::State::Null);
+        if complete {
+            log::info!("Motion clip saved: {}", self.path.display());
+        } else {
+            log::warn!(
+                "Motion clip {} was not finalized cleanly",
+                self.path.display()
+            );
+        }
+    }
+}
+
+struct RecorderState {
+    pre_roll: PreRoll<gst::Buffer>,
+    clip: Option<Clip>,
+    /// Wall-clock time of the motion that opens the next clip.
+    pending_start: Option<SystemTime>,
+    last_motion: Option<Instant>,
+}
+
+/// Motion detection and clip writing for `--record-on-motion`.
+pub struct MotionRecorder {
+    settings: MotionRecording,
+    encoder: RestartingSink,
+    detector: Mutex<MotionDetector>,
+    state: Arc<Mutex<RecorderState>>,
+}
+
+impl MotionRecorder {
+    /// Starts encoding the raw color frames (`caps`) with `video_branch` into the
+    /// pre-roll ring. Clips are only written once motion is detected.
+    pub fn start(
+        settings: &MotionRecording,
+        format: ColorCaptureFormat,
+        caps: &str,
+        video_branch: &str,
+    ) -> Result<Self> {
+        std::fs::create_dir_all(&settings.dir)
+            .with_context(|| format!("Failed to create {}", settings.dir.display()))?;
+        let state = Arc::new(Mutex::new(RecorderState {
+            pre_roll: PreRoll::new(Duration::from_secs(settings.pre_roll_secs.into())),
+            clip: None,
+            pending_start: None,
+            last_motion: None,
+        }));
+        let encoder = {
+            let (settings, caps, video_branch) =
+                (settings.clone(), caps.to_string(), video_branch.to_string());
+            let state = state.clone();
+            RestartingSink::start("Motion recording", move || {
+                start_encoder(&settings, &caps, &video_branch, &state)
+            })?
+        };
+        log::info!(
+            "Recording {} motion clips to {} ({}% of the frame, {} s pre-roll, {} s post-roll)",
+            settings.format.extension().to_uppercase(),
+            settings.dir.display(),
+            settings.threshold,
+            settings.pre_roll_secs,
+            settings.post_roll_secs
+        );
+        Ok(Self {
+            settings: settings.clone(),
+            encoder,
+            detector: Mutex::new(MotionDetector::new(format, settings.threshold)),
+            state,
+        })
+    }
+
+    /// Feeds a raw color frame to the motion detector and the pre-roll encoder.
+    pub fn push(&self, width: u32, data: &[u8]) {
+        if self.detector.lock().detect(data, width as usize) {
+            let mut state = self.state.lock();
+            state.last_motion = Some(Instant::now());
+            if state.clip.is_none() && state.pending_start.is_none() {
+                log::info!("Motion detected, recording a clip");
+                state.pending_start = Some(SystemTime::now());
+            }
+        }
+        self.encoder.push_numbered(data, None);
+    }
+
+    /// Stops encoding and finalizes the clip being written, if any.
+    pub fn finish(&self, timeout: Duration) {
+        self.encoder.finish(timeout);
+        if let Some(clip) = self.state.lock().clip.take() {
+            clip.finish(timeout.max(Duration::from_secs(1)));
+        }
+        log::debug!(
+            "Motion recording to {} stopped",
+            self.settings.dir.display()
+        );
+    }
+}
+
+/// Starts the pipeline encoding the raw color frames into the pre-roll of `state`.
+/// A clip left open by a failed pipeline is closed first, and the pre-roll emptied:
+/// the timestamps of the new pipeline start over.
+fn start_encoder(
+    settings: &MotionRecording,
+    caps: &str,
+    video_branch: &str,
+    state: &Arc<Mutex<RecorderState>>,
+) -> Result<LocalSink> {
+    {
+        let mut state = state.lock();
+        if let Some(clip) = state.clip.take() {
+            close_clip(clip);
+        }
+        state.pre_roll = PreRoll::new(Duration::from_secs(settings.pre_roll_secs.into()));
+    }
+    // The second h264parse repeats SPS/PPS at every IDR, whatever
+    // --sps-pps-interval is, so every clip starts decodable
+    let encoder = LocalSink::start(
+        "Motion recording",
+        caps,
+        &format!(
+            "{video_branch} ! h264parse config-interval=-1 \
+            ! video/x-h264,stream-format=byte-stream,alignment=au \
+            ! appsink name={ENCODED_SINK_NAME} sync=false"
+        ),
+    )?;
+    let appsink = encoder
+        .by_name(ENCODED_SINK_NAME)
+        .and_then(|e| e.downcast::<gst_app::AppSink>().ok())
+        .context("Motion recording pipeline has no appsink")?;
+
+    let callback_state = state.clone();
+    let dir = settings.dir.clone();
+    let container = settings.format;
+    let post_roll = Duration::from_secs(settings.post_roll_secs.into());
+    appsink.set_callbacks(
+        gst_app::AppSinkCallbacks::builder()
+            .new_sample(move |sink| {
+                let sample = sink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
+                if let Some(buffer) = sample.buffer_owned() {
+                    on_encoded(
+                        &mut callback_state.lock(),
+                        &dir,
+                        container,
+                        post_roll,
+                        buffer,
+                    );
+                }
+                Ok(gst::FlowSuccess::Ok)
+            })
+            .build(),
+    );
+    Ok(encoder)
+}
+
+/// Finalizes `clip` on its own thread: writing the index can take a moment, and
+/// shouldn't hold up the encoder.
+fn close_clip(clip: Clip) {
+    let finished = std::thread::Builder::new()
+        .name("motion-clip".into())
+        .spawn(move || clip.finish(CLIP_FINISH_TIMEOUT));
+    if let Err(e) = finished {
+        log::warn!("Failed to spawn the motion clip thread: {e}");
+    }
+}
+
+/// Handles one encoded frame: fills the pre-roll, opens a clip on pending motion,
+/// and closes it once the post-roll has passed without motion.
+fn on_encoded(
+    state: &mut RecorderState,
+    dir: &Path,
+    container: RecordFormat,
+    post_roll: Duration,
+    buffer: gst::Buffer,
+) {
+    let timestamp = Duration::from_nanos(buffer.pts().map_or(0, |pts| pts.nseconds()));
+    let keyframe = !buffer.flags().contains(gst::BufferFlags::DELTA_UNIT);
+    if let Some(clip) = &state.clip {
+        clip.push(&buffer);
+    }
+    state.pre_roll.push(timestamp, keyframe, buffer);
+
+    if state.clip.is_none()
+        && let Some(start) = state.pending_start.take()
+    {
+        let base = state.pre_roll.iter().next().map_or(timestamp, |f| f.0);
+        let base = gst::ClockTime::from_nseconds(base.as_nanos() as u64);
+        match Clip::open(
+            dir.join(clip_file_name("motion", start, container)),
+            container,
+            base,
+        ) {
+            Ok(clip) => {
+                for (_, _, frame) in state.pre_roll.iter() {
+                    clip.push(frame);
+                }
+                state.clip = Some(clip);
+            }
+            Err(e) => log::warn!("Failed to start a motion clip: {e:#}"),
+        }
+    }
+
+    if state
+        .last_motion
+        .is_none_or(|motion| motion.elapsed() >= post_roll)
+        && let Some(clip) = state.clip.take()
+    {
+        close_clip(clip);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_motion_detector() {
+        let (width, height) = (512, 288);
+        let mut detector = MotionDetector::new(ColorCaptureFormat::Bgra, 1.0);
+        let still = vec![100u8; width * height * 4];
+        let mut moved = still.clone();
+        // A bright 64x64 square, a bit over 1% of the frame
+        for y in 100..164 {
+            moved[(y * width + 200) * 4..(y * width + 264) * 4].fill(250);
+        }
+        let mut check = |frame: &[u8]| {
+            // Every frame of the interval, so the next call starts at a sampled frame
+            (0..CHECK_INTERVAL)
+                .filter(|_| detector.detect(frame, width))
+                .count()
+                > 0
+        };
+        assert!(!check(&still));
+        assert!(!check(&still));
+        assert!(check(&moved));
+        assert!(!check(&moved));
+        // Frames smaller than the grid are ignored
+        assert!(!detector.detect(&still[..100], width));
+    }
+
+    #[test]
+    fn test_pre_roll_starts_at_keyframe() {
+        let secs = Duration::from_secs;
+        let mut pre_roll = PreRoll::new(secs(2));
+        pre_roll.push(secs(0), false, 0);
+        for t in 1..=5 {
+            pre_roll.push(secs(t), t % 2 == 1, t);
+        }
+        // Cutoff 3 s: keyframes at 1, 3 and 5, the latest at or before it is 3
+        let kept: Vec<u64> = pre_roll.iter().map(|f| f.2).collect();
+        assert_eq!(kept, vec![3, 4, 5]);
+    }
+
+    #[test]
+    fn test_clip_file_name() {
+        // 2024-02-29 13:45:30 UTC
+        let start = UNIX_EPOCH + Duration::from_secs(1_709_214_330);
+        assert_eq!(
+            clip_file_name("motion", start, RecordFormat::Mp4),
+            "motion-20240229-134530Z.mp4"
+        );
+        assert_eq!(
+            clip_file_name("motion", start, RecordFormat::Mkv),
+            "motion-20240229-134530Z.mkv"
+        );
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+    }
+
+    #[test]
+    fn test_parse_motion_threshold() {
+        assert_eq!(parse_motion_threshold("2.5"), Ok(2.5));
+        assert!(parse_motion_threshold("0").is_err());
+        assert!(parse_motion_threshold("101").is_err());
+        assert!(parse_motion_threshold("x").is_err());
+    }
+}
@@ -0,0 +1,67 @@
+//! Optional below-normal OS priority of the capture, publish, RTSP main-loop and
+//! GStreamer streaming threads (`--low-priority`), so streaming in the background
+//! leaves a shared desktop responsive.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use thread_priority::windows::WinAPIThreadPriority;
+use thread_priority::{ThreadPriority, set_current_thread_priority};
+
+static LOW_PRIORITY: AtomicBool = AtomicBool::new(false);
+
+/// `THREAD_PRIORITY_BELOW_NORMAL`: one step under normal, still above idle work.
+const PRIORITY: WinAPIThreadPriority = WinAPIThreadPriority::BelowNormal;
+
+/// Lowers the priority of every pipeline thread started from now on.
+pub fn enable() {
+    LOW_PRIORITY.store(true, Ordering::Relaxed);
+    log::info!("Running the pipeline threads at {PRIORITY:?} priority");
+}
+
+/// Lowers the priority of the calling thread. No-op unless [`enable`] was called.
+pub fn lower_current_thread() {
+    if !LOW_PRIORITY.load(Ordering::Relaxed) {
+        return;
+    }
+    let thread = std::thread::current();
+    let name = thread.name().unwrap_or("unnamed");
+    match set_current_thread_priority(ThreadPriority::Os(PRIORITY.into())) {
+        Ok(()) => log::info!("Thread '{name}' set to {PRIORITY:?} priority"),
+        Err(e) => log::warn!("Failed to lower the priority of thread '{name}': {e:?}"),
+    }
+}
+
+/// Lowers the priority of the streaming threads of `pipeline`, which run its
+/// encoders, as they start. No-op unless [`enable`] was called.
+pub fn lower_streaming_threads(pipeline: &gst::Pipeline) {
+    if !LOW_PRIORITY.load(Ordering::Relaxed) {
+        return;
+    }
+    let Some(bus) = pipeline.bus() else {
+        return;
+    };
+    // A streaming thread posts `Enter` from itself before it runs; a sync-message
+    // handler sees it on that thread and leaves the pipeline's own bus watch untouched
+    bus.enable_sync_message_emission();
+    bus.connect_sync_message(Some("stream-status"), |_, msg| {
+        let gst::MessageView::StreamStatus(status) = msg.view() else {
+            return;
+        };
+        let (kind, owner) = status.get();
+        if kind != gst::StreamStatusType::Enter {
+            return;
+        }
+        match set_current_thread_priority(ThreadPriority::Os(PRIORITY.into())) {
+            Ok(()) => log::debug!(
+                "{} streaming thread set to {PRIORITY:?} priority",
+                owner.name()
+            ),
+            Err(e) => log::warn!(
+                "Failed to lower the priority of the {} streaming thread: {e:?}",
+                owner.name()
+            ),
+        }
+    });
+}
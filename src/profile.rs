@@ -0,0 +1,70 @@
+//! `--profile`: tuned defaults for common scenarios. A profile only changes the
+//! defaults of the flags it lists; flags given on the command line, and registry
+//! settings, still win.
+
+use crate::encoder::{EncoderPreset, ScaleMethod};
+use crate::frame_queue::QueueFullPolicy;
+use crate::rtsp_publisher::CaptureMode;
+
+/// A bundle of defaults (`--profile`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Profile {
+    /// Freshest frames and fastest joins, for live monitoring and robotics.
+    LowLatency,
+    /// Best picture for the CPU, for viewing on large screens.
+    HighQuality,
+    /// Fewer frames and a total bitrate cap, for slow or metered links.
+    LowBandwidth,
+    /// Steady, always decodable streams, for NVRs and `--tee-record`.
+    Recording,
+}
+
+/// Values a profile sets; `None` leaves the flag at its own default.
+#[derive(Debug, Default, PartialEq)]
+pub struct ProfileDefaults {
+    pub encoder_preset: Option<EncoderPreset>,
+    pub scale_method: Option<ScaleMethod>,
+    pub queue_full_policy: Option<QueueFullPolicy>,
+    pub capture_mode: Option<CaptureMode>,
+    pub sps_pps_interval: Option<i32>,
+    pub color_publish_fps: Option<u32>,
+    pub infra_publish_fps: Option<u32>,
+    pub max_total_bitrate: Option<u64>,
+}
+
+impl Profile {
+    pub fn defaults(self) -> ProfileDefaults {
+        match self {
+            Profile::LowLatency => ProfileDefaults {
+                encoder_preset: Some(EncoderPreset::Low),
+                // A slow publish step skips to the newest frame instead of queueing
+                queue_full_policy: Some(QueueFullPolicy::DropOldest),
+                // No sensor start-up when the first client connects
+                capture_mode: Some(CaptureMode::AlwaysOn),
+                // SPS/PPS with every keyframe, so a client can decode the first one
+                sps_pps_interval: Some(-1),
+                ..Default::default()
+            },
+            Profile::HighQuality => ProfileDefaults {
+                encoder_preset: Some(EncoderPreset::High),
+                scale_method: Some(ScaleMethod::Lanczos),
+                ..Default::default()
+            },
+            Profile::LowBandwidth => ProfileDefaults {
+                // More encoder effort gives a better picture within the lower budget
+                encoder_preset: Some(EncoderPreset::High),
+                color_publish_fps: Some(15),
+                infra_publish_fps: Some(15),
+                max_total_bitrate: Some(4_000_000),
+                ..Default::default()
+            },
+            Profile::Recording => ProfileDefaults {
+                encoder_preset: Some(EncoderPreset::Medium),
+                capture_mode: Some(CaptureMode::AlwaysOn),
+                sps_pps_interval: Some(-1),
+                ..Default::default()
+            },
+        }
+    }
+}
@@ -0,0 +1,216 @@
+//! `--raw-tcp-color <port>` / `--raw-tcp-infrared <port>`: the raw frames over a
+//! plain TCP socket, for consumers that can't use GStreamer or shared memory.
+//!
+//! Wire format, all integers little-endian. On connect the server sends a 16-byte
+//! header: the magic `KRAW`, the pixel format as a FourCC (`YUY2`, `BGRA` or `RGBA`
+//! for color, `Y16 ` for 16-bit infrared), then the width and height as u32. Each
+//! frame follows as a u32 payload length and the payload, `width * height` pixels
+//! without padding. Every client gets its own small queue; a client too slow to
+//! keep up misses frames instead of holding up the publish loop or the others.
+
+use std::io::{BufWriter, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use crossbeam_channel::{Sender, TrySendError};
+use parking_lot::Mutex;
+
+/// Frames a client may fall behind before new ones are dropped for it.
+const CLIENT_QUEUE: usize = 2;
+
+/// The frame queues of the connected clients.
+type Clients = Arc<Mutex<Vec<Sender<Arc<[u8]>>>>>;
+
+/// Serves the frames pushed to it to every connected client.
+pub struct RawTcpServer {
+    label: &'static str,
+    clients: Clients,
+    local_addr: SocketAddr,
+}
+
+impl RawTcpServer {
+    /// Listens on `127.0.0.1:port`; the frames aren't authenticated, so they're only
+    /// reachable from the local host. `format` is the FourCC of the header, padded
+    /// with spaces.
+    pub fn start(
+        label: &'static str,
+        port: u16,
+        format: &str,
+        width: u32,
+        height: u32,
+    ) -> Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))
+            .with_context(|| format!("{label}: failed to listen on 127.0.0.1:{port}"))?;
+        let local_addr = listener.local_addr()?;
+        let header = header(format, width, height);
+        let clients = Clients::default();
+        let accepted = clients.clone();
+        std::thread::Builder::new()
+            .name(format!("{label} accept"))
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    match stream {
+                        Ok(stream) => {
+                            let (tx, rx) = crossbeam_channel::bounded(CLIENT_QUEUE);
+                            let peer = stream.peer_addr().ok();
+                            log::info!("{label}: client {peer:?} connected");
+                            let spawned = std::thread::Builder::new()
+                                .name(format!("{label} client"))
+                                .spawn(move || {
+                                    if let Err(e) = write_frames(stream, &header, rx) {
+                                        log::debug!("{label}: client {peer:?}: {e}");
+                                    }
+                                    log::info!("{label}: client {peer:?} disconnected");
+                                });
+                            match spawned {
+                                Ok(_) => accepted.lock().push(tx),
+                                Err(e) => log::warn!("{label}: failed to serve a client: {e}"),
+                            }
+                        }
+                        Err(e) => log::warn!("{label}: failed to accept a client: {e}"),
+                    }
+                }
+            })?;
+        Ok(Self {
+            label,
+            clients,
+            local_addr,
+        })
+    }
+
+    /// The address the server listens on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Whether a client is connected, so the capture must run. A client that left
+    /// counts until the next frame finds it gone.
+    pub fn has_clients(&self) -> bool {
+        !self.clients.lock().is_empty()
+    }
+
+    /// Queues `data` for every client, skipping those whose queue is full.
+    pub fn push(&self, data: &[u8]) {
+        self.push_frame(|| data.into());
+    }
+
+    /// Queues 16-bit `data` as little-endian bytes, like [`Self::push`].
+    pub fn push_u16(&self, data: &[u16]) {
+        self.push_frame(|| {
+            // Sized up front, so the frame is allocated once and filled in place
+            let mut frame: Arc<[u8]> = std::iter::repeat_n(0, data.len() * 2).collect();
+            let bytes = Arc::get_mut(&mut frame).expect("not shared yet");
+            for (pair, value) in bytes.chunks_exact_mut(2).zip(data) {
+                pair.copy_from_slice(&value.to_le_bytes());
+            }
+            frame
+        });
+    }
+
+    /// Queues the frame built by `frame` for every client; it's only built if there
+    /// is one.
+    fn push_frame(&self, frame: impl FnOnce() -> Arc<[u8]>) {
+        let mut clients = self.clients.lock();
+        if clients.is_empty() {
+            return;
+        }
+        let frame = frame();
+        clients.retain(|client| match client.try_send(frame.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                log::debug!("{}: client too slow, frame dropped", self.label);
+                true
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+    }
+}
+
+/// The header sent once to every client.
+fn header(format: &str, width: u32, height: u32) -> [u8; 16] {
+    let mut header = [b' '; 16];
+    header[..4].copy_from_slice(b"KRAW");
+    for (byte, &format) in header[4..8].iter_mut().zip(format.as_bytes()) {
+        *byte = format;
+    }
+    header[8..12].copy_from_slice(&width.to_le_bytes());
+    header[12..].copy_from_slice(&height.to_le_bytes());
+    header
+}
+
+/// Writes a length-prefixed frame.
+fn write_frame(writer: &mut impl Write, frame: &[u8]) -> std::io::Result<()> {
+    let len = u32::try_from(frame.len()).map_err(std::io::Error::other)?;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(frame)?;
+    writer.flush()
+}
+
+/// Sends the header and then the queued frames until the client goes away.
+fn write_frames(
+    stream: TcpStream,
+    header: &[u8],
+    frames: crossbeam_channel::Receiver<Arc<[u8]>>,
+) -> std::io::Result<()> {
+    stream.set_nodelay(true)?;
+    let mut writer = BufWriter::new(stream);
+    writer.write_all(header)?;
+    writer.flush()?;
+    for frame in frames {
+        write_frame(&mut writer, &frame)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_client_gets_the_header_and_the_frames() {
+        let server = RawTcpServer::start("Raw test", 0, "Y16", 4, 2).unwrap();
+        assert!(!server.has_clients());
+        let mut client = TcpStream::connect(server.local_addr()).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut header = [0u8; 16];
+        client.read_exact(&mut header).unwrap();
+        assert_eq!(&header[..8], b"KRAWY16 ");
+        assert_eq!(header[8..12], 4u32.to_le_bytes());
+        assert_eq!(header[12..], 2u32.to_le_bytes());
+
+        // The client is registered once its thread runs, maybe after the header arrived
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !server.has_clients() {
+            assert!(Instant::now() < deadline);
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        server.push(&[7; 16]);
+        let mut len = [0u8; 4];
+        client.read_exact(&mut len).unwrap();
+        assert_eq!(u32::from_le_bytes(len), 16);
+        let mut frame = [0u8; 16];
+        client.read_exact(&mut frame).unwrap();
+        assert_eq!(frame, [7; 16]);
+
+        server.push_u16(&[0x0102, 0x0304]);
+        client.read_exact(&mut len).unwrap();
+        assert_eq!(u32::from_le_bytes(len), 4);
+        let mut frame = [0u8; 4];
+        client.read_exact(&mut frame).unwrap();
+        assert_eq!(frame, [2, 1, 4, 3]);
+
+        drop(client);
+        // The writer notices the closed socket on a write, the push after that drops it
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while server.has_clients() {
+            assert!(Instant::now() < deadline);
+            server.push(&[0; 16]);
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
@@ -0,0 +1,101 @@
+//! Server settings read from a registry key under `HKEY_LOCAL_MACHINE`, so managed
+//! deployments can push them through group policy. Command-line flags take
+//! precedence over every value found here.
+
+use std::io;
+
+use anyhow::Context;
+use clap::ValueEnum;
+use winreg::RegKey;
+use winreg::enums::{HKEY_LOCAL_MACHINE, KEY_READ};
+
+use crate::encoder::{EncoderPreset, VideoEncoder};
+
+/// Key read when `--registry-key` isn't given, the usual place for policy settings.
+pub const DEFAULT_KEY: &str = r"SOFTWARE\Policies\KinectRTSP";
+
+/// Values found under the key; `None` for each one that is absent.
+#[derive(Debug, Default)]
+pub struct RegistrySettings {
+    /// `Port` (REG_DWORD)
+    pub port: Option<u16>,
+    /// `Username` (REG_SZ)
+    pub username: Option<String>,
+    /// `Password` (REG_SZ)
+    pub password: Option<String>,
+    /// `AuthRealm` (REG_SZ)
+    pub auth_realm: Option<String>,
+    /// `SoftwareEncoder` (REG_SZ), as with `--software-encoder`
+    pub software_encoder: Option<VideoEncoder>,
+    /// `EncoderPreset` (REG_SZ), as with `--encoder-preset`
+    pub encoder_preset: Option<EncoderPreset>,
+}
+
+/// Reads `value` from `key`, mapping an absent value to `None`.
+fn optional<T: winreg::types::FromRegValue>(
+    key: &RegKey,
+    value: &str,
+) -> anyhow::Result<Option<T>> {
+    match key.get_value(value) {
+        Ok(v) => Ok(Some(v)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("Failed to read registry value {value}")),
+    }
+}
+
+/// Parses a registry string the way its flag would be, e.g. `x264` or `medium`.
+fn parse_enum<T: ValueEnum>(value: &str, text: &str) -> anyhow::Result<T> {
+    T::from_str(text.trim(), true)
+        .map_err(|e| anyhow::anyhow!("Invalid registry value {value}={text:?}: {e}"))
+}
+
+/// Reads the settings under `HKLM\<subkey>`. A missing key yields no settings.
+pub fn read(subkey: &str) -> anyhow::Result<RegistrySettings> {
+    let key = match RegKey::predef(HKEY_LOCAL_MACHINE).open_subkey_with_flags(subkey, KEY_READ) {
+        Ok(key) => key,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            log::debug!(r"No registry settings at HKLM\{subkey}");
+            return Ok(RegistrySettings::default());
+        }
+        Err(e) => return Err(e).with_context(|| format!(r"Failed to open HKLM\{subkey}")),
+    };
+    let port = optional::<u32>(&key, "Port")?
+        .map(|port| {
+            u16::try_from(port).map_err(|_| anyhow::anyhow!("Invalid registry value Port={port}"))
+        })
+        .transpose()?;
+    let software_encoder = optional::<String>(&key, "SoftwareEncoder")?
+        .map(|text| parse_enum("SoftwareEncoder", &text))
+        .transpose()?;
+    let encoder_preset = optional::<String>(&key, "EncoderPreset")?
+        .map(|text| parse_enum("EncoderPreset", &text))
+        .transpose()?;
+    let settings = RegistrySettings {
+        port,
+        username: optional(&key, "Username")?,
+        password: optional(&key, "Password")?,
+        auth_realm: optional(&key, "AuthRealm")?,
+        software_encoder,
+        encoder_preset,
+    };
+    log::info!(r"Read server settings from HKLM\{subkey}");
+    Ok(settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_enum_matches_the_cli_names() {
+        assert_eq!(
+            parse_enum::<VideoEncoder>("SoftwareEncoder", "X264 ").unwrap(),
+            VideoEncoder::X264
+        );
+        assert_eq!(
+            parse_enum::<EncoderPreset>("EncoderPreset", "medium").unwrap(),
+            EncoderPreset::Medium
+        );
+        assert!(parse_enum::<EncoderPreset>("EncoderPreset", "fast").is_err());
+    }
+}
@@ -0,0 +1,121 @@
+//! `--rtp-stats-secs`: RTP statistics of each mount, read from the `rtpbin` sessions
+//! of its media. The packets sent tell whether the encoder produces anything, the
+//! loss in the receivers' RTCP receiver reports whether the network delivers it.
+
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use gstreamer_rtsp_server as rtsp;
+use gstreamer_rtsp_server::prelude::*;
+
+/// Counters of a media, summed over its streams (video and audio). They start over
+/// with every new media.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RtpStats {
+    pub packets_sent: u64,
+    pub bytes_sent: u64,
+    /// Whether a receiver report came in, without one the loss is unknown.
+    pub has_reports: bool,
+    /// Packets lost in total according to the latest receiver report of each stream.
+    pub packets_lost: u64,
+    /// Highest fraction (0 to 1) of the packets lost in the interval the latest
+    /// receiver reports cover.
+    pub fraction_lost: f64,
+}
+
+impl RtpStats {
+    /// Reads the current counters of `media`.
+    pub fn read(media: &rtsp::RTSPMedia) -> Self {
+        let mut stats = Self::default();
+        for index in 0..media.n_streams() {
+            let Some(session) = media.stream(index).and_then(|s| s.rtpsession()) else {
+                continue;
+            };
+            let session_stats = session.property::<gst::Structure>("stats");
+            let Ok(sources) = session_stats.get::<glib::ValueArray>("source-stats") else {
+                continue;
+            };
+            for source in sources.iter() {
+                if let Ok(source) = source.get::<gst::Structure>() {
+                    stats.add_source(&source);
+                }
+            }
+        }
+        stats
+    }
+
+    /// Adds the `source-stats` entry of one RTP source. Only our own sources count:
+    /// the reports of the receivers about them land on them as `rb-*` fields.
+    fn add_source(&mut self, source: &gst::StructureRef) {
+        if !source.get::<bool>("internal").unwrap_or(false) {
+            return;
+        }
+        self.packets_sent += source.get::<u64>("packets-sent").unwrap_or(0);
+        self.bytes_sent += source.get::<u64>("octets-sent").unwrap_or(0);
+        if source.get::<bool>("have-rb").unwrap_or(false) {
+            self.has_reports = true;
+            let lost = source.get::<i32>("rb-packetslost").unwrap_or(0);
+            self.packets_lost += u64::try_from(lost).unwrap_or(0);
+            // Fixed point, in 1/256
+            let fraction = f64::from(source.get::<u32>("rb-fractionlost").unwrap_or(0)) / 256.0;
+            self.fraction_lost = self.fraction_lost.max(fraction);
+        }
+    }
+
+    /// Packets and bytes sent since `previous`, an earlier sample of the same media;
+    /// all of them if the media was recreated in between.
+    pub fn sent_since(&self, previous: &Self) -> (u64, u64) {
+        if self.packets_sent < previous.packets_sent {
+            return (self.packets_sent, self.bytes_sent);
+        }
+        (
+            self.packets_sent - previous.packets_sent,
+            self.bytes_sent.saturating_sub(previous.bytes_sent),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(internal: bool, sent: u64, report: Option<(i32, u32)>) -> gst::Structure {
+        let mut s = gst::Structure::builder("application/x-rtp-source-stats")
+            .field("internal", internal)
+            .field("packets-sent", sent)
+            .field("octets-sent", sent * 1000)
+            .field("have-rb", report.is_some())
+            .build();
+        if let Some((lost, fraction)) = report {
+            s.set("rb-packetslost", lost);
+            s.set("rb-fractionlost", fraction);
+        }
+        s
+    }
+
+    #[test]
+    fn test_sums_our_sources_and_their_reports() {
+        gst::init().unwrap();
+        let mut stats = RtpStats::default();
+        stats.add_source(&source(true, 100, None));
+        assert!(!stats.has_reports);
+        stats.add_source(&source(true, 50, Some((3, 64))));
+        // A receiver's own source isn't ours
+        stats.add_source(&source(false, 1000, Some((7, 255))));
+        assert_eq!(stats.packets_sent, 150);
+        assert_eq!(stats.bytes_sent, 150_000);
+        assert!(stats.has_reports);
+        assert_eq!(stats.packets_lost, 3);
+        assert_eq!(stats.fraction_lost, 0.25);
+    }
+
+    #[test]
+    fn test_sent_since_restarts_with_a_new_media() {
+        let sample = |packets_sent, bytes_sent| RtpStats {
+            packets_sent,
+            bytes_sent,
+            ..Default::default()
+        };
+        assert_eq!(sample(150, 9000).sent_since(&sample(100, 6000)), (50, 3000));
+        assert_eq!(sample(20, 1000).sent_since(&sample(100, 6000)), (20, 1000));
+    }
+}
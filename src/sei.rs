@@ -0,0 +1,167 @@
+//! Per-frame H.264 SEI carrying a frame counter and the capture time, so
+//! downstream tools can check frame continuity (`--embed-frame-metadata`).
+//!
+//! Each access unit gets a `user_data_unregistered` SEI (payload type 5) right
+//! before its first slice. Payload: [`FRAME_METADATA_UUID`] followed by the frame
+//! index and the capture time in microseconds since the Unix epoch, both u64
+//! big-endian. With `--overlay-frame-number` a third u64 follows: the frame number
+//! burned into the picture.
+//!
+//! With `--embed-location` a picture gets a second such SEI about once a second
+//! while a location is known: [`LOCATION_UUID`] followed by latitude, longitude and
+//! altitude (NaN if unknown) as f64 big-endian.
+
+/// UUID identifying our SEI payload among other user data.
+pub const FRAME_METADATA_UUID: [u8; 16] = [
+    0x6b, 0x69, 0x6e, 0x65, 0x63, 0x74, 0x2d, 0x72, 0x74, 0x73, 0x70, 0x2d, 0x66, 0x6d, 0x65, 0x74,
+];
+
+/// UUID identifying our location SEI payload.
+pub const LOCATION_UUID: [u8; 16] = [
+    0x6b, 0x69, 0x6e, 0x65, 0x63, 0x74, 0x2d, 0x72, 0x74, 0x73, 0x70, 0x2d, 0x67, 0x6c, 0x6f, 0x63,
+];
+
+const START_CODE: [u8; 4] = [0, 0, 0, 1];
+const NAL_TYPE_SEI: u8 = 6;
+const SEI_USER_DATA_UNREGISTERED: u8 = 5;
+
+/// Builds the complete SEI NAL unit, start code included.
+pub fn frame_metadata_sei(
+    frame_index: u64,
+    capture_unix_micros: u64,
+    frame_number: Option<u64>,
+) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(40);
+    payload.extend_from_slice(&FRAME_METADATA_UUID);
+    payload.extend_from_slice(&frame_index.to_be_bytes());
+    payload.extend_from_slice(&capture_unix_micros.to_be_bytes());
+    if let Some(number) = frame_number {
+        payload.extend_from_slice(&number.to_be_bytes());
+    }
+    user_data_sei(&payload)
+}
+
+/// Builds the complete location SEI NAL unit, start code included.
+pub fn location_sei(lat: f64, lon: f64, alt: Option<f64>) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(40);
+    payload.extend_from_slice(&LOCATION_UUID);
+    for value in [lat, lon, alt.unwrap_or(f64::NAN)] {
+        payload.extend_from_slice(&value.to_be_bytes());
+    }
+    user_data_sei(&payload)
+}
+
+/// Wraps a `user_data_unregistered` payload (shorter than 255 bytes) in an SEI NAL unit.
+fn user_data_sei(payload: &[u8]) -> Vec<u8> {
+    let mut rbsp = vec![SEI_USER_DATA_UNREGISTERED, payload.len() as u8];
+    rbsp.extend_from_slice(payload);
+    rbsp.push(0x80); // rbsp_trailing_bits
+
+    let mut nal = START_CODE.to_vec();
+    nal.push(NAL_TYPE_SEI);
+    nal.extend(escape_emulation(&rbsp));
+    nal
+}
+
+/// Inserts an emulation prevention byte after every `00 00` followed by a byte <= 3.
+fn escape_emulation(rbsp: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(rbsp.len() + 4);
+    let mut zeros = 0;
+    for &byte in rbsp {
+        if zeros == 2 && byte <= 3 {
+            out.push(3);
+            zeros = 0;
+        }
+        out.push(byte);
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+    }
+    out
+}
+
+/// Returns `data` (Annex B byte-stream) with `sei` inserted before the start code
+/// of the first slice of a picture, or `None` if `data` doesn't start a picture.
+pub fn insert_before_first_slice(data: &[u8], sei: &[u8]) -> Option<Vec<u8>> {
+    let position = nal_units(data).find(|&(_, header)| {
+        let nal_type = data[header] & 0x1f;
+        // first_mb_in_slice is ue(v) coded, a leading 1 bit means 0: the picture's first slice
+        matches!(nal_type, 1 | 5) && data.get(header + 1).is_some_and(|b| b & 0x80 != 0)
+    })?;
+    let start = position.0;
+    let mut out = Vec::with_capacity(data.len() + sei.len());
+    out.extend_from_slice(&data[..start]);
+    out.extend_from_slice(sei);
+    out.extend_from_slice(&data[start..]);
+    Some(out)
+}
+
+/// Iterates over the NAL units of an Annex B stream as (start code offset, header offset).
+fn nal_units(data: &[u8]) -> impl Iterator<Item = (usize, usize)> + '_ {
+    (0..data.len().saturating_sub(2)).filter_map(move |i| {
+        if data[i] != 0 || data[i + 1] != 0 || data[i + 2] != 1 || i + 3 >= data.len() {
+            return None;
+        }
+        // Include the leading zero of a 4-byte start code
+        let start = if i > 0 && data[i - 1] == 0 { i - 1 } else { i };
+        Some((start, i + 3))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sei_layout() {
+        let sei = frame_metadata_sei(7, 1_000, None);
+        assert_eq!(
+            &sei[..6],
+            &[0, 0, 0, 1, NAL_TYPE_SEI, SEI_USER_DATA_UNREGISTERED]
+        );
+        assert_eq!(sei[6], 32);
+        assert_eq!(&sei[7..23], &FRAME_METADATA_UUID);
+        assert_eq!(*sei.last().unwrap(), 0x80);
+
+        // No zero bytes, so nothing is escaped before the number
+        let number = 0x0102_0304_0506_0708;
+        let numbered = frame_metadata_sei(u64::MAX, u64::MAX, Some(number));
+        assert_eq!(numbered[6], 40);
+        assert_eq!(&numbered[39..47], &number.to_be_bytes());
+    }
+
+    #[test]
+    fn test_location_sei_layout() {
+        let sei = location_sei(48.1173, 11.5167, None);
+        assert_eq!(sei[6], 40);
+        assert_eq!(&sei[7..23], &LOCATION_UUID);
+        assert_eq!(&sei[23..31], &48.1173f64.to_be_bytes());
+    }
+
+    #[test]
+    fn test_emulation_prevention() {
+        // Frame index 0 contains long zero runs that must be escaped
+        let sei = frame_metadata_sei(0, 0, Some(0));
+        assert!(
+            !sei[4..]
+                .windows(3)
+                .any(|w| w[0] == 0 && w[1] == 0 && w[2] < 3)
+        );
+        assert_eq!(
+            escape_emulation(&[0, 0, 1, 0, 0, 4]),
+            vec![0, 0, 3, 1, 0, 0, 4]
+        );
+    }
+
+    #[test]
+    fn test_inserted_before_first_slice_only() {
+        let sps = [0, 0, 0, 1, 0x67, 0x42];
+        let idr = [0, 0, 0, 1, 0x65, 0x88, 0x84];
+        let second_slice = [0, 0, 1, 0x65, 0x40, 0x84];
+        let au = [&sps[..], &idr, &second_slice].concat();
+        let sei = [0, 0, 0, 1, 6, 5];
+
+        let out = insert_before_first_slice(&au, &sei).unwrap();
+        assert_eq!(out, [&sps[..], &sei, &idr, &second_slice].concat());
+        assert!(insert_before_first_slice(&second_slice, &sei).is_none());
+        assert!(insert_before_first_slice(&sps, &sei).is_none());
+    }
+}
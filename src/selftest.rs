@@ -0,0 +1,310 @@
+//! `selftest` subcommand: briefly exercises the real capture and encode path of every
+//! stream and prints a PASS/FAIL line per step with the reason of each failure.
+
+use std::fmt::Display;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use gstreamer_app as gst_app;
+use kinect_v2::Kinect;
+#[cfg(feature = "audio")]
+use kinect_v2::audio_capture::AudioFrameCapture;
+use kinect_v2::color_capture::ColorFrameCapture;
+use kinect_v2::depth_capture::DepthFrameCapture;
+use kinect_v2::infrared_capture::InfraredFrameCapture;
+
+use crate::color::ColorCaptureFormat;
+use crate::encoder::{BFrames, EncoderPreset, VideoEncoder};
+
+/// Frames captured and encoded per source.
+const FRAMES: usize = 3;
+/// How long a source may take to deliver its frames.
+const CAPTURE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long the device may take to show up.
+const DEVICE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Outcome of one step: a short detail on success, the failure reason otherwise.
+type StepResult = Result<String, String>;
+
+/// Runs every step, prints the report and returns whether all steps passed.
+pub fn run(encoder: VideoEncoder, color_format: ColorCaptureFormat) -> Result<bool> {
+    let (report, passed) = report(encoder, color_format);
+    print!("{report}");
+    Ok(passed)
+}
+
+/// Runs every step and returns the report, a PASS or FAIL line per step, and
+/// whether all steps passed.
+pub fn report(encoder: VideoEncoder, color_format: ColorCaptureFormat) -> (String, bool) {
+    let mut results: Vec<(&str, StepResult)> = Vec::new();
+
+    let gstreamer = check_gstreamer(encoder);
+    let gstreamer_ok = gstreamer.is_ok();
+    results.push(("gstreamer", gstreamer));
+
+    let device = check_device();
+    let device_ok = device.is_ok();
+    results.push(("device", device));
+
+    let skipped = |reason: &str| Err(format!("skipped: {reason}"));
+    let steps: &[(&str, fn(VideoEncoder, ColorCaptureFormat) -> StepResult)] = &[
+        ("color", test_color),
+        ("infrared", test_infrared),
+        ("depth", test_depth),
+        #[cfg(feature = "audio")]
+        ("audio", test_audio),
+    ];
+    for &(name, step) in steps {
+        let result = if !device_ok {
+            skipped("no device")
+        } else if !gstreamer_ok {
+            skipped("GStreamer check failed")
+        } else {
+            step(encoder, color_format)
+        };
+        results.push((name, result));
+    }
+
+    let mut passed = true;
+    let mut report = String::new();
+    for (name, result) in &results {
+        match result {
+            Ok(detail) => report.push_str(&format!("{name:<10} PASS  {detail}\n")),
+            Err(reason) => {
+                passed = false;
+                report.push_str(&format!("{name:<10} FAIL  {reason}\n"));
+            }
+        }
+    }
+    (report, passed)
+}
+
+fn check_gstreamer(encoder: VideoEncoder) -> StepResult {
+    gst::init().map_err(|e| format!("GStreamer failed to initialize: {e}"))?;
+    let required = [
+        "appsrc",
+        "appsink",
+        "videoconvert",
+        encoder.element_name(),
+        "h264parse",
+        "rtph264pay",
+        #[cfg(feature = "audio")]
+        "audioconvert",
+        #[cfg(feature = "audio")]
+        "audioresample",
+        #[cfg(feature = "audio")]
+        "opusenc",
+        #[cfg(feature = "audio")]
+        "rtpopuspay",
+    ];
+    let missing: Vec<&str> = required
+        .into_iter()
+        .filter(|name| gst::ElementFactory::find(name).is_none())
+        .collect();
+    if missing.is_empty() {
+        Ok(format!("GStreamer {}", gst::version_string()))
+    } else {
+        Err(format!("missing element(s): {}", missing.join(", ")))
+    }
+}
+
+fn check_device() -> StepResult {
+    let kinect = Kinect::new().map_err(|e| format!("Kinect runtime unavailable: {e}"))?;
+    let started = Instant::now();
+    loop {
+        match kinect.is_available() {
+            Ok(true) => return Ok("Kinect available".to_string()),
+            Ok(false) if started.elapsed() < DEVICE_TIMEOUT => {
+                std::thread::sleep(Duration::from_millis(200))
+            }
+            Ok(false) => {
+                return Err(format!(
+                    "no device within {}s (USB 3.0 connection and power supply?)",
+                    DEVICE_TIMEOUT.as_secs()
+                ));
+            }
+            Err(e) => return Err(format!("device query failed: {e}")),
+        }
+    }
+}
+
+/// Polls `next` until `FRAMES` frames arrived or `CAPTURE_TIMEOUT` passed.
+fn capture_frames<T, E: Display>(
+    mut next: impl FnMut() -> Option<Result<T, E>>,
+) -> Result<Vec<T>, String> {
+    let started = Instant::now();
+    let mut frames = Vec::new();
+    while frames.len() < FRAMES {
+        match next() {
+            Some(Ok(frame)) => frames.push(frame),
+            Some(Err(e)) => return Err(format!("capture error: {e}")),
+            None if started.elapsed() >= CAPTURE_TIMEOUT => {
+                return Err(format!(
+                    "no frames within {}s ({} received)",
+                    CAPTURE_TIMEOUT.as_secs(),
+                    frames.len()
+                ));
+            }
+            None => std::thread::sleep(Duration::from_millis(5)),
+        }
+    }
+    Ok(frames)
+}
+
+/// Pushes `buffers` through `appsrc caps={caps} ! {branch} ! appsink` and returns the
+/// number of buffers that reached the sink.
+fn run_through(caps: &str, branch: &str, buffers: Vec<Vec<u8>>) -> Result<usize> {
+    let launch = format!(
+        "appsrc name=src format=time caps={caps} ! {branch} ! appsink name=sink sync=false"
+    );
+    let pipeline = gst::parse::launch(&launch)
+        .with_context(|| format!("Failed to build pipeline: {launch}"))?
+        .downcast::<gst::Pipeline>()
+        .map_err(|_| anyhow::anyhow!("Self-test pipeline is not a gst::Pipeline"))?;
+    let appsrc = pipeline
+        .by_name("src")
+        .and_then(|e| e.downcast::<gst_app::AppSrc>().ok())
+        .context("Self-test pipeline has no appsrc")?;
+    let appsink = pipeline
+        .by_name("sink")
+        .and_then(|e| e.downcast::<gst_app::AppSink>().ok())
+        .context("Self-test pipeline has no appsink")?;
+
+    let received = Arc::new(AtomicUsize::new(0));
+    let received_sink = received.clone();
+    appsink.set_callbacks(
+        gst_app::AppSinkCallbacks::builder()
+            .new_sample(move |sink| {
+                sink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
+                received_sink.fetch_add(1, Ordering::Relaxed);
+                Ok(gst::FlowSuccess::Ok)
+            })
+            .build(),
+    );
+
+    pipeline.set_state(gst::State::Playing)?;
+    let frame_duration = gst::ClockTime::from_mseconds(33);
+    for (index, data) in buffers.into_iter().enumerate() {
+        let mut buffer = gst::Buffer::from_mut_slice(data);
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(frame_duration * index as u64);
+            buffer.set_duration(frame_duration);
+        }
+        appsrc.push_buffer(buffer)?;
+    }
+    appsrc.end_of_stream()?;
+
+    let bus = pipeline.bus().context("Self-test pipeline has no bus")?;
+    let msg = bus.timed_pop_filtered(
+        gst::ClockTime::from_seconds(10),
+        &[gst::MessageType::Eos, gst::MessageType::Error],
+    );
+    pipeline.set_state(gst::State::Null)?;
+    match msg {
+        Some(msg) => {
+            if let gst::MessageView::Error(err) = msg.view() {
+                return Err(anyhow::anyhow!("{}", err.error()));
+            }
+        }
+        None => return Err(anyhow::anyhow!("pipeline did not finish within 10s")),
+    }
+    Ok(received.load(Ordering::Relaxed))
+}
+
+/// Encodes raw video frames up to the RTP payloader.
+fn encode_video(caps: &str, encoder: VideoEncoder, frames: Vec<Vec<u8>>) -> StepResult {
+    let branch = format!(
+        "videoconvert ! video/x-raw,format=I420 ! {} ! h264parse ! rtph264pay",
+        encoder.encoder_fragment(1_500_000, 30, EncoderPreset::Low, BFrames::default())
+    );
+    let frame_count = frames.len();
+    match run_through(caps, &branch, frames) {
+        Ok(0) => Err(format!(
+            "{frame_count} frames captured, but no RTP packets came out of the encoder"
+        )),
+        Ok(packets) => Ok(format!(
+            "{frame_count} frames captured, {packets} RTP packets"
+        )),
+        Err(e) => Err(format!("encoding failed: {e:#}")),
+    }
+}
+
+fn test_color(encoder: VideoEncoder, format: ColorCaptureFormat) -> StepResult {
+    let capture = ColorFrameCapture::new_with_format(format.image_format())
+        .map_err(|e| format!("capture unavailable: {e}"))?;
+    let mut iter = capture
+        .iter()
+        .map_err(|e| format!("capture unavailable: {e}"))?;
+    let frames = capture_frames(|| iter.next())?;
+    let (width, height) = (frames[0].width, frames[0].height);
+    let caps = format!(
+        "video/x-raw,format={},width={width},height={height},framerate=30/1",
+        format.caps_format()
+    );
+    encode_video(&caps, encoder, frames.into_iter().map(|f| f.data).collect())
+}
+
+fn test_infrared(encoder: VideoEncoder, _: ColorCaptureFormat) -> StepResult {
+    let capture = InfraredFrameCapture::new().map_err(|e| format!("capture unavailable: {e}"))?;
+    let mut iter = capture
+        .iter()
+        .map_err(|e| format!("capture unavailable: {e}"))?;
+    let frames = capture_frames(|| iter.next())?;
+    let (width, height) = (frames[0].width, frames[0].height);
+    let caps = format!("video/x-raw,format=GRAY16_LE,width={width},height={height},framerate=30/1");
+    let buffers = frames
+        .into_iter()
+        .map(|f| f.data.iter().flat_map(|v| v.to_le_bytes()).collect())
+        .collect();
+    encode_video(&caps, encoder, buffers)
+}
+
+fn test_depth(_: VideoEncoder, _: ColorCaptureFormat) -> StepResult {
+    let capture = DepthFrameCapture::new().map_err(|e| format!("capture unavailable: {e}"))?;
+    let mut iter = capture
+        .iter()
+        .map_err(|e| format!("capture unavailable: {e}"))?;
+    let frames = capture_frames(|| iter.next())?;
+    let measured = frames[0].data.iter().filter(|&&d| d != 0).count();
+    if measured == 0 {
+        return Err(format!(
+            "{} frames captured, but every pixel is invalid (0)",
+            frames.len()
+        ));
+    }
+    Ok(format!(
+        "{} frames captured, {measured} valid pixels in the first",
+        frames.len()
+    ))
+}
+
+#[cfg(feature = "audio")]
+fn test_audio(_: VideoEncoder, _: ColorCaptureFormat) -> StepResult {
+    let capture = AudioFrameCapture::new().map_err(|e| format!("capture unavailable: {e}"))?;
+    let mut iter = capture
+        .iter()
+        .map_err(|e| format!("capture unavailable: {e}"))?;
+    let frames = capture_frames(|| iter.next())?;
+    let frame_count = frames.len();
+    // Kinect audio is 32-bit float mono at 16 kHz
+    let caps = "audio/x-raw,format=F32LE,layout=interleaved,rate=16000,channels=1";
+    let buffers = frames.into_iter().map(|f| f.data).collect();
+    match run_through(
+        caps,
+        "audioconvert ! audioresample ! opusenc ! rtpopuspay",
+        buffers,
+    ) {
+        Ok(0) => Err(format!(
+            "{frame_count} frames captured, but no RTP packets came out of opusenc"
+        )),
+        Ok(packets) => Ok(format!(
+            "{frame_count} frames captured, {packets} RTP packets"
+        )),
+        Err(e) => Err(format!("encoding failed: {e:#}")),
+    }
+}
@@ -0,0 +1,291 @@
+//! `--snapshot-sequence-dir`: writes a raw color and infrared frame every
+//! `--snapshot-interval-ms` as numbered lossless PNGs, e.g. for collecting training
+//! data. Color goes to `color/frame_000001.png` as 8-bit RGB, infrared to
+//! `infrared/frame_000001.png` as 16-bit grayscale with the sensor values untouched.
+//! With `--snapshot-manifest` each directory also gets a hash chain over its files,
+//! see [`crate::hash_chain`].
+
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use crossbeam_channel::{Sender, TrySendError};
+use parking_lot::Mutex;
+
+use crate::color::ColorCaptureFormat;
+use crate::hash_chain::ManifestWriter;
+
+/// Frames waiting for the writer; further frames are skipped while it's this far behind.
+const QUEUE_DEPTH: usize = 8;
+
+enum Image {
+    /// A raw color frame, converted to RGB by the writer.
+    Color(Vec<u8>),
+    Gray16(Vec<u16>),
+}
+
+struct Job {
+    /// Index into the writer's sequences and manifests, 0 for color and 1 for infrared.
+    stream: usize,
+    unix_ms: u64,
+    width: u32,
+    height: u32,
+    image: Image,
+}
+
+fn unix_ms_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+/// Numbering of one stream's files, kept by the writer so only written frames
+/// take a number.
+struct Sequence {
+    dir: PathBuf,
+    next_index: u64,
+}
+
+impl Sequence {
+    fn open(dir: PathBuf) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        // Continue after an earlier run instead of overwriting its frames
+        let next_index = last_index(&dir)? + 1;
+        Ok(Self { dir, next_index })
+    }
+
+    /// Path of the next file.
+    fn next_path(&self) -> PathBuf {
+        self.dir.join(frame_file_name(self.next_index))
+    }
+}
+
+/// When one stream's next frame is due.
+#[derive(Default)]
+struct Schedule {
+    next_due: Option<Instant>,
+}
+
+impl Schedule {
+    /// Whether a frame is due at `now`; if so the next one is due `interval` later.
+    fn take_due(&mut self, now: Instant, interval: Duration) -> bool {
+        if self.next_due.is_some_and(|due| now < due) {
+            return false;
+        }
+        self.next_due = Some(now + interval);
+        true
+    }
+}
+
+fn frame_file_name(index: u64) -> String {
+    format!("frame_{index:06}.png")
+}
+
+/// Highest `frame_NNNNNN.png` number in `dir`, 0 if there is none.
+fn last_index(dir: &Path) -> anyhow::Result<u64> {
+    let entries =
+        std::fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))?;
+    Ok(entries
+        .filter_map(|entry| {
+            let name = entry.ok()?.file_name();
+            name.to_str()?
+                .strip_prefix("frame_")?
+                .strip_suffix(".png")?
+                .parse::<u64>()
+                .ok()
+        })
+        .max()
+        .unwrap_or(0))
+}
+
+pub struct SnapshotSequence {
+    interval: Duration,
+    color: Mutex<Schedule>,
+    infrared: Mutex<Schedule>,
+    /// Taken by [`Self::finish`], which ends the writer thread.
+    jobs: Mutex<Option<Sender<Job>>>,
+    writer: Mutex<Option<JoinHandle<u64>>>,
+}
+
+impl SnapshotSequence {
+    /// Creates the `color` and `infrared` directories under `dir` and starts the
+    /// writer thread. `color_format` is the format of the frames [`Self::offer_color`] gets.
+    /// With `manifest` every file is also added to its directory's hash chain.
+    pub fn start(
+        dir: &Path,
+        interval: Duration,
+        color_format: ColorCaptureFormat,
+        manifest: bool,
+    ) -> anyhow::Result<Self> {
+        let mut sequences = [
+            Sequence::open(dir.join("color"))?,
+            Sequence::open(dir.join("infrared"))?,
+        ];
+        let mut manifests = if manifest {
+            vec![
+                ManifestWriter::open(&sequences[0].dir)?,
+                ManifestWriter::open(&sequences[1].dir)?,
+            ]
+        } else {
+            Vec::new()
+        };
+        let (jobs_tx, jobs_rx) = crossbeam_channel::bounded::<Job>(QUEUE_DEPTH);
+        let writer = std::thread::Builder::new()
+            .name("snapshot-writer".into())
+            .spawn(move || {
+                let mut written = 0;
+                for job in jobs_rx {
+                    let sequence = &mut sequences[job.stream];
+                    let manifest = manifests.get_mut(job.stream);
+                    match write_png(&sequence.next_path(), job, color_format, manifest) {
+                        Ok(()) => {
+                            sequence.next_index += 1;
+                            written += 1;
+                        }
+                        Err(e) => log::warn!("Failed to write snapshot: {e:#}"),
+                    }
+                }
+                for manifest in &manifests {
+                    let (path, head) = manifest.head();
+                    log::info!("Manifest {} ends with chain hash {head}", path.display());
+                }
+                written
+            })
+            .context("Failed to spawn snapshot writer thread")?;
+        log::info!(
+            "Writing a color and infrared PNG every {} ms to {}",
+            interval.as_millis(),
+            dir.display()
+        );
+        Ok(Self {
+            interval,
+            color: Mutex::default(),
+            infrared: Mutex::default(),
+            jobs: Mutex::new(Some(jobs_tx)),
+            writer: Mutex::new(Some(writer)),
+        })
+    }
+
+    /// Queues a copy of `data`, a raw color frame, if a color snapshot is due.
+    pub fn offer_color(&self, width: u32, height: u32, data: &[u8]) {
+        if let Some(jobs) = self.due_sender(&self.color, "color") {
+            Self::queue(&jobs, 0, width, height, Image::Color(data.to_vec()));
+        }
+    }
+
+    /// Queues a copy of `data`, a raw 16-bit infrared frame, if an infrared snapshot is due.
+    pub fn offer_infrared(&self, width: u32, height: u32, data: &[u16]) {
+        if let Some(jobs) = self.due_sender(&self.infrared, "infrared") {
+            Self::queue(&jobs, 1, width, height, Image::Gray16(data.to_vec()));
+        }
+    }
+
+    /// The writer's queue if a frame of `schedule` is due and the queue has room for
+    /// it, so frames that would be skipped aren't copied first.
+    fn due_sender(&self, schedule: &Mutex<Schedule>, stream: &str) -> Option<Sender<Job>> {
+        if !schedule.lock().take_due(Instant::now(), self.interval) {
+            return None;
+        }
+        let jobs = self.jobs.lock().clone()?;
+        if jobs.is_full() {
+            log::warn!("Snapshot writer is behind, skipped a {stream} frame");
+            return None;
+        }
+        Some(jobs)
+    }
+
+    fn queue(jobs: &Sender<Job>, stream: usize, width: u32, height: u32, image: Image) {
+        let job = Job {
+            stream,
+            unix_ms: unix_ms_now(),
+            width,
+            height,
+            image,
+        };
+        // Another stream may have taken the last slot since the check
+        if let Err(TrySendError::Full(_)) = jobs.try_send(job) {
+            log::warn!("Snapshot writer is behind, skipped a frame");
+        }
+    }
+
+    /// Writes the queued frames and stops the writer; later frames are ignored.
+    pub fn finish(&self) {
+        drop(self.jobs.lock().take());
+        if let Some(writer) = self.writer.lock().take() {
+            match writer.join() {
+                Ok(written) => log::info!("Snapshot sequence finished, {written} PNGs written"),
+                Err(_) => log::error!("Snapshot writer thread panicked"),
+            }
+        }
+    }
+}
+
+/// Writes the PNG to `path` under a temporary name first, so an interrupted write
+/// never leaves a truncated `frame_*.png` behind, and adds it to `manifest` once
+/// it's in place. Color frames of `color_format` are converted to RGB here.
+fn write_png(
+    path: &Path,
+    job: Job,
+    color_format: ColorCaptureFormat,
+    manifest: Option<&mut ManifestWriter>,
+) -> anyhow::Result<()> {
+    let partial = path.with_extension("png.part");
+    let mut png = Cursor::new(Vec::new());
+    let result = match job.image {
+        Image::Color(raw) => {
+            image::RgbImage::from_raw(job.width, job.height, color_format.to_rgb8(&raw))
+                .context("RGB frame has the wrong size")?
+                .write_to(&mut png, image::ImageFormat::Png)
+        }
+        Image::Gray16(gray) => {
+            image::ImageBuffer::<image::Luma<u16>, _>::from_raw(job.width, job.height, gray)
+                .context("Infrared frame has the wrong size")?
+                .write_to(&mut png, image::ImageFormat::Png)
+        }
+    };
+    result.with_context(|| format!("Failed to encode {}", path.display()))?;
+    let png = png.into_inner();
+    std::fs::write(&partial, &png)
+        .with_context(|| format!("Failed to write {}", partial.display()))?;
+    std::fs::rename(&partial, path)
+        .with_context(|| format!("Failed to rename to {}", path.display()))?;
+    if let Some(manifest) = manifest {
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default();
+        manifest.append(name, job.unix_ms, &png)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sequence_continues_numbering_and_honours_interval() {
+        let dir = std::env::temp_dir().join(format!("kinect-rtsp-seq-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join(frame_file_name(41)), b"").unwrap();
+        std::fs::write(dir.join("notes.txt"), b"").unwrap();
+
+        let sequence = Sequence::open(dir.clone()).unwrap();
+        assert_eq!(
+            sequence.next_path().file_name().unwrap(),
+            "frame_000042.png"
+        );
+
+        let mut schedule = Schedule::default();
+        let interval = Duration::from_millis(500);
+        let t0 = Instant::now();
+        assert!(schedule.take_due(t0, interval));
+        assert!(!schedule.take_due(t0 + Duration::from_millis(499), interval));
+        assert!(schedule.take_due(t0 + interval, interval));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,252 @@
+//! `--srt-adaptive-bitrate`: adapts the encoder of the `--srt-url` push to the uplink.
+//!
+//! Every [`CHECK_INTERVAL`] the `stats` of the `srtsink` are read. Packets the
+//! receiver reported lost or the sender dropped as too late mean the link can't
+//! carry the current bitrate, so it's lowered right away; after a quiet stretch it's
+//! raised again step by step. A keyframe is forced after drops, so the receiver
+//! doesn't wait a whole GOP for a clean picture.
+
+use std::time::Duration;
+
+use gstreamer as gst;
+use gstreamer::prelude::*;
+
+use crate::encoder::VideoEncoder;
+
+/// How often the `srtsink` statistics are read.
+const CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Share of lost packets, in percent, above which the link counts as congested.
+const LOSS_PERCENT: u64 = 5;
+
+/// Factor applied to the bitrate on congestion.
+const DECREASE_FACTOR: f64 = 0.7;
+
+/// Quiet checks needed before the bitrate is raised by one step.
+const RECOVER_AFTER: u32 = 5;
+
+/// Share of the maximum added per recovery step.
+const INCREASE_STEP: f64 = 0.1;
+
+/// Default of `--srt-min-bitrate`.
+pub const DEFAULT_MIN_BITRATE: u32 = 500_000;
+
+/// Packet counters of one check interval.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IntervalStats {
+    pub sent: u64,
+    /// Reported lost by the receiver, retransmitted if still in time.
+    pub lost: u64,
+    /// Dropped by the sender because they were too late to be played.
+    pub dropped: u64,
+}
+
+impl IntervalStats {
+    fn is_congested(&self) -> bool {
+        self.dropped > 0 || self.lost * 100 > self.sent * LOSS_PERCENT
+    }
+}
+
+/// Picks the bitrate between `min` and `max` from the congestion of each interval.
+#[derive(Debug)]
+pub struct BitrateController {
+    min: u32,
+    max: u32,
+    current: u32,
+    quiet: u32,
+}
+
+impl BitrateController {
+    /// Starts at `max`.
+    pub fn new(min: u32, max: u32) -> Self {
+        Self {
+            min,
+            max,
+            current: max,
+            quiet: 0,
+        }
+    }
+
+    pub fn current(&self) -> u32 {
+        self.current
+    }
+
+    /// Feeds the counters of the last interval and returns the new bitrate if it changed.
+    /// Intervals with nothing sent (no receiver connected) leave it as it is.
+    pub fn update(&mut self, stats: IntervalStats) -> Option<u32> {
+        if stats.sent == 0 {
+            return None;
+        }
+        let target = if stats.is_congested() {
+            self.quiet = 0;
+            ((self.current as f64 * DECREASE_FACTOR).round() as u32).max(self.min)
+        } else {
+            self.quiet += 1;
+            if self.quiet < RECOVER_AFTER {
+                return None;
+            }
+            self.quiet = 0;
+            (self.current + (self.max as f64 * INCREASE_STEP) as u32).min(self.max)
+        };
+        if target == self.current {
+            return None;
+        }
+        self.current = target;
+        Some(target)
+    }
+}
+
+/// Cumulative counters of the `srtsink`, summed over the callers in listener mode.
+fn read_counters(sink: &gst::Element) -> IntervalStats {
+    let stats = sink.property::<gst::Structure>("stats");
+    match stats.get::<glib::ValueArray>("callers") {
+        Ok(callers) => callers
+            .iter()
+            .filter_map(|caller| caller.get::<gst::Structure>().ok())
+            .map(|caller| socket_counters(&caller))
+            .fold(IntervalStats::default(), |sum, c| IntervalStats {
+                sent: sum.sent + c.sent,
+                lost: sum.lost + c.lost,
+                dropped: sum.dropped + c.dropped,
+            }),
+        Err(_) => socket_counters(&stats),
+    }
+}
+
+fn socket_counters(stats: &gst::StructureRef) -> IntervalStats {
+    IntervalStats {
+        sent: counter(stats, "packets-sent"),
+        lost: counter(stats, "packets-sent-lost"),
+        dropped: counter(stats, "packets-sent-dropped"),
+    }
+}
+
+/// A counter field, whichever integer type the srt plugin version uses; 0 if absent.
+fn counter(stats: &gst::StructureRef, field: &str) -> u64 {
+    let Ok(value) = stats.value(field) else {
+        return 0;
+    };
+    value
+        .get::<i64>()
+        .ok()
+        .or_else(|| value.get::<i32>().ok().map(i64::from))
+        .and_then(|v| u64::try_from(v).ok())
+        .or_else(|| value.get::<u64>().ok())
+        .or_else(|| value.get::<u32>().ok().map(u64::from))
+        .unwrap_or(0)
+}
+
+/// Asks `encoder` for a keyframe with SPS/PPS.
+fn force_keyframe(encoder: &gst::Element) {
+    let Some(pad) = encoder.static_pad("src") else {
+        return;
+    };
+    let event = gst::event::CustomUpstream::new(
+        gst::Structure::builder("GstForceKeyUnit")
+            .field("all-headers", true)
+            .build(),
+    );
+    pad.send_event(event);
+}
+
+/// Polls `sink` and drives the `bitrate` of `encoder` until the pipeline is gone.
+pub fn spawn(
+    encoder: gst::Element,
+    sink: gst::Element,
+    video_encoder: VideoEncoder,
+    min: u32,
+    max: u32,
+) {
+    let mut controller = BitrateController::new(min, max);
+    encoder.set_property(
+        "bitrate",
+        video_encoder.bitrate_property_value(controller.current()),
+    );
+    let encoder = encoder.downgrade();
+    let sink = sink.downgrade();
+    std::thread::Builder::new()
+        .name("srt-bitrate".into())
+        .spawn(move || {
+            log::info!("SRT adaptive bitrate: {min} to {max} bit/s");
+            let mut last = IntervalStats::default();
+            loop {
+                std::thread::sleep(CHECK_INTERVAL);
+                let (Some(encoder), Some(sink)) = (encoder.upgrade(), sink.upgrade()) else {
+                    break;
+                };
+                let counters = read_counters(&sink);
+                // The counters restart with every new connection
+                let interval = IntervalStats {
+                    sent: counters.sent.saturating_sub(last.sent),
+                    lost: counters.lost.saturating_sub(last.lost),
+                    dropped: counters.dropped.saturating_sub(last.dropped),
+                };
+                last = counters;
+                if interval.dropped > 0 {
+                    force_keyframe(&encoder);
+                }
+                let previous = controller.current();
+                if let Some(bitrate) = controller.update(interval) {
+                    encoder.set_property("bitrate", video_encoder.bitrate_property_value(bitrate));
+                    if bitrate < previous {
+                        log::warn!(
+                            "SRT link congested ({} of {} packets lost, {} dropped): \
+                            lowering bitrate to {bitrate} bit/s",
+                            interval.lost,
+                            interval.sent,
+                            interval.dropped
+                        );
+                    } else {
+                        log::info!("SRT link clear, raising bitrate to {bitrate} bit/s");
+                    }
+                }
+            }
+        })
+        .expect("Failed to spawn SRT bitrate thread");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(sent: u64, lost: u64, dropped: u64) -> IntervalStats {
+        IntervalStats {
+            sent,
+            lost,
+            dropped,
+        }
+    }
+
+    #[test]
+    fn test_lowers_on_congestion_down_to_min() {
+        let mut controller = BitrateController::new(1_000_000, 6_000_000);
+        assert_eq!(controller.update(stats(1000, 100, 0)), Some(4_200_000));
+        assert_eq!(controller.update(stats(1000, 0, 3)), Some(2_940_000));
+        assert_eq!(controller.update(stats(1000, 0, 3)), Some(2_058_000));
+        assert_eq!(controller.update(stats(1000, 0, 3)), Some(1_440_600));
+        assert_eq!(controller.update(stats(1000, 0, 3)), Some(1_008_420));
+        assert_eq!(controller.update(stats(1000, 0, 3)), Some(1_000_000));
+        assert_eq!(controller.update(stats(1000, 0, 3)), None);
+        // A few losses below the threshold aren't congestion
+        assert!(!stats(1000, 50, 0).is_congested());
+    }
+
+    #[test]
+    fn test_recovers_after_quiet_intervals() {
+        let mut controller = BitrateController::new(1_000_000, 6_000_000);
+        controller.update(stats(1000, 0, 1));
+        for _ in 1..RECOVER_AFTER {
+            assert_eq!(controller.update(stats(1000, 0, 0)), None);
+        }
+        assert_eq!(controller.update(stats(1000, 0, 0)), Some(4_800_000));
+        // Congestion restarts the quiet stretch
+        controller.update(stats(1000, 0, 1));
+        assert_eq!(controller.current(), 3_360_000);
+        for _ in 0..RECOVER_AFTER * 10 {
+            controller.update(stats(1000, 0, 0));
+        }
+        assert_eq!(controller.current(), 6_000_000);
+        // Nothing sent, no receiver: no change
+        assert_eq!(controller.update(stats(0, 0, 0)), None);
+    }
+}
@@ -0,0 +1,125 @@
+//! `status` subcommand: queries a running instance over its control API and prints
+//! a summary of the device, the uptime and every stream.
+
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::http;
+
+/// How long each request may take before the instance is considered unreachable.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+/// Time between the two `/streams` samples the frame rates are computed from.
+const FPS_SAMPLE: Duration = Duration::from_secs(1);
+
+#[derive(Deserialize)]
+struct Health {
+    device_available: bool,
+    uptime_secs: u64,
+    qos_events: u64,
+    tilt_degrees: Option<f32>,
+}
+
+#[derive(Deserialize)]
+struct Stream {
+    name: String,
+    path: String,
+    enabled: bool,
+    available: bool,
+    sessions: usize,
+    frames: u64,
+}
+
+fn get<T: serde::de::DeserializeOwned>(port: u16, path: &str) -> anyhow::Result<T> {
+    let body = http::get("127.0.0.1", port, path, REQUEST_TIMEOUT)?;
+    serde_json::from_str(&body).with_context(|| format!("Unexpected response to GET {path}"))
+}
+
+/// Formats `secs` as e.g. `2d 03h 04m 05s`, leaving out leading zero units.
+fn format_uptime(secs: u64) -> String {
+    let (days, hours, minutes, seconds) =
+        (secs / 86_400, secs / 3_600 % 24, secs / 60 % 60, secs % 60);
+    if days > 0 {
+        format!("{days}d {hours:02}h {minutes:02}m {seconds:02}s")
+    } else if hours > 0 {
+        format!("{hours}h {minutes:02}m {seconds:02}s")
+    } else if minutes > 0 {
+        format!("{minutes}m {seconds:02}s")
+    } else {
+        format!("{seconds}s")
+    }
+}
+
+fn yes_no(value: bool) -> &'static str {
+    if value { "yes" } else { "no" }
+}
+
+/// Prints the status of the instance whose control API listens on `port`.
+pub fn run(port: u16) -> anyhow::Result<()> {
+    let health: Health = get(port, "/health").with_context(|| {
+        format!(
+            "No kinect-rtsp instance reachable at http://127.0.0.1:{port} \
+            (is it running with --control-port {port}?)"
+        )
+    })?;
+    let before: Vec<Stream> = get(port, "/streams")?;
+    let sampled = Instant::now();
+    std::thread::sleep(FPS_SAMPLE);
+    let streams: Vec<Stream> = get(port, "/streams")?;
+    let elapsed = sampled.elapsed().as_secs_f64();
+
+    println!("Uptime:     {}", format_uptime(health.uptime_secs));
+    println!(
+        "Device:     {}",
+        if health.device_available {
+            "available"
+        } else {
+            "not available"
+        }
+    );
+    println!("QoS events: {}", health.qos_events);
+    match health.tilt_degrees {
+        Some(tilt) => println!("Tilt:       {tilt:.1}°"),
+        None => println!("Tilt:       unavailable"),
+    }
+    println!();
+    println!(
+        "{:<12} {:<20} {:<8} {:<10} {:>7} {:>6}",
+        "STREAM", "PATH", "ENABLED", "AVAILABLE", "CLIENTS", "FPS"
+    );
+    for stream in &streams {
+        let fps = before
+            .iter()
+            .find(|s| s.name == stream.name)
+            .map_or(0.0, |s| {
+                stream.frames.saturating_sub(s.frames) as f64 / elapsed
+            });
+        println!(
+            "{:<12} {:<20} {:<8} {:<10} {:>7} {:>6.1}",
+            stream.name,
+            stream.path,
+            yes_no(stream.enabled),
+            yes_no(stream.available),
+            stream.sessions,
+            fps
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_uptime() {
+        assert_eq!(format_uptime(0), "0s");
+        assert_eq!(format_uptime(65), "1m 05s");
+        assert_eq!(format_uptime(3_600 + 62), "1h 01m 02s");
+        assert_eq!(
+            format_uptime(2 * 86_400 + 3 * 3_600 + 4 * 60 + 5),
+            "2d 03h 04m 05s"
+        );
+    }
+}
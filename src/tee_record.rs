@@ -0,0 +1,102 @@
+//! `--tee-record <dir>`: writes every video stream to a file for as long as its media
+//! serves clients, so the recordings are exactly what the viewers got.
+//!
+//! When a media is configured a `tee` goes between the default branch's h264parse
+//! and the RTP payloader, its second branch muxing into the `--record-format`
+//! container. The file is written as `<name>.part`; the media sends an EOS on
+//! shutdown that finishes the container, and the file gets its final name once the
+//! media is unprepared.
+
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::Result;
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use gstreamer_rtsp_server as rtsp;
+use gstreamer_rtsp_server::prelude::*;
+
+use crate::encoder::tee_after_parse;
+use crate::motion_recording::{RecordFormat, clip_file_name};
+
+/// Where and how the served streams are recorded.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct TeeRecording {
+    /// Directory the recordings are written to.
+    pub dir: PathBuf,
+    /// Container of the recordings.
+    pub format: RecordFormat,
+}
+
+/// Elements the recording branch needs besides the muxer.
+pub const ELEMENTS: [&str; 3] = ["tee", "queue", "filesink"];
+
+/// File name prefix of a mount's recordings: its path without slashes.
+fn mount_prefix(mount: &str) -> String {
+    mount.trim_matches('/').replace('/', "-")
+}
+
+/// Records every media of `factory`, which serves `mount`.
+pub fn enable(factory: &rtsp::RTSPMediaFactory, recording: &TeeRecording, mount: &str) {
+    // The EOS lets the muxer write its index before the pipeline stops
+    factory.set_eos_shutdown(true);
+    let TeeRecording { dir, format } = recording.clone();
+    let mount = mount.to_string();
+    factory.connect_media_configure(move |_, media| {
+        let path = dir.join(clip_file_name(
+            &mount_prefix(&mount),
+            SystemTime::now(),
+            format,
+        ));
+        let part = part_path(&path);
+        if let Err(e) = add_branch(media, &part, format) {
+            log::warn!("Tee recording of {mount} not started: {e:#}");
+            return;
+        }
+        log::info!(
+            "🎞️ Recording {mount} to {} while it's served",
+            path.display()
+        );
+        let mount = mount.clone();
+        media.connect_unprepared(move |_| match std::fs::rename(&part, &path) {
+            Ok(()) => log::info!("🎞️ Recording of {mount} saved to {}", path.display()),
+            Err(e) => log::warn!(
+                "Failed to rename the recording of {mount} to {}: {e}",
+                path.display()
+            ),
+        });
+    });
+}
+
+/// `path` with `.part` appended to its extension.
+fn part_path(path: &Path) -> PathBuf {
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    part.into()
+}
+
+/// Puts a tee after the media's h264parse with a branch writing `location`.
+fn add_branch(media: &rtsp::RTSPMedia, location: &Path, format: RecordFormat) -> Result<()> {
+    let queue = gst::ElementFactory::make("queue").build()?;
+    let muxer = gst::ElementFactory::make(format.muxer()).build()?;
+    let sink = gst::ElementFactory::make("filesink")
+        .property("location", location.to_string_lossy().into_owned())
+        .property("async", false)
+        .build()?;
+    tee_after_parse(media, &[&queue, &muxer, &sink])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_names() {
+        assert_eq!(mount_prefix("/color"), "color");
+        assert_eq!(mount_prefix("/infrared/long"), "infrared-long");
+        assert_eq!(
+            part_path(Path::new("rec/color-20260301-142305Z.mkv")),
+            Path::new("rec/color-20260301-142305Z.mkv.part")
+        );
+    }
+}
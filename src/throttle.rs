@@ -0,0 +1,61 @@
+//! Rate limiting for the capture threads' logging, which would otherwise repeat
+//! the same line for every frame.
+
+use std::time::{Duration, Instant};
+
+/// Time between two capture progress logs.
+pub const PROGRESS_LOG_INTERVAL: Duration = Duration::from_secs(5);
+/// How long a started capture may go without frames before it warns.
+pub const STALL_WARN_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Lets a log line through at most once per `interval`, whatever the frame rate.
+pub struct Throttle {
+    interval: Duration,
+    last: Instant,
+}
+
+impl Throttle {
+    /// The first line goes through once `interval` has passed.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last: Instant::now(),
+        }
+    }
+
+    /// Whether to log now; if so, the next line waits another `interval`.
+    pub fn should_log(&mut self) -> bool {
+        self.should_log_at(Instant::now())
+    }
+
+    fn should_log_at(&mut self, now: Instant) -> bool {
+        if now.saturating_duration_since(self.last) < self.interval {
+            return false;
+        }
+        self.last = now;
+        true
+    }
+
+    /// Starts the interval over, e.g. when a frame arrives for a stall warning.
+    pub fn reset(&mut self) {
+        self.last = Instant::now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throttle_logs_once_per_interval() {
+        let mut throttle = Throttle::new(Duration::from_secs(5));
+        let start = throttle.last;
+        let at = |secs| start + Duration::from_secs(secs);
+        assert!(!throttle.should_log_at(at(4)));
+        assert!(throttle.should_log_at(at(5)));
+        assert!(!throttle.should_log_at(at(9)));
+        assert!(throttle.should_log_at(at(10)));
+        assert!(throttle.should_log_at(at(30)));
+        assert!(!throttle.should_log_at(at(34)));
+    }
+}
@@ -0,0 +1,177 @@
+//! Optional system-tray icon (`--tray`): green while the Kinect streams, red while it
+//! isn't available, a tooltip with the client counts and a menu to copy the RTSP
+//! URL, open the control API and quit.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use tao::event::{Event, StartCause};
+use tao::event_loop::{ControlFlow, EventLoopBuilder};
+use tao::platform::run_return::EventLoopExtRunReturn;
+use tao::platform::windows::EventLoopBuilderExtWindows;
+use tokio::sync::Notify;
+use tray_icon::menu::{Menu, MenuEvent, MenuItem, PredefinedMenuItem};
+use tray_icon::{Icon, TrayIcon, TrayIconBuilder};
+
+use crate::rtsp_publisher::{RtspPublisher, StreamStatus};
+
+/// How often the icon and tooltip are refreshed.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+const ICON_SIZE: u32 = 32;
+const GREEN: [u8; 3] = [0x2e, 0xa0, 0x43];
+const RED: [u8; 3] = [0xd0, 0x30, 0x30];
+
+/// A filled circle of `color` on a transparent background.
+fn status_icon([r, g, b]: [u8; 3]) -> anyhow::Result<Icon> {
+    let center = (ICON_SIZE as f32 - 1.0) / 2.0;
+    let radius = ICON_SIZE as f32 / 2.0 - 2.0;
+    let rgba = (0..ICON_SIZE * ICON_SIZE)
+        .flat_map(|i| {
+            let (x, y) = ((i % ICON_SIZE) as f32, (i / ICON_SIZE) as f32);
+            let inside = (x - center).hypot(y - center) <= radius;
+            [r, g, b, if inside { 255 } else { 0 }]
+        })
+        .collect();
+    Icon::from_rgba(rgba, ICON_SIZE, ICON_SIZE).context("Failed to build the tray icon")
+}
+
+/// Tooltip text: the state, then the client count in total and per watched stream.
+fn tooltip(running: bool, streams: &[StreamStatus]) -> String {
+    let clients: usize = streams.iter().map(|s| s.sessions).sum();
+    let mut text = format!(
+        "kinect-rtsp: {}, {clients} client(s)",
+        if running { "running" } else { "no Kinect" }
+    );
+    for stream in streams.iter().filter(|s| s.sessions > 0) {
+        text.push_str(&format!("\n{}: {}", stream.name, stream.sessions));
+    }
+    text
+}
+
+/// Opens `url` with the default handler (the browser for http URLs).
+fn open_url(url: &str) {
+    let opened = std::process::Command::new("cmd")
+        .args(["/C", "start", "", url])
+        .spawn();
+    if let Err(e) = opened {
+        log::warn!("Failed to open {url}: {e}");
+    }
+}
+
+fn copy_to_clipboard(text: &str) {
+    match arboard::Clipboard::new().and_then(|mut c| c.set_text(text)) {
+        Ok(()) => log::info!("Copied {text} to the clipboard"),
+        Err(e) => log::warn!("Failed to copy {text} to the clipboard: {e}"),
+    }
+}
+
+struct Tray {
+    icon: TrayIcon,
+    copy_url: MenuItem,
+    open_status: Option<MenuItem>,
+    quit: MenuItem,
+    running: bool,
+}
+
+impl Tray {
+    fn new(status_url: Option<&str>) -> anyhow::Result<Self> {
+        let copy_url = MenuItem::new("Copy RTSP URL", true, None);
+        let open_status = status_url.map(|_| MenuItem::new("Open status", true, None));
+        let quit = MenuItem::new("Quit", true, None);
+        let menu = Menu::new();
+        menu.append(&copy_url)?;
+        if let Some(open_status) = &open_status {
+            menu.append(open_status)?;
+        }
+        menu.append(&PredefinedMenuItem::separator())?;
+        menu.append(&quit)?;
+        let icon = TrayIconBuilder::new()
+            .with_menu(Box::new(menu))
+            .with_tooltip("kinect-rtsp")
+            .with_icon(status_icon(RED)?)
+            .build()
+            .context("Failed to create the tray icon")?;
+        Ok(Self {
+            icon,
+            copy_url,
+            open_status,
+            quit,
+            running: false,
+        })
+    }
+
+    fn refresh(&mut self, rtsp: &RtspPublisher) {
+        let running = rtsp.is_device_available();
+        if running != self.running {
+            self.running = running;
+            match status_icon(if running { GREEN } else { RED }) {
+                Ok(icon) => {
+                    let _ = self.icon.set_icon(Some(icon));
+                }
+                Err(e) => log::warn!("{e:#}"),
+            }
+        }
+        let _ = self
+            .icon
+            .set_tooltip(Some(tooltip(running, &rtsp.stream_statuses())));
+    }
+}
+
+/// Shows the tray icon on its own thread until the publisher stops. Quit from the
+/// menu notifies `quit`, which starts the same graceful shutdown as Ctrl-C.
+pub fn spawn(
+    rtsp: Arc<RtspPublisher>,
+    rtsp_url: String,
+    status_url: Option<String>,
+    quit: Arc<Notify>,
+) -> std::thread::JoinHandle<()> {
+    std::thread::Builder::new()
+        .name("tray".into())
+        .spawn(move || {
+            // The icon's window messages are handled by the thread that created it
+            let mut event_loop = EventLoopBuilder::new().with_any_thread(true).build();
+            let mut tray: Option<Tray> = None;
+            event_loop.run_return(|event, _, control_flow| {
+                *control_flow = ControlFlow::WaitUntil(Instant::now() + REFRESH_INTERVAL);
+                if rtsp.is_stopping() {
+                    tray = None;
+                    *control_flow = ControlFlow::Exit;
+                    return;
+                }
+                let Event::NewEvents(cause) = event else {
+                    return;
+                };
+                if cause == StartCause::Init {
+                    match Tray::new(status_url.as_deref()) {
+                        Ok(new) => tray = Some(new),
+                        Err(e) => {
+                            log::error!("Tray icon unavailable: {e:#}");
+                            *control_flow = ControlFlow::Exit;
+                            return;
+                        }
+                    }
+                }
+                let Some(tray) = tray.as_mut() else {
+                    return;
+                };
+                while let Ok(event) = MenuEvent::receiver().try_recv() {
+                    if event.id == *tray.copy_url.id() {
+                        copy_to_clipboard(&rtsp_url);
+                    } else if event.id == *tray.quit.id() {
+                        log::info!("Quit selected in the tray menu");
+                        quit.notify_one();
+                    } else if let Some(url) = &status_url
+                        && tray
+                            .open_status
+                            .as_ref()
+                            .is_some_and(|i| event.id == *i.id())
+                    {
+                        open_url(url);
+                    }
+                }
+                tray.refresh(&rtsp);
+            });
+        })
+        .expect("Failed to spawn tray thread")
+}
@@ -0,0 +1,58 @@
+//! `--warmup-frames`: the first frames of a capture that just (re)started are held
+//! back while the sensor's exposure settles.
+
+/// Default of `--warmup-frames`, about 170 ms at 30 fps.
+pub const DEFAULT_WARMUP_FRAMES: u32 = 5;
+
+/// Frames each capture discards after it (re)starts, see [`Warmup`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct WarmupFrames {
+    pub color: u32,
+    pub infrared: u32,
+    pub depth: u32,
+}
+
+/// Discards the first frames after a capture (re)starts, while the sensor's
+/// exposure settles, so clients never see its initial dark or blown-out frames.
+pub struct Warmup {
+    frames: u32,
+    remaining: u32,
+}
+
+impl Warmup {
+    pub fn new(frames: u32) -> Self {
+        Self {
+            frames,
+            remaining: frames,
+        }
+    }
+
+    /// Starts over, to be called whenever the capture (re)starts.
+    pub fn restart(&mut self) {
+        self.remaining = self.frames;
+    }
+
+    /// Whether the frame just captured is discarded.
+    pub fn discard(&mut self) -> bool {
+        if self.remaining == 0 {
+            return false;
+        }
+        self.remaining -= 1;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_warmup_discards_the_first_frames_of_every_start() {
+        let mut warmup = Warmup::new(2);
+        let kept = |warmup: &mut Warmup| (0..4).filter(|_| !warmup.discard()).count();
+        assert_eq!(kept(&mut warmup), 2);
+        warmup.restart();
+        assert_eq!(kept(&mut warmup), 2);
+        assert!(!Warmup::new(0).discard());
+    }
+}